@@ -0,0 +1,7 @@
+-- Drop the initial tables
+DROP TABLE IF EXISTS logs;
+DROP TABLE IF EXISTS user_roles;
+DROP TABLE IF EXISTS role_permissions;
+DROP TABLE IF EXISTS permissions;
+DROP TABLE IF EXISTS roles;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,4 @@
+-- Drop user profiles
+DROP TRIGGER IF EXISTS update_user_profiles_updated_at ON user_profiles;
+DROP FUNCTION IF EXISTS update_updated_at_column;
+DROP TABLE IF EXISTS user_profiles;
@@ -0,0 +1,2 @@
+-- Forget which email addresses were verified
+ALTER TABLE users DROP COLUMN IF EXISTS email_verified_at;
@@ -0,0 +1,8 @@
+-- Remove the seeded admin permissions, their grants go with them. The Admin
+-- role is kept, it may have existed before the seed
+DELETE FROM permissions WHERE name IN (
+    'roles.read', 'roles.create', 'roles.delete', 'roles.grant',
+    'permissions.read', 'permissions.create', 'permissions.delete',
+    'users.read', 'users.delete', 'users.roles.assign', 'users.roles.revoke',
+    'users.2fa.reset'
+);
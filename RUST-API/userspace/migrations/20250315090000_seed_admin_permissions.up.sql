@@ -0,0 +1,32 @@
+-- Seed the permissions checked by the admin routes and grant them all to Admin,
+-- so existing admins keep their access
+INSERT INTO permissions (name) VALUES
+    ('roles.read'),
+    ('roles.create'),
+    ('roles.delete'),
+    ('roles.grant'),
+    ('permissions.read'),
+    ('permissions.create'),
+    ('permissions.delete'),
+    ('users.read'),
+    ('users.delete'),
+    ('users.roles.assign'),
+    ('users.roles.revoke'),
+    ('users.2fa.reset')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO roles (name) VALUES ('Admin')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+CROSS JOIN permissions p
+WHERE r.name = 'Admin'
+AND p.name IN (
+    'roles.read', 'roles.create', 'roles.delete', 'roles.grant',
+    'permissions.read', 'permissions.create', 'permissions.delete',
+    'users.read', 'users.delete', 'users.roles.assign', 'users.roles.revoke',
+    'users.2fa.reset'
+)
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,6 @@
+-- Roles inherit every permission of their parent role
+ALTER TABLE roles ADD COLUMN parent_id UUID REFERENCES roles(id) ON DELETE SET NULL;
+ALTER TABLE roles ADD CONSTRAINT roles_parent_not_self CHECK (parent_id <> id);
+
+-- Add indexes for common queries
+CREATE INDEX idx_roles_parent_id ON roles(parent_id);
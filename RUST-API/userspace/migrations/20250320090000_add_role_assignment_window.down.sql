@@ -0,0 +1,6 @@
+-- Role assignments lose their start and expiry. Run the role sweeper first if
+-- expired assignments shouldn't become permanent
+DROP INDEX IF EXISTS idx_user_roles_expires_at;
+ALTER TABLE user_roles DROP CONSTRAINT IF EXISTS user_roles_window_valid;
+ALTER TABLE user_roles DROP COLUMN IF EXISTS expires_at;
+ALTER TABLE user_roles DROP COLUMN IF EXISTS starts_at;
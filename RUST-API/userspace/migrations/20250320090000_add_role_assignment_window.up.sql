@@ -0,0 +1,7 @@
+-- Role assignments can be scheduled to start later and to expire
+ALTER TABLE user_roles ADD COLUMN starts_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE user_roles ADD COLUMN expires_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE user_roles ADD CONSTRAINT user_roles_window_valid CHECK (expires_at > starts_at);
+
+-- Add indexes for common queries
+CREATE INDEX idx_user_roles_expires_at ON user_roles(expires_at) WHERE expires_at IS NOT NULL;
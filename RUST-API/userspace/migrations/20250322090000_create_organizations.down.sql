@@ -0,0 +1,12 @@
+-- Drop organizations with their members, roles held inside them and invitations
+DELETE FROM permissions WHERE name IN (
+    'org.read', 'org.update', 'org.delete',
+    'org.members.read', 'org.members.invite', 'org.members.remove',
+    'org.members.roles.assign', 'org.members.roles.revoke'
+);
+DELETE FROM roles WHERE name IN ('Org Admin', 'Org Member');
+
+DROP TABLE IF EXISTS invitations;
+DROP TABLE IF EXISTS organization_member_roles;
+DROP TABLE IF EXISTS organization_members;
+DROP TABLE IF EXISTS organizations;
@@ -0,0 +1,77 @@
+-- Organizations (teams, workspaces) with their own members and role assignments
+CREATE TABLE organizations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(100) NOT NULL,
+    slug VARCHAR(50) UNIQUE NOT NULL,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE organization_members (
+    organization_id UUID REFERENCES organizations(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (organization_id, user_id)
+);
+
+-- Roles held inside one organization only; leaving the organization drops them
+CREATE TABLE organization_member_roles (
+    organization_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    role_id UUID REFERENCES roles(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (organization_id, user_id, role_id),
+    FOREIGN KEY (organization_id, user_id)
+        REFERENCES organization_members(organization_id, user_id) ON DELETE CASCADE
+);
+
+-- Only the hash of an invitation token is stored, the raw token is mailed once
+CREATE TABLE invitations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    email VARCHAR(255) NOT NULL,
+    role_id UUID REFERENCES roles(id) ON DELETE SET NULL,
+    token_hash VARCHAR(64) UNIQUE NOT NULL,
+    invited_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    accepted_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Add indexes for common queries
+CREATE INDEX idx_organization_members_user_id ON organization_members(user_id);
+CREATE INDEX idx_organization_member_roles_user_id ON organization_member_roles(user_id);
+CREATE INDEX idx_invitations_organization_id ON invitations(organization_id);
+
+-- Permissions checked inside an organization. Org Admin holds them all and is
+-- given to whoever creates an organization, Org Member can see the member list
+INSERT INTO permissions (name) VALUES
+    ('org.read'),
+    ('org.update'),
+    ('org.delete'),
+    ('org.members.read'),
+    ('org.members.invite'),
+    ('org.members.remove'),
+    ('org.members.roles.assign'),
+    ('org.members.roles.revoke')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO roles (name) VALUES ('Org Admin'), ('Org Member')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+CROSS JOIN permissions p
+WHERE r.name IN ('Admin', 'Org Admin')
+AND p.name LIKE 'org.%'
+ON CONFLICT DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+CROSS JOIN permissions p
+WHERE r.name = 'Org Member'
+AND p.name IN ('org.read', 'org.members.read')
+ON CONFLICT DO NOTHING;
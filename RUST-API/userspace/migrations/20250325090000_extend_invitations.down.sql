@@ -0,0 +1,28 @@
+-- Back to single-use organization invitations bound to an email and one role.
+-- Invitations that don't fit that shape (global, shared codes, no email or
+-- expiry) are deleted, multi-role invitations keep one of their roles
+DELETE FROM permissions WHERE name IN ('invitations.read', 'invitations.create', 'invitations.revoke');
+
+DELETE FROM invitations
+WHERE organization_id IS NULL OR email IS NULL OR expires_at IS NULL OR max_uses > 1;
+
+ALTER TABLE invitations ADD COLUMN role_id UUID REFERENCES roles(id) ON DELETE SET NULL;
+UPDATE invitations i SET role_id = (
+    SELECT ir.role_id FROM invitation_roles ir WHERE ir.invitation_id = i.id LIMIT 1
+);
+
+ALTER TABLE invitations ADD COLUMN accepted_at TIMESTAMP WITH TIME ZONE;
+UPDATE invitations i SET accepted_at = (
+    SELECT MAX(r.redeemed_at) FROM invitation_redemptions r WHERE r.invitation_id = i.id
+)
+WHERE use_count > 0;
+
+DROP TABLE IF EXISTS invitation_redemptions;
+DROP TABLE IF EXISTS invitation_roles;
+
+ALTER TABLE invitations DROP CONSTRAINT IF EXISTS invitations_uses_valid;
+ALTER TABLE invitations DROP COLUMN IF EXISTS use_count;
+ALTER TABLE invitations DROP COLUMN IF EXISTS max_uses;
+ALTER TABLE invitations ALTER COLUMN expires_at SET NOT NULL;
+ALTER TABLE invitations ALTER COLUMN email SET NOT NULL;
+ALTER TABLE invitations ALTER COLUMN organization_id SET NOT NULL;
@@ -0,0 +1,46 @@
+-- Invitations can also be global (used to register), shared as a code with
+-- several uses, left without expiry or email binding, and grant a set of roles
+ALTER TABLE invitations ALTER COLUMN organization_id DROP NOT NULL;
+ALTER TABLE invitations ALTER COLUMN email DROP NOT NULL;
+ALTER TABLE invitations ALTER COLUMN expires_at DROP NOT NULL;
+ALTER TABLE invitations ADD COLUMN max_uses INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE invitations ADD COLUMN use_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE invitations ADD CONSTRAINT invitations_uses_valid CHECK (max_uses > 0 AND use_count <= max_uses);
+
+UPDATE invitations SET use_count = 1 WHERE accepted_at IS NOT NULL;
+ALTER TABLE invitations DROP COLUMN accepted_at;
+
+-- Roles granted on redemption; organization roles for organization invitations
+CREATE TABLE invitation_roles (
+    invitation_id UUID REFERENCES invitations(id) ON DELETE CASCADE,
+    role_id UUID REFERENCES roles(id) ON DELETE CASCADE,
+    PRIMARY KEY (invitation_id, role_id)
+);
+
+INSERT INTO invitation_roles (invitation_id, role_id)
+SELECT id, role_id FROM invitations WHERE role_id IS NOT NULL;
+ALTER TABLE invitations DROP COLUMN role_id;
+
+CREATE TABLE invitation_redemptions (
+    invitation_id UUID REFERENCES invitations(id) ON DELETE CASCADE,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    redeemed_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (invitation_id, user_id)
+);
+
+-- Add indexes for common queries
+CREATE INDEX idx_invitation_redemptions_user_id ON invitation_redemptions(user_id);
+
+INSERT INTO permissions (name) VALUES
+    ('invitations.read'),
+    ('invitations.create'),
+    ('invitations.revoke')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+CROSS JOIN permissions p
+WHERE r.name = 'Admin'
+AND p.name IN ('invitations.read', 'invitations.create', 'invitations.revoke')
+ON CONFLICT DO NOTHING;
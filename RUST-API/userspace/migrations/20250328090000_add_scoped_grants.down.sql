@@ -0,0 +1,9 @@
+-- Grants lose their scope. Scoped grants are deleted rather than widened to
+-- every resource
+DROP INDEX IF EXISTS idx_role_permissions_permission_id;
+DELETE FROM role_permissions WHERE resource_type <> '*' OR resource_id <> '*';
+
+ALTER TABLE role_permissions DROP CONSTRAINT role_permissions_pkey;
+ALTER TABLE role_permissions DROP COLUMN resource_id;
+ALTER TABLE role_permissions DROP COLUMN resource_type;
+ALTER TABLE role_permissions ADD PRIMARY KEY (role_id, permission_id);
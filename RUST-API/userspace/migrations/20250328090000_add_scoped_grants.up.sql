@@ -0,0 +1,12 @@
+-- Scope permission grants to a resource type and id, '*' meaning any.
+-- Existing grants become unscoped
+ALTER TABLE role_permissions ADD COLUMN resource_type VARCHAR(50) NOT NULL DEFAULT '*';
+ALTER TABLE role_permissions ADD COLUMN resource_id VARCHAR(255) NOT NULL DEFAULT '*';
+
+ALTER TABLE role_permissions DROP CONSTRAINT role_permissions_pkey;
+ALTER TABLE role_permissions ALTER COLUMN role_id SET NOT NULL;
+ALTER TABLE role_permissions ALTER COLUMN permission_id SET NOT NULL;
+ALTER TABLE role_permissions ADD PRIMARY KEY (role_id, permission_id, resource_type, resource_id);
+
+-- Add indexes for common queries
+CREATE INDEX idx_role_permissions_permission_id ON role_permissions(permission_id);
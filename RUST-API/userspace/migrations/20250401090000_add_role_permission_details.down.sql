@@ -0,0 +1,8 @@
+-- Drop descriptions and the permissions for editing roles and permissions
+DELETE FROM permissions WHERE name IN ('roles.update', 'roles.revoke', 'permissions.update');
+
+ALTER TABLE permissions DROP COLUMN IF EXISTS updated_at;
+ALTER TABLE permissions DROP COLUMN IF EXISTS description;
+
+ALTER TABLE roles DROP COLUMN IF EXISTS updated_at;
+ALTER TABLE roles DROP COLUMN IF EXISTS description;
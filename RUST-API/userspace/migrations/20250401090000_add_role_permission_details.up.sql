@@ -0,0 +1,21 @@
+-- Roles and permissions can be described and renamed
+ALTER TABLE roles ADD COLUMN description TEXT;
+ALTER TABLE roles ADD COLUMN updated_at TIMESTAMP WITH TIME ZONE;
+
+ALTER TABLE permissions ADD COLUMN description TEXT;
+ALTER TABLE permissions ADD COLUMN updated_at TIMESTAMP WITH TIME ZONE;
+
+-- Seed the permissions for the new admin routes and grant them to Admin
+INSERT INTO permissions (name) VALUES
+    ('roles.update'),
+    ('roles.revoke'),
+    ('permissions.update')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+CROSS JOIN permissions p
+WHERE r.name = 'Admin'
+AND p.name IN ('roles.update', 'roles.revoke', 'permissions.update')
+ON CONFLICT DO NOTHING;
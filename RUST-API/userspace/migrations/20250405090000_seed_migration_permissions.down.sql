@@ -0,0 +1,2 @@
+-- Remove the migration status permission, its grants go with it
+DELETE FROM permissions WHERE name = 'migrations.read';
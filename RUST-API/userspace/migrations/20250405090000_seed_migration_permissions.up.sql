@@ -0,0 +1,12 @@
+-- Seed the permission for reading the migration status and grant it to Admin
+INSERT INTO permissions (name, description) VALUES
+    ('migrations.read', 'See which database migrations are applied')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+CROSS JOIN permissions p
+WHERE r.name = 'Admin'
+AND p.name = 'migrations.read'
+ON CONFLICT DO NOTHING;
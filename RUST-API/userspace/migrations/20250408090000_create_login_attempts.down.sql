@@ -0,0 +1,4 @@
+-- Remove the unlock permission, its grants go with it
+DELETE FROM permissions WHERE name = 'users.unlock';
+
+DROP TABLE IF EXISTS login_attempts;
@@ -0,0 +1,24 @@
+-- Failed login counters, keyed by account (email) or client IP, shared by
+-- every instance
+CREATE TABLE login_attempts (
+    key VARCHAR(320) PRIMARY KEY,
+    failures INTEGER NOT NULL DEFAULT 0,
+    window_started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    last_failure_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    locked_until TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX idx_login_attempts_window_started_at ON login_attempts(window_started_at);
+
+-- Seed the permission for lifting lockouts and grant it to Admin
+INSERT INTO permissions (name, description) VALUES
+    ('users.unlock', 'Lift the lockout of an account after too many failed logins')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT r.id, p.id
+FROM roles r
+CROSS JOIN permissions p
+WHERE r.name = 'Admin'
+AND p.name = 'users.unlock'
+ON CONFLICT DO NOTHING;
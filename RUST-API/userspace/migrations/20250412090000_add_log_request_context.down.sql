@@ -0,0 +1,7 @@
+-- The indexes go with their columns
+ALTER TABLE logs
+    DROP COLUMN IF EXISTS ip_address,
+    DROP COLUMN IF EXISTS user_agent,
+    DROP COLUMN IF EXISTS client_type,
+    DROP COLUMN IF EXISTS app_version,
+    DROP COLUMN IF EXISTS request_id;
@@ -0,0 +1,12 @@
+-- Where each audited request came from
+ALTER TABLE logs
+    ADD COLUMN ip_address INET,
+    ADD COLUMN user_agent TEXT,
+    ADD COLUMN client_type VARCHAR(16),
+    ADD COLUMN app_version VARCHAR(64),
+    ADD COLUMN request_id VARCHAR(128);
+
+CREATE INDEX idx_logs_ip_address ON logs(ip_address);
+CREATE INDEX idx_logs_client_type ON logs(client_type);
+CREATE INDEX idx_logs_app_version ON logs(app_version);
+CREATE INDEX idx_logs_request_id ON logs(request_id);
@@ -0,0 +1,2 @@
+-- Drop second factor challenges
+DROP TABLE IF EXISTS two_factor_challenges;
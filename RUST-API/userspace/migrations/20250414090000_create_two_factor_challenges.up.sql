@@ -0,0 +1,13 @@
+-- Outstanding second factor challenges, so each challenge token works once
+-- and wrong codes against it are counted
+CREATE TABLE two_factor_challenges (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    consumed_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- Add indexes for common queries
+CREATE INDEX idx_two_factor_challenges_expires_at ON two_factor_challenges(expires_at);
@@ -0,0 +1,2 @@
+-- Drop the organization assignable flag from roles
+ALTER TABLE roles DROP COLUMN IF EXISTS organization_assignable;
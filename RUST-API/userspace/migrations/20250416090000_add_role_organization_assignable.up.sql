@@ -0,0 +1,5 @@
+-- Roles organization admins may hand out to members. Global roles such as
+-- Admin stay off the list
+ALTER TABLE roles ADD COLUMN organization_assignable BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE roles SET organization_assignable = TRUE WHERE name IN ('Org Admin', 'Org Member');
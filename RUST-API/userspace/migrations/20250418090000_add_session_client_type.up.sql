@@ -0,0 +1,4 @@
+-- The kind of client a session was opened from. Its access tokens carry it,
+-- so rate limits follow the login instead of what each request claims
+ALTER TABLE sessions
+    ADD COLUMN client_type VARCHAR(16);
@@ -0,0 +1,519 @@
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use rocket::serde::json::{json, Value};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+use userspace::models::user::RegisterRequest;
+use userspace::utils::hashing::hash_password;
+use userspace::utils::logger::{log_action, LogAction, LogBuilder};
+use userspace::utils::migrations::{self, MigrationState};
+use userspace::utils::policy;
+use userspace::utils::session::revoke_user_sessions;
+
+/// Bootstrap and maintenance tasks for the userspace API. Reads the same
+/// environment (`DATABASE_URL`, ...) as the server
+#[derive(Parser)]
+#[command(name = "userspace-admin", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a user with a verified email, optionally with global roles
+    CreateUser {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        username: String,
+        /// Read from stdin when left out, which keeps it out of shell history
+        #[arg(long)]
+        password: Option<String>,
+        /// Role to give the user, can be repeated
+        #[arg(long = "role")]
+        roles: Vec<String>,
+    },
+    /// Give a user a global role
+    GrantRole {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        role: String,
+        /// When the role is taken away again, e.g. 2025-12-31T00:00:00Z
+        #[arg(long)]
+        expires_at: Option<DateTime<Utc>>,
+    },
+    /// Set a new password and sign the user out everywhere
+    ResetPassword {
+        #[arg(long)]
+        email: String,
+        /// Read from stdin when left out
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Sign a user out of every session
+    RevokeSessions {
+        #[arg(long)]
+        email: String,
+    },
+    /// Apply pending database migrations, or manage them with a subcommand
+    Migrate {
+        #[command(subcommand)]
+        command: Option<MigrateCommand>,
+    },
+    /// Export or import roles, permissions and grants as a policy file
+    Rbac {
+        #[command(subcommand)]
+        command: RbacCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum MigrateCommand {
+    /// Apply pending migrations, the same as `migrate` on its own
+    Run,
+    /// List every migration and whether it's applied
+    Status,
+    /// Revert the latest migration, or every one newer than a version
+    Revert {
+        /// Version to go back to, 0 reverts everything
+        #[arg(long)]
+        target: Option<i64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RbacCommand {
+    /// Write the roles and permissions in the database as a policy file
+    Export {
+        /// File to write, stdout when left out
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Reconcile the database with a policy file
+    Import {
+        file: PathBuf,
+        /// Delete roles, permissions and grants the file doesn't list
+        #[arg(long)]
+        prune: bool,
+        /// Only print what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Write an audit entry for something done from the command line. There is
+/// no user behind it, so the actor is recorded as `system`
+async fn record(pool: &PgPool, builder: LogBuilder, details: Value) -> Result<()> {
+    let mut details = details;
+    if let Some(object) = details.as_object_mut() {
+        object.insert("performed_by".to_string(), json!("system"));
+        object.insert("via".to_string(), json!("cli"));
+    }
+
+    let log = builder.with_additional_details(&details)?.build();
+    log_action(pool, &log).await?;
+
+    Ok(())
+}
+
+fn read_password(password: Option<String>) -> Result<String> {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            eprint!("Password: ");
+            io::stderr().flush()?;
+
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    if password.len() < 8 {
+        bail!("Passwords need at least 8 characters");
+    }
+
+    Ok(password)
+}
+
+async fn find_user(pool: &PgPool, email: &str) -> Result<Uuid> {
+    sqlx::query_scalar!("SELECT id FROM users WHERE LOWER(email) = LOWER($1)", email)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow!("No user with email {}", email))
+}
+
+async fn find_role(pool: &PgPool, name: &str) -> Result<Uuid> {
+    sqlx::query_scalar!("SELECT id FROM roles WHERE name = $1", name)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow!("No role named {}", name))
+}
+
+async fn create_user(
+    pool: &PgPool,
+    email: String,
+    username: String,
+    password: Option<String>,
+    roles: Vec<String>,
+) -> Result<()> {
+    let request = RegisterRequest {
+        email,
+        username,
+        password: read_password(password)?,
+        invite_code: None,
+        token_in_body: false,
+    };
+
+    if let Err(errors) = request.validate() {
+        bail!("Invalid user: {}", errors);
+    }
+
+    let mut role_ids = Vec::new();
+    for role in &roles {
+        role_ids.push(find_role(pool, role).await?);
+    }
+
+    let password_hash = hash_password(&request.password)
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))?;
+
+    let mut tx = pool.begin().await?;
+
+    // Created from the command line, so there's no mailbox to confirm
+    let user = sqlx::query!(
+        r#"
+        INSERT INTO users (email, username, password_hash, email_verified_at)
+        VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
+        RETURNING id
+        "#,
+        request.email,
+        request.username,
+        password_hash
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(err) if err.is_unique_violation() => {
+            anyhow!("A user with that email or username already exists")
+        }
+        e => e.into(),
+    })?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_profiles (user_id, created_at, updated_at)
+        VALUES ($1, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+        "#,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for role_id in &role_ids {
+        sqlx::query!(
+            "INSERT INTO user_roles (user_id, role_id) VALUES ($1, $2)",
+            user.id,
+            role_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    let builder = LogBuilder::system(LogAction::Create, "user")
+        .with_resource_id(user.id.to_string())
+        .with_new_state(&json!({
+            "email": request.email,
+            "username": request.username,
+            "roles": roles
+        }))?;
+    record(pool, builder, json!({})).await?;
+
+    println!("Created user {} ({})", request.username, user.id);
+
+    Ok(())
+}
+
+async fn grant_role(pool: &PgPool, email: &str, role: &str, expires_at: Option<DateTime<Utc>>) -> Result<()> {
+    if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        bail!("expires_at must be in the future");
+    }
+
+    let user_id = find_user(pool, email).await?;
+    let role_id = find_role(pool, role).await?;
+
+    // A role the user already holds gets the new expiry, which also brings
+    // back an expired assignment the sweeper hasn't removed yet
+    let assignment = sqlx::query!(
+        r#"
+        WITH previous AS (
+            SELECT starts_at, expires_at
+            FROM user_roles
+            WHERE user_id = $1 AND role_id = $2
+        )
+        INSERT INTO user_roles (user_id, role_id, expires_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, role_id) DO UPDATE
+        SET starts_at = NULL, expires_at = EXCLUDED.expires_at
+        RETURNING
+            EXISTS (SELECT 1 FROM previous) AS "existed!",
+            (SELECT starts_at FROM previous) AS previous_starts_at,
+            (SELECT expires_at FROM previous) AS previous_expires_at
+        "#,
+        user_id,
+        role_id,
+        expires_at
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let mut builder = LogBuilder::system(LogAction::Create, "user_role")
+        .with_resource_id(format!("{}:{}", user_id, role_id))
+        .with_new_state(&json!({
+            "user_id": user_id,
+            "role_id": role_id,
+            "role_name": role,
+            "expires_at": expires_at
+        }))?;
+
+    if assignment.existed {
+        builder = builder.with_previous_state(&json!({
+            "starts_at": assignment.previous_starts_at,
+            "expires_at": assignment.previous_expires_at
+        }))?;
+    }
+
+    record(pool, builder, json!({})).await?;
+
+    if assignment.existed {
+        println!("Updated the {} role of {}", role, email);
+        return Ok(());
+    }
+
+    println!("Gave {} the {} role", email, role);
+
+    Ok(())
+}
+
+async fn reset_password(pool: &PgPool, email: &str, password: Option<String>) -> Result<()> {
+    let user_id = find_user(pool, email).await?;
+    let password = read_password(password)?;
+    let password_hash = hash_password(&password)
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+        password_hash,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = CURRENT_TIMESTAMP
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let sessions_revoked = revoke_user_sessions(&mut *tx, user_id, None).await?;
+
+    tx.commit().await?;
+
+    let builder = LogBuilder::system(LogAction::Custom("password_reset".to_string()), "auth")
+        .with_resource_id(user_id.to_string());
+    record(pool, builder, json!({ "sessions_revoked": sessions_revoked })).await?;
+
+    println!("Password reset for {}, {} session(s) revoked", email, sessions_revoked);
+
+    Ok(())
+}
+
+async fn revoke_sessions(pool: &PgPool, email: &str) -> Result<()> {
+    let user_id = find_user(pool, email).await?;
+    let sessions_revoked = revoke_user_sessions(pool, user_id, None).await?;
+
+    let builder = LogBuilder::system(LogAction::Custom("sessions_revoked".to_string()), "auth")
+        .with_resource_id(user_id.to_string());
+    record(pool, builder, json!({ "sessions_revoked": sessions_revoked })).await?;
+
+    println!("Revoked {} session(s) of {}", sessions_revoked, email);
+
+    Ok(())
+}
+
+async fn migrate(pool: &PgPool) -> Result<()> {
+    let applied = migrations::run(pool).await?;
+
+    if applied.is_empty() {
+        println!("Database is up to date");
+        return Ok(());
+    }
+
+    for migration in &applied {
+        println!("Applied {} {}", migration.version, migration.description);
+    }
+
+    let versions: Vec<String> = applied
+        .iter()
+        .map(|migration| format!("{} {}", migration.version, migration.description))
+        .collect();
+
+    let builder = LogBuilder::system(LogAction::Custom("migrated".to_string()), "database");
+    record(pool, builder, json!({ "applied": versions })).await?;
+
+    Ok(())
+}
+
+async fn migration_status(pool: &PgPool) -> Result<()> {
+    let statuses = migrations::status(pool).await?;
+
+    for status in &statuses {
+        println!("{}", status);
+    }
+
+    let pending = statuses
+        .iter()
+        .filter(|status| status.state == MigrationState::Pending)
+        .count();
+    if pending > 0 {
+        println!("{} pending migration(s)", pending);
+    }
+
+    // Anything else than applied or pending needs someone to look at it
+    let mismatched: Vec<String> = statuses
+        .iter()
+        .filter(|status| !matches!(status.state, MigrationState::Applied | MigrationState::Pending))
+        .map(|status| status.to_string())
+        .collect();
+
+    let builder = LogBuilder::system(LogAction::Custom("checked_migrations".to_string()), "database");
+    record(pool, builder, json!({
+        "migrations": statuses.len(),
+        "pending": pending,
+        "mismatched": mismatched
+    }))
+    .await?;
+
+    if !mismatched.is_empty() {
+        bail!("The database doesn't match the migrations of this build");
+    }
+
+    Ok(())
+}
+
+async fn revert_migrations(pool: &PgPool, target: Option<i64>) -> Result<()> {
+    let reverted = migrations::revert(pool, target).await?;
+
+    if reverted.is_empty() {
+        println!("Nothing to revert");
+        return Ok(());
+    }
+
+    for migration in &reverted {
+        println!("Reverted {} {}", migration.version, migration.description);
+    }
+
+    let versions: Vec<String> = reverted
+        .iter()
+        .map(|migration| format!("{} {}", migration.version, migration.description))
+        .collect();
+
+    // The logs table goes away with the initial schema
+    let builder = LogBuilder::system(LogAction::Custom("reverted".to_string()), "database");
+    if let Err(e) = record(pool, builder, json!({ "reverted": versions })).await {
+        eprintln!("Couldn't record the revert: {}", e);
+    }
+
+    Ok(())
+}
+
+async fn export_rbac(pool: &PgPool, output: Option<PathBuf>) -> Result<()> {
+    let policy = policy::export(pool).await?;
+    let contents = toml::to_string_pretty(&policy).context("Failed to write the policy")?;
+
+    match &output {
+        Some(path) => {
+            std::fs::write(path, contents)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            eprintln!("Wrote {}", path.display());
+        }
+        None => print!("{}", contents),
+    }
+
+    let builder = LogBuilder::system(LogAction::Custom("exported".to_string()), "rbac_policy");
+    record(pool, builder, json!({
+        "roles": policy.roles.len(),
+        "permissions": policy.permissions.len(),
+        "output": output.map(|path| path.display().to_string())
+    }))
+    .await?;
+
+    Ok(())
+}
+
+async fn import_rbac(pool: &PgPool, file: &Path, prune: bool, dry_run: bool) -> Result<()> {
+    let plan = policy::sync(pool, file, prune, dry_run).await?;
+
+    // An applied sync writes its own audit entry, a dry run is logged here
+    if dry_run {
+        let builder = LogBuilder::system(LogAction::Custom("planned".to_string()), "rbac_policy")
+            .with_resource_id(file.display().to_string());
+        record(pool, builder, json!({
+            "changes": plan.changes.iter().map(|change| change.to_string()).collect::<Vec<_>>(),
+            "extras": plan.extras.iter().map(|extra| extra.to_string()).collect::<Vec<_>>(),
+            "pruned": prune,
+            "dry_run": true
+        }))
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let cli = Cli::parse();
+
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&std::env::var("DATABASE_URL").context("DATABASE_URL must be set")?)
+        .await
+        .context("Failed to connect to Postgres")?;
+
+    match cli.command {
+        Command::CreateUser { email, username, password, roles } => {
+            create_user(&pool, email, username, password, roles).await
+        }
+        Command::GrantRole { email, role, expires_at } => grant_role(&pool, &email, &role, expires_at).await,
+        Command::ResetPassword { email, password } => reset_password(&pool, &email, password).await,
+        Command::RevokeSessions { email } => revoke_sessions(&pool, &email).await,
+        Command::Migrate { command: None | Some(MigrateCommand::Run) } => migrate(&pool).await,
+        Command::Migrate { command: Some(MigrateCommand::Status) } => migration_status(&pool).await,
+        Command::Migrate { command: Some(MigrateCommand::Revert { target }) } => {
+            revert_migrations(&pool, target).await
+        }
+        Command::Rbac { command: RbacCommand::Export { output } } => export_rbac(&pool, output).await,
+        Command::Rbac { command: RbacCommand::Import { file, prune, dry_run } } => {
+            import_rbac(&pool, &file, prune, dry_run).await
+        }
+    }
+}
@@ -0,0 +1,6 @@
+#[macro_use] extern crate rocket;
+
+pub mod middleware;
+pub mod models;
+pub mod routes;
+pub mod utils;
@@ -1,54 +1,45 @@
 #[macro_use] extern crate rocket;
 
-use rocket::serde::json::{Json, json};
-use rocket::serde::json::Value;
-use rocket::Request;
 use dotenv::dotenv;
 use sqlx::postgres::PgPoolOptions;
 
-mod middleware;
-mod models;
-mod routes;
-mod utils;
-use middleware::cors::CORS;
-
-#[catch(404)]
-fn not_found() -> Json<Value> {
-    Json(json!({
-        "status": 404,
-        "error": {
-            "message": "The path you're looking for seems to be missing in the void.",
-            "details": "Check the URL and try again, or navigate back to safety.",
-            "code": "RESOURCE_NOT_FOUND"
-        },
-        "timestamp": chrono::Utc::now().to_rfc3339()
-    }))
-}
-
-#[catch(500)]
-fn internal_error(req: &Request) -> Json<Value> {
-    Json(json!({
-        "status": 500,
-        "error": {
-            "message": "Something went wrong on our end.",
-            "details": "Our team has been notified and is working on it.",
-            "code": "INTERNAL_SERVER_ERROR",
-            "path": req.uri().path().to_string()
-        },
-        "timestamp": chrono::Utc::now().to_rfc3339()
-    }))
-}
+use userspace::middleware::cors::CORS;
+use userspace::middleware::rate_limit::{self, RateLimiter};
+use userspace::middleware::request_id::RequestIdFairing;
+use userspace::routes;
+use userspace::utils::authz::AuthzCache;
+use userspace::utils::login_attempts::{self, LoginThrottle};
+use userspace::utils::mailer::Mailer;
+use userspace::utils::migrations;
+use userspace::utils::policy;
+use userspace::utils::role_sweeper;
+use userspace::utils::webauthn::build_webauthn;
 
 #[launch]
 async fn rocket() -> _ {
     dotenv().ok();
     
+    // Rate limits and login throttling share this pool with the handlers
+    let max_connections = std::env::var("DATABASE_MAX_CONNECTIONS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(20);
+
     let pool = PgPoolOptions::new()
-        .max_connections(5)
+        .max_connections(max_connections)
         .connect(&std::env::var("DATABASE_URL").expect("DATABASE_URL must be set"))
         .await
         .expect("Failed to connect to Postgres");
 
+    migrations::run_from_env(&pool).await.expect("Failed to run migrations");
+    policy::sync_from_env(&pool).await.expect("Failed to apply the RBAC policy");
+
+    let mailer = Mailer::from_env().expect("Failed to configure the mail transport");
+    let webauthn = build_webauthn().expect("Failed to configure WebAuthn");
+    let login_throttle = LoginThrottle::from_env(&pool).expect("Failed to configure login throttling");
+    let rate_limiter = RateLimiter::from_env(&pool).expect("Failed to configure rate limiting");
+    let role_sweeper = role_sweeper::fairing().expect("Failed to configure the role sweeper");
+
     let cors = CORS::new();
     let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
     let config = rocket::Config::figment()
@@ -56,9 +47,20 @@ async fn rocket() -> _ {
 
     rocket::custom(config)
         .manage(pool)
+        .manage(mailer)
+        .manage(webauthn)
+        .manage(AuthzCache::from_env())
+        .manage(login_throttle)
+        .attach(RequestIdFairing)
+        .attach(rate_limiter)
         .attach(cors)
+        .attach(role_sweeper)
+        .attach(login_attempts::fairing())
+        .mount("/", rate_limit::routes())
         .mount("/api/auth", routes::auth_routes())
         .mount("/api/admin", routes::admin_routes())
         .mount("/api/users", routes::user_routes())
-        .register("/", catchers![not_found, internal_error])
+        .mount("/api/orgs", routes::org_routes())
+        .mount("/api/authz", routes::authz_routes())
+        .register("/", routes::catchers())
 }
\ No newline at end of file
@@ -5,14 +5,16 @@ use std::collections::HashMap;
 use lazy_static::lazy_static;
 use std::env;
 
+use crate::middleware::service_client;
+
 #[derive(Debug)]
 struct ClientConfig {
     client_type: ClientType,
     allowed_methods: Vec<&'static str>,
 }
 
-#[derive(Debug, PartialEq)]
-enum ClientType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientType {
     Web,
     Game,
     Mobile,
@@ -32,16 +34,60 @@ impl From<&str> for ClientType {
     }
 }
 
+impl ClientType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClientType::Web => "web",
+            ClientType::Game => "game",
+            ClientType::Mobile => "mobile",
+            ClientType::Desktop => "desktop",
+            ClientType::Service => "service",
+        }
+    }
+
+    /// The kind of client sending `request`: browsers by their configured
+    /// `Origin`, backends once their service key checks out, other clients by
+    /// the headers they're allowed to send. Clients can claim any of these
+    /// but `Service`, so don't grant access based on it
+    pub fn of(request: &Request<'_>) -> Option<ClientType> {
+        if let Some(client_type) = request.headers().get_one("Origin").and_then(|origin| CLIENT_TYPES.get(origin)) {
+            return Some(*client_type);
+        }
+
+        let headers = request.headers();
+        if service_client::authenticate(request).is_some() {
+            Some(ClientType::Service)
+        } else if headers.contains("X-Game-Version") || headers.contains("X-Game-Platform") {
+            Some(ClientType::Game)
+        } else if headers.contains("X-Device-Type") {
+            Some(ClientType::Mobile)
+        } else if headers.contains("X-App-Version") {
+            Some(ClientType::Desktop)
+        } else {
+            None
+        }
+    }
+}
+
 lazy_static! {
+    /// Client type of every configured origin
+    static ref CLIENT_TYPES: HashMap<String, ClientType> = CORS::load_client_configs()
+        .into_iter()
+        .map(|(origin, config)| (origin, config.client_type))
+        .collect();
+
     static ref ROUTE_CONFIGS: HashMap<&'static str, Vec<&'static str>> = {
         let mut m = HashMap::new();
         m.insert("/api/auth", vec!["POST", "OPTIONS"]);
         m.insert("/api/admin", vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"]);
-        m.insert("/api/users", vec!["GET", "PUT", "OPTIONS"]);
+        m.insert("/api/users", vec!["GET", "PUT", "DELETE", "OPTIONS"]);
+        m.insert("/api/orgs", vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"]);
+        m.insert("/api/authz", vec!["POST", "OPTIONS"]);
         m
     };
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CORS {
     client_origins: HashMap<String, ClientConfig>,
 }
@@ -105,7 +151,7 @@ impl CORS {
 
     fn get_headers_for_client_type(client_type: &ClientType) -> &'static str {
         match client_type {
-            ClientType::Web => "Content-Type, Authorization, Accept, X-Real-IP, X-Requested-With",
+            ClientType::Web => "Content-Type, Authorization, Accept, X-Real-IP, X-Requested-With, If-None-Match",
             ClientType::Game => "Content-Type, Authorization, X-Game-Version, X-Game-Platform",
             ClientType::Mobile => "Content-Type, Authorization, X-App-Version, X-Device-Type",
             ClientType::Desktop => "Content-Type, Authorization, X-App-Version",
@@ -118,6 +164,12 @@ impl CORS {
     }
 }
 
+impl Default for CORS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[rocket::async_trait]
 impl Fairing for CORS {
     fn info(&self) -> Info {
@@ -143,6 +195,7 @@ impl Fairing for CORS {
                 ));
 
                 response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+                response.set_header(Header::new("Access-Control-Expose-Headers", "ETag, X-Request-ID, Retry-After, RateLimit-Limit, RateLimit-Remaining, RateLimit-Reset, RateLimit-Policy"));
 
                 if request.method() == rocket::http::Method::Options {
                     response.set_header(Header::new("Access-Control-Max-Age", "86400"));
@@ -1,2 +1,7 @@
 pub mod cors;
-pub mod verify_jwt;
\ No newline at end of file
+pub mod verify_jwt;
+pub mod require_permission;
+pub mod service_client;
+pub mod request_id;
+pub mod rate_limit;
+pub mod request_context;
\ No newline at end of file
@@ -0,0 +1,659 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Orbit, Request, Response, Rocket, Route};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::middleware::cors::ClientType;
+use crate::middleware::request_context::client_ip;
+use crate::middleware::service_client;
+use crate::middleware::verify_jwt::token_client;
+use crate::utils::api_error::ApiError;
+
+/// Where limited requests are rerouted to, a fairing can't answer them itself
+const LIMITED_PATH: &str = "/__rate_limited";
+
+/// `capacity` requests at once, refilled evenly over `period`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl Budget {
+    fn refill_per_second(&self) -> f64 {
+        self.capacity as f64 / self.period.as_secs_f64()
+    }
+}
+
+/// A budget for requests under `mount` (any mount without one) from clients
+/// of `client_type` (any client without one)
+#[derive(Debug, Clone)]
+pub struct RateLimitRule {
+    pub mount: Option<String>,
+    pub client_type: Option<ClientType>,
+    pub budget: Budget,
+}
+
+impl RateLimitRule {
+    fn matches_mount(&self, path: &str) -> bool {
+        match &self.mount {
+            Some(mount) => path
+                .strip_prefix(mount.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+            None => true,
+        }
+    }
+
+    fn matches(&self, path: &str, client_type: Option<ClientType>) -> bool {
+        self.matches_mount(path) && (self.client_type.is_none() || self.client_type == client_type)
+    }
+
+    /// Mount matches beat client type matches, longer mounts beat shorter ones
+    fn specificity(&self) -> (usize, bool) {
+        (self.mount.as_ref().map_or(0, |mount| mount.len() + 1), self.client_type.is_some())
+    }
+}
+
+impl fmt::Display for RateLimitRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mount.as_deref().unwrap_or("*"))?;
+        if let Some(client_type) = self.client_type {
+            write!(f, ":{}", client_type.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse rules like `/api/auth=20/60, /api/auth:mobile=40/60, *:service=1000/60, *=300/60`,
+/// each `<mount or *>[:<client type>]=<requests>/<seconds>`
+pub fn parse_rules(spec: &str) -> Result<Vec<RateLimitRule>> {
+    let mut rules = Vec::new();
+
+    for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (scope, budget) = entry
+            .split_once('=')
+            .with_context(|| format!("Rate limit '{}' is missing '=<requests>/<seconds>'", entry))?;
+        let (mount, client_type) = match scope.trim().split_once(':') {
+            Some((mount, client_type)) => (mount.trim(), Some(client_type.trim())),
+            None => (scope.trim(), None),
+        };
+        let (capacity, seconds) = budget
+            .trim()
+            .split_once('/')
+            .and_then(|(capacity, seconds)| Some((capacity.trim().parse::<u32>().ok()?, seconds.trim().parse::<u64>().ok()?)))
+            .filter(|(capacity, seconds)| *capacity > 0 && *seconds > 0)
+            .with_context(|| format!("Rate limit '{}' needs a budget like 100/60", entry))?;
+
+        let mount = match mount {
+            "*" => None,
+            mount if mount.starts_with('/') => Some(mount.trim_end_matches('/').to_string()),
+            mount => bail!("Rate limit '{}' must name a mount starting with '/' or '*', not '{}'", entry, mount),
+        };
+        let client_type = match client_type {
+            Some(name) => match ClientType::from(name) {
+                client_type if client_type.as_str() == name.to_lowercase() => Some(client_type),
+                _ => bail!("Rate limit '{}' names an unknown client type '{}'", entry, name),
+            },
+            None => None,
+        };
+
+        rules.push(RateLimitRule {
+            mount,
+            client_type,
+            budget: Budget { capacity, period: Duration::from_secs(seconds) },
+        });
+    }
+
+    Ok(rules)
+}
+
+/// Who a request is counted against: a backend by its verified service id,
+/// a user by their access token, anyone else by address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientKey {
+    Service(String),
+    User(Uuid),
+    Ip(IpAddr),
+}
+
+impl ClientKey {
+    /// The key, along with the client type it's budgeted as. Only a verified
+    /// service key or the session behind an access token vouch for a client
+    /// type, what the request's headers claim doesn't count
+    pub fn of(request: &Request<'_>) -> Option<(ClientKey, Option<ClientType>)> {
+        if let Some(service_id) = service_client::authenticate(request) {
+            return Some((ClientKey::Service(service_id), Some(ClientType::Service)));
+        }
+
+        if let Some((user_id, client_type)) = token_client(request) {
+            return Some((ClientKey::User(user_id), client_type));
+        }
+
+        client_ip(request).map(|ip| (ClientKey::Ip(ip), None))
+    }
+}
+
+impl fmt::Display for ClientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientKey::Service(service_id) => write!(f, "service:{}", service_id),
+            ClientKey::User(user_id) => write!(f, "user:{}", user_id),
+            ClientKey::Ip(ip) => write!(f, "ip:{}", ip),
+        }
+    }
+}
+
+/// Where the buckets live. They have to be shared by every instance for the
+/// limits to hold, so anything but Postgres is only good for a single instance
+#[rocket::async_trait]
+pub trait BucketStore: Send + Sync {
+    /// Refill the bucket `key` for the time since it was last used and take
+    /// a token if there is one. Returns whether one was taken and how many
+    /// are left. Must be atomic, instances take tokens concurrently
+    async fn take(&self, key: &str, budget: &Budget) -> Result<(bool, f64)>;
+
+    /// Delete buckets unused for `idle`. They've refilled by then, so this
+    /// changes nothing but the size of the store
+    async fn prune(&self, idle: Duration) -> Result<u64>;
+}
+
+/// Keeps the buckets in the `rate_limit_buckets` table
+pub struct PgBucketStore {
+    pool: PgPool,
+}
+
+impl PgBucketStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgBucketStore { pool }
+    }
+}
+
+#[rocket::async_trait]
+impl BucketStore for PgBucketStore {
+    async fn take(&self, key: &str, budget: &Budget) -> Result<(bool, f64)> {
+        let capacity = budget.capacity as f64;
+        let rate = budget.refill_per_second();
+
+        // Only updates when a token is left, an empty bucket returns no row
+        let taken = sqlx::query_scalar!(
+            r#"
+            INSERT INTO rate_limit_buckets AS b (key, tokens, updated_at)
+            VALUES ($1, $2::float8 - 1, now())
+            ON CONFLICT (key) DO UPDATE SET
+                tokens = LEAST($2, b.tokens + GREATEST(EXTRACT(EPOCH FROM now() - b.updated_at)::float8, 0) * $3) - 1,
+                updated_at = now()
+            WHERE LEAST($2, b.tokens + GREATEST(EXTRACT(EPOCH FROM now() - b.updated_at)::float8, 0) * $3) >= 1
+            RETURNING tokens
+            "#,
+            key,
+            capacity,
+            rate
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(tokens) = taken {
+            return Ok((true, tokens));
+        }
+
+        let tokens = sqlx::query_scalar!(
+            r#"
+            SELECT LEAST($2, tokens + GREATEST(EXTRACT(EPOCH FROM now() - updated_at)::float8, 0) * $3) AS "tokens!"
+            FROM rate_limit_buckets
+            WHERE key = $1
+            "#,
+            key,
+            capacity,
+            rate
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .unwrap_or(0.0);
+
+        Ok((false, tokens))
+    }
+
+    async fn prune(&self, idle: Duration) -> Result<u64> {
+        let result = sqlx::query!(
+            "DELETE FROM rate_limit_buckets WHERE updated_at < now() - make_interval(secs => $1)",
+            idle.as_secs_f64()
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Keeps the buckets in this process, for a single instance or tests
+#[derive(Default)]
+pub struct MemoryBucketStore {
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+#[rocket::async_trait]
+impl BucketStore for MemoryBucketStore {
+    async fn take(&self, key: &str, budget: &Budget) -> Result<(bool, f64)> {
+        let capacity = budget.capacity as f64;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().expect("rate limit buckets poisoned");
+        let (tokens, updated_at) = buckets.entry(key.to_string()).or_insert((capacity, now));
+
+        *tokens = capacity.min(*tokens + now.duration_since(*updated_at).as_secs_f64() * budget.refill_per_second());
+        *updated_at = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok((true, *tokens))
+        } else {
+            Ok((false, *tokens))
+        }
+    }
+
+    async fn prune(&self, idle: Duration) -> Result<u64> {
+        let mut buckets = self.buckets.lock().expect("rate limit buckets poisoned");
+        let before = buckets.len();
+        buckets.retain(|_, (_, updated_at)| updated_at.elapsed() < idle);
+        Ok((before - buckets.len()) as u64)
+    }
+}
+
+/// The outcome of taking a token, sent back in the `RateLimit-*` headers
+#[derive(Debug, Clone, Copy)]
+pub struct Decision {
+    pub allowed: bool,
+    pub budget: Budget,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again
+    pub reset: u64,
+    /// Seconds until the next token, only meaningful when not allowed
+    pub retry_after: u64,
+}
+
+impl Decision {
+    fn new(allowed: bool, budget: Budget, tokens: f64) -> Self {
+        let rate = budget.refill_per_second();
+
+        Decision {
+            allowed,
+            budget,
+            remaining: tokens.max(0.0).floor() as u32,
+            reset: ((budget.capacity as f64 - tokens).max(0.0) / rate).ceil() as u64,
+            retry_after: ((1.0 - tokens).max(0.0) / rate).ceil().max(1.0) as u64,
+        }
+    }
+}
+
+/// The decision for the current request, if it was rate limited at all
+struct RequestDecision(Option<Decision>);
+
+/// Token-bucket rate limiting for every request, with the budget of the most
+/// specific rule matching the request's mount and client type. Anonymous
+/// requests get the strictest budget of their mount
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn BucketStore>,
+    rules: Vec<RateLimitRule>,
+}
+
+impl RateLimiter {
+    pub fn new(store: impl BucketStore + 'static, rules: Vec<RateLimitRule>) -> Self {
+        RateLimiter { store: Arc::new(store), rules }
+    }
+
+    /// Rules from `RATE_LIMITS`, nothing is limited without them. The store
+    /// is picked with `RATE_LIMIT_STORE` (postgres or memory), defaulting to
+    /// postgres. That costs every limited request a query on the shared pool,
+    /// and a refused one a second
+    pub fn from_env(pool: &PgPool) -> Result<Self> {
+        let rules = parse_rules(&std::env::var("RATE_LIMITS").unwrap_or_default())?;
+
+        let limiter = match std::env::var("RATE_LIMIT_STORE").unwrap_or_default().to_lowercase().as_str() {
+            "" | "postgres" => RateLimiter::new(PgBucketStore::new(pool.clone()), rules),
+            "memory" => RateLimiter::new(MemoryBucketStore::default(), rules),
+            other => bail!("Unknown RATE_LIMIT_STORE '{}'", other),
+        };
+
+        Ok(limiter)
+    }
+
+    pub fn rule_for(&self, path: &str, client_type: Option<ClientType>) -> Option<&RateLimitRule> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(path, client_type))
+            .max_by_key(|rule| rule.specificity())
+    }
+
+    /// The rule for callers nobody vouches for: the tightest budget any
+    /// client type gets under the most specific mount matching `path`
+    pub fn strictest_rule_for(&self, path: &str) -> Option<&RateLimitRule> {
+        let mount = self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches_mount(path))
+            .map(|rule| rule.specificity().0)
+            .max()?;
+
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches_mount(path) && rule.specificity().0 == mount)
+            .min_by(|a, b| {
+                a.budget
+                    .refill_per_second()
+                    .total_cmp(&b.budget.refill_per_second())
+                    .then(a.budget.capacity.cmp(&b.budget.capacity))
+            })
+    }
+
+    /// Take a token for `request`. Requests no rule covers, and requests the
+    /// store failed on, aren't limited
+    pub async fn check(&self, request: &Request<'_>) -> Option<Decision> {
+        let (client, client_type) = ClientKey::of(request)?;
+        let path = request.uri().path();
+        let rule = match client {
+            ClientKey::Ip(_) => self.strictest_rule_for(path.as_str()),
+            _ => self.rule_for(path.as_str(), client_type),
+        }?;
+        // Keyed on the mount only, so a user's sessions from different
+        // client types spend from one bucket
+        let key = format!("{}|{}", rule.mount.as_deref().unwrap_or("*"), client);
+
+        match self.store.take(&key, &rule.budget).await {
+            Ok((allowed, tokens)) => Some(Decision::new(allowed, rule.budget, tokens)),
+            Err(e) => {
+                eprintln!("Rate limiting {} failed: {:#}", key, e);
+                None
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate Limiter",
+            kind: Kind::Liftoff | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_liftoff(&self, _: &Rocket<Orbit>) {
+        // A bucket unused for its whole period is full again, as good as gone
+        let idle = match self.rules.iter().map(|rule| rule.budget.period).max() {
+            Some(idle) => idle,
+            None => return,
+        };
+        let store = self.store.clone();
+
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(idle.max(Duration::from_secs(60)));
+
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = store.prune(idle).await {
+                    eprintln!("Failed to prune rate limit buckets: {}", e);
+                }
+            }
+        });
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        if self.rules.is_empty() || request.method() == Method::Options {
+            return;
+        }
+
+        let decision = self.check(request).await;
+        request.local_cache(|| RequestDecision(decision));
+
+        if decision.is_some_and(|decision| !decision.allowed) {
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(LIMITED_PATH).expect("valid rate limit path"));
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let decision = match request.local_cache(|| RequestDecision(None)).0 {
+            Some(decision) => decision,
+            None => return,
+        };
+
+        response.set_header(Header::new("RateLimit-Limit", decision.budget.capacity.to_string()));
+        response.set_header(Header::new("RateLimit-Remaining", decision.remaining.to_string()));
+        response.set_header(Header::new("RateLimit-Reset", decision.reset.to_string()));
+        response.set_header(Header::new(
+            "RateLimit-Policy",
+            format!("{};w={}", decision.budget.capacity, decision.budget.period.as_secs()),
+        ));
+    }
+}
+
+/// Request guard for requests the fairing turned away
+pub struct Limited(Option<Decision>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Limited {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let decision = request.local_cache(|| RequestDecision(None)).0;
+        Outcome::Success(Limited(decision.filter(|decision| !decision.allowed)))
+    }
+}
+
+#[get("/__rate_limited")]
+fn rate_limited(limited: Limited) -> ApiError {
+    match limited.0 {
+        Some(decision) => ApiError::too_many_requests(
+            "RATE_LIMITED",
+            "Too many requests.",
+            decision.retry_after,
+        ),
+        None => ApiError::not_found("RESOURCE_NOT_FOUND", "The resource doesn't exist."),
+    }
+}
+
+/// The route limited requests end up on, mount it at `/`
+pub fn routes() -> Vec<Route> {
+    routes![rate_limited]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_jwt::create_token;
+
+    fn budget(capacity: u32, seconds: u64) -> Budget {
+        Budget { capacity, period: Duration::from_secs(seconds) }
+    }
+
+    /// Take tokens from `store` until it refuses, returning how many were taken
+    async fn drain(store: &dyn BucketStore, key: &str, budget: &Budget) -> u32 {
+        let mut taken = 0;
+        while store.take(key, budget).await.unwrap().0 {
+            taken += 1;
+        }
+        taken
+    }
+
+    #[rocket::async_test]
+    async fn buckets_start_full_and_run_dry() {
+        let store = MemoryBucketStore::default();
+        let budget = budget(3, 60);
+
+        assert_eq!(store.take("a", &budget).await.unwrap(), (true, 2.0));
+        assert_eq!(drain(&store, "a", &budget).await, 2);
+        assert!(!store.take("a", &budget).await.unwrap().0);
+
+        // Other keys have their own bucket
+        assert_eq!(drain(&store, "b", &budget).await, 3);
+    }
+
+    #[rocket::async_test]
+    async fn buckets_refill_evenly_up_to_their_capacity() {
+        let store = MemoryBucketStore::default();
+        let budget = budget(10, 60);
+        drain(&store, "a", &budget).await;
+
+        // 30 seconds of a 10 per minute budget is 5 tokens
+        let age = |seconds: u64| {
+            let mut buckets = store.buckets.lock().unwrap();
+            buckets.get_mut("a").unwrap().1 -= Duration::from_secs(seconds);
+        };
+        age(30);
+        let (allowed, tokens) = store.take("a", &budget).await.unwrap();
+        assert!(allowed);
+        assert!((tokens - 4.0).abs() < 0.01, "{}", tokens);
+
+        // Idle for longer than the period refills to capacity, not beyond
+        age(600);
+        let (_, tokens) = store.take("a", &budget).await.unwrap();
+        assert!((tokens - 9.0).abs() < 0.01, "{}", tokens);
+    }
+
+    #[rocket::async_test]
+    async fn idle_buckets_are_pruned() {
+        let store = MemoryBucketStore::default();
+        store.take("old", &budget(1, 60)).await.unwrap();
+        store.take("new", &budget(1, 60)).await.unwrap();
+        store.buckets.lock().unwrap().get_mut("old").unwrap().1 -= Duration::from_secs(120);
+
+        assert_eq!(store.prune(Duration::from_secs(60)).await.unwrap(), 1);
+        assert!(store.buckets.lock().unwrap().contains_key("new"));
+    }
+
+    #[sqlx::test]
+    async fn postgres_buckets_take_and_refill_like_memory_ones(pool: PgPool) {
+        let store = PgBucketStore::new(pool.clone());
+        let budget = budget(10, 60);
+
+        assert_eq!(drain(&store, "a", &budget).await, 10);
+        let (allowed, tokens) = store.take("a", &budget).await.unwrap();
+        assert!(!allowed);
+        assert!(tokens < 1.0, "{}", tokens);
+
+        sqlx::query("UPDATE rate_limit_buckets SET updated_at = updated_at - interval '30 seconds'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (allowed, tokens) = store.take("a", &budget).await.unwrap();
+        assert!(allowed);
+        assert!((tokens - 4.0).abs() < 0.1, "{}", tokens);
+
+        sqlx::query("UPDATE rate_limit_buckets SET updated_at = updated_at - interval '1 hour'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(store.prune(Duration::from_secs(60)).await.unwrap(), 1);
+    }
+
+    #[test]
+    fn decisions_count_down_to_the_next_token_and_a_full_bucket() {
+        // 10 per minute is a token every 6 seconds
+        let allowed = Decision::new(true, budget(10, 60), 7.5);
+        assert_eq!(allowed.remaining, 7);
+        assert_eq!(allowed.reset, 15);
+
+        let refused = Decision::new(false, budget(10, 60), 0.5);
+        assert_eq!(refused.remaining, 0);
+        assert_eq!(refused.retry_after, 3);
+        assert_eq!(refused.reset, 57);
+
+        // Never tell a client to retry right away
+        assert_eq!(Decision::new(false, budget(1000, 1), 0.9999).retry_after, 1);
+    }
+
+    #[test]
+    fn rules_parse_mounts_client_types_and_budgets() {
+        let rules = parse_rules("/api/auth/=20/60, /api/auth:mobile=40/60, *:service=1000/60, *=300/60,").unwrap();
+
+        let parsed: Vec<(String, Budget)> = rules.iter().map(|rule| (rule.to_string(), rule.budget)).collect();
+        assert_eq!(parsed, [
+            ("/api/auth".to_string(), budget(20, 60)),
+            ("/api/auth:mobile".to_string(), budget(40, 60)),
+            ("*:service".to_string(), budget(1000, 60)),
+            ("*".to_string(), budget(300, 60)),
+        ]);
+
+        assert!(parse_rules("").unwrap().is_empty());
+        for invalid in ["/api", "/api=20", "/api=0/60", "/api=20/0", "api=20/60", "/api:robot=20/60"] {
+            assert!(parse_rules(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn the_most_specific_rule_wins() {
+        let limiter = RateLimiter::new(
+            MemoryBucketStore::default(),
+            parse_rules("/api/auth=20/60, /api/auth/login=5/60, /api:mobile=40/60, *=300/60").unwrap(),
+        );
+        let rule = |path: &str, client_type: Option<ClientType>| {
+            limiter.rule_for(path, client_type).map(ToString::to_string)
+        };
+
+        assert_eq!(rule("/api/auth/login", None).as_deref(), Some("/api/auth/login"));
+        assert_eq!(rule("/api/auth/register", Some(ClientType::Mobile)).as_deref(), Some("/api/auth"));
+        assert_eq!(rule("/api/users", Some(ClientType::Mobile)).as_deref(), Some("/api:mobile"));
+        assert_eq!(rule("/api/users", None).as_deref(), Some("*"));
+        // Mounts match whole segments only
+        assert_eq!(rule("/api/authz/check", None).as_deref(), Some("*"));
+    }
+
+    #[test]
+    fn anonymous_callers_get_the_tightest_budget_of_the_closest_mount() {
+        let limiter = RateLimiter::new(
+            MemoryBucketStore::default(),
+            parse_rules("/api/auth:mobile=40/60, /api/auth:game=10/30, *:service=1000/60, *=300/60").unwrap(),
+        );
+        let rule = |path: &str| limiter.strictest_rule_for(path).map(ToString::to_string);
+
+        // 10 per 30 seconds refills slower than 40 per minute
+        assert_eq!(rule("/api/auth/login").as_deref(), Some("/api/auth:game"));
+        assert_eq!(rule("/api/users").as_deref(), Some("*"));
+    }
+
+    #[rocket::async_test]
+    async fn claimed_client_types_dont_change_the_budget() {
+        std::env::set_var("JWT_SECRET", "test-secret");
+        let limiter = RateLimiter::new(
+            MemoryBucketStore::default(),
+            parse_rules("/api/auth=2/60, /api/auth:mobile=3/60, *:service=1000/60").unwrap(),
+        );
+        let client = rocket::local::asynchronous::Client::untracked(rocket::build()).await.unwrap();
+        let request = |header: Option<(&'static str, String)>| {
+            let request = client.post("/api/auth/login").remote("203.0.113.7:4000".parse().unwrap());
+            match header {
+                Some((name, value)) => request.header(Header::new(name, value)),
+                None => request,
+            }
+        };
+
+        let plain = limiter.check(&request(None)).await.unwrap();
+        assert_eq!((plain.allowed, plain.budget.capacity), (true, 2));
+
+        // Claiming to be a mobile app gets nothing more
+        let mobile = limiter.check(&request(Some(("X-Device-Type", "ios".to_string())))).await.unwrap();
+        assert_eq!((mobile.allowed, mobile.budget.capacity), (true, 2));
+
+        // An unverified service id is no service
+        let service = limiter.check(&request(Some(("X-Service-ID", "billing".to_string())))).await.unwrap();
+        assert_eq!((service.allowed, service.budget.capacity), (false, 2));
+
+        // A session opened from the mobile app carries its budget, in its own bucket
+        let token = create_token(Uuid::new_v4(), Uuid::new_v4(), Some(ClientType::Mobile)).unwrap();
+        let user = limiter.check(&request(Some(("Authorization", format!("Bearer {}", token))))).await.unwrap();
+        assert_eq!((user.allowed, user.budget.capacity, user.remaining), (true, 3, 2));
+    }
+}
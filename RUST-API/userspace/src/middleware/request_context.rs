@@ -0,0 +1,164 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use lazy_static::lazy_static;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+use crate::middleware::cors::ClientType;
+use crate::middleware::request_id::RequestId;
+
+lazy_static! {
+    /// Proxies whose `X-Forwarded-For` and `X-Real-IP` headers are believed,
+    /// from `TRUSTED_PROXIES` (e.g. "127.0.0.1,10.0.0.0/8")
+    static ref TRUSTED_PROXIES: Vec<IpRange> = load_trusted_proxies();
+}
+
+/// An address or a CIDR block
+#[derive(Debug, Clone, Copy)]
+struct IpRange {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl IpRange {
+    fn parse(value: &str) -> Option<Self> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+
+        let network = address.trim().parse::<IpAddr>().ok()?.to_canonical();
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|prefix| *prefix <= max_prefix)?,
+            None => max_prefix,
+        };
+
+        Some(IpRange { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+fn load_trusted_proxies() -> Vec<IpRange> {
+    std::env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .filter_map(|value| {
+            let range = IpRange::parse(value);
+            if range.is_none() {
+                eprintln!("Ignoring invalid TRUSTED_PROXIES entry '{}'", value);
+            }
+            range
+        })
+        .collect()
+}
+
+fn is_trusted_proxy(ip: IpAddr) -> bool {
+    TRUSTED_PROXIES.iter().any(|range| range.contains(ip))
+}
+
+/// Addresses in forwarding headers, with or without a port
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+    value
+        .parse::<IpAddr>()
+        .or_else(|_| value.parse::<SocketAddr>().map(|address| address.ip()))
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+/// The address of the client behind `request`. Forwarding headers are only
+/// believed from a trusted proxy, and `X-Forwarded-For` is read from the
+/// nearest hop back, so a client can't pass off an address it prepended
+pub fn client_ip(request: &Request<'_>) -> Option<IpAddr> {
+    let peer = request.remote()?.ip().to_canonical();
+    if !is_trusted_proxy(peer) {
+        return Some(peer);
+    }
+
+    let forwarded: Vec<IpAddr> = request
+        .headers()
+        .get("X-Forwarded-For")
+        .flat_map(|header| header.split(','))
+        .filter_map(parse_forwarded_ip)
+        .collect();
+
+    if let Some(ip) = forwarded.iter().rev().find(|ip| !is_trusted_proxy(**ip)) {
+        return Some(*ip);
+    }
+
+    // Every hop is a trusted proxy, the first one is as far back as it goes
+    if let Some(ip) = forwarded.first() {
+        return Some(*ip);
+    }
+
+    request
+        .headers()
+        .get_one("X-Real-IP")
+        .and_then(parse_forwarded_ip)
+        .or(Some(peer))
+}
+
+/// A header value fit for the logs: trimmed, without control characters
+/// and at most `max` characters long
+fn header_value(request: &Request<'_>, name: &str, max: usize) -> Option<String> {
+    let value: String = request
+        .headers()
+        .get_one(name)?
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(max)
+        .collect();
+
+    (!value.is_empty()).then_some(value)
+}
+
+/// Where a request came from, for the audit log
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub client_type: Option<ClientType>,
+    /// From `X-App-Version`, or `X-Game-Version` for games
+    pub app_version: Option<String>,
+    pub request_id: String,
+}
+
+impl RequestContext {
+    pub fn of(request: &Request<'_>) -> Self {
+        RequestContext {
+            ip_address: client_ip(request),
+            user_agent: header_value(request, "User-Agent", 512),
+            client_type: ClientType::of(request),
+            app_version: header_value(request, "X-App-Version", 64)
+                .or_else(|| header_value(request, "X-Game-Version", 64)),
+            request_id: RequestId::of(request).to_string(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestContext {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(request.local_cache(|| RequestContext::of(request)).clone())
+    }
+}
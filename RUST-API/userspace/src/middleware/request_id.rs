@@ -0,0 +1,62 @@
+use std::convert::Infallible;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request, Response};
+use uuid::Uuid;
+
+/// Id of the request being served, echoed in the `X-Request-ID` response
+/// header and in error bodies so a report can be matched with the logs. An
+/// id sent by a proxy in `X-Request-ID` is kept, otherwise one is generated
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// The id of `request`, assigned on first use
+    pub fn of<'r>(request: &'r Request<'_>) -> &'r str {
+        &request.local_cache(|| RequestId(incoming_id(request).unwrap_or_else(|| Uuid::new_v4().to_string()))).0
+    }
+}
+
+/// Only short ids from a safe alphabet are taken over, anything else could
+/// be used to forge log lines
+fn incoming_id(request: &Request<'_>) -> Option<String> {
+    request
+        .headers()
+        .get_one("X-Request-ID")
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .filter(|id| id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .map(str::to_string)
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId(RequestId::of(request).to_string()))
+    }
+}
+
+/// Assigns every request its id and sets the `X-Request-ID` response header
+pub struct RequestIdFairing;
+
+#[rocket::async_trait]
+impl Fairing for RequestIdFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request ID",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        RequestId::of(request);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_header(Header::new("X-Request-ID", RequestId::of(request).to_string()));
+    }
+}
@@ -0,0 +1,130 @@
+use std::marker::PhantomData;
+
+use rocket::http::Status;
+use rocket::outcome::try_outcome;
+use rocket::request::{FromRequest, Outcome, Request};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::utils::auth::{has_org_permission, has_permission};
+
+/// A permission a route can require, named as in the `permissions` table
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+macro_rules! permissions {
+    ($($(#[$meta:meta])* $marker:ident => $name:literal),* $(,)?) => {
+        $(
+            $(#[$meta])*
+            pub struct $marker;
+
+            impl Permission for $marker {
+                const NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+permissions! {
+    RolesRead => "roles.read",
+    RolesCreate => "roles.create",
+    RolesUpdate => "roles.update",
+    RolesDelete => "roles.delete",
+    /// Attach permissions to roles
+    RolesGrant => "roles.grant",
+    /// Detach permissions from roles
+    RolesRevoke => "roles.revoke",
+    PermissionsRead => "permissions.read",
+    PermissionsCreate => "permissions.create",
+    PermissionsUpdate => "permissions.update",
+    PermissionsDelete => "permissions.delete",
+    UsersRead => "users.read",
+    UsersDelete => "users.delete",
+    UsersRolesAssign => "users.roles.assign",
+    UsersRolesRevoke => "users.roles.revoke",
+    UsersTwoFactorReset => "users.2fa.reset",
+    UsersUnlock => "users.unlock",
+    MigrationsRead => "migrations.read",
+    InvitationsRead => "invitations.read",
+    InvitationsCreate => "invitations.create",
+    InvitationsRevoke => "invitations.revoke",
+    OrgRead => "org.read",
+    OrgUpdate => "org.update",
+    OrgDelete => "org.delete",
+    OrgMembersRead => "org.members.read",
+    OrgMembersInvite => "org.members.invite",
+    OrgMembersRemove => "org.members.remove",
+    OrgMembersRolesAssign => "org.members.roles.assign",
+    OrgMembersRolesRevoke => "org.members.roles.revoke",
+}
+
+/// Request guard for an authenticated user holding permission `P`, e.g.
+/// `Require<UsersDelete>`. Fails with 401 without a valid session and with
+/// 403 when none of the user's roles grant the permission
+#[derive(Debug)]
+pub struct Require<P: Permission> {
+    pub user_id: Uuid,
+    _permission: PhantomData<fn() -> P>,
+}
+
+#[rocket::async_trait]
+impl<'r, P: Permission> FromRequest<'r> for Require<P> {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user = try_outcome!(request.guard::<AuthenticatedUser>().await);
+
+        let pool = match request.rocket().state::<PgPool>() {
+            Some(pool) => pool,
+            None => return Outcome::Error((Status::InternalServerError, Status::InternalServerError)),
+        };
+
+        match has_permission(pool, user.user_id, P::NAME).await {
+            Ok(true) => Outcome::Success(Require {
+                user_id: user.user_id,
+                _permission: PhantomData,
+            }),
+            Ok(false) => Outcome::Error((Status::Forbidden, Status::Forbidden)),
+            Err(status) => Outcome::Error((status, status)),
+        }
+    }
+}
+
+/// Request guard for an authenticated user holding permission `P` inside the
+/// organization whose id is the route's first dynamic segment, e.g.
+/// `/<org_id>/members`. Global roles count in every organization
+#[derive(Debug)]
+pub struct RequireOrg<P: Permission> {
+    pub user_id: Uuid,
+    _permission: PhantomData<fn() -> P>,
+}
+
+#[rocket::async_trait]
+impl<'r, P: Permission> FromRequest<'r> for RequireOrg<P> {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let organization_id = match request.param::<Uuid>(0) {
+            Some(Ok(organization_id)) => organization_id,
+            _ => return Outcome::Forward(Status::NotFound),
+        };
+
+        let user = try_outcome!(request.guard::<AuthenticatedUser>().await);
+
+        let pool = match request.rocket().state::<PgPool>() {
+            Some(pool) => pool,
+            None => return Outcome::Error((Status::InternalServerError, Status::InternalServerError)),
+        };
+
+        match has_org_permission(pool, user.user_id, organization_id, P::NAME).await {
+            Ok(true) => Outcome::Success(RequireOrg {
+                user_id: user.user_id,
+                _permission: PhantomData,
+            }),
+            Ok(false) => Outcome::Error((Status::Forbidden, Status::Forbidden)),
+            Err(status) => Outcome::Error((status, status)),
+        }
+    }
+}
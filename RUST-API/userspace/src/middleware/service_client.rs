@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sha2::{Digest, Sha256};
+
+lazy_static! {
+    /// Backends allowed to call service endpoints, from `SERVICE_CLIENTS`
+    /// (e.g. "billing:key1,search:key2"). Only key digests are kept around
+    static ref SERVICE_CLIENTS: HashMap<String, [u8; 32]> = load_service_clients();
+}
+
+fn load_service_clients() -> HashMap<String, [u8; 32]> {
+    std::env::var("SERVICE_CLIENTS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|client| client.split_once(':'))
+        .map(|(id, key)| (id.trim().to_string(), key_digest(key.trim())))
+        .filter(|(id, _)| !id.is_empty())
+        .collect()
+}
+
+fn key_digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// Compare digests without returning early, so response timing doesn't
+/// reveal how much of a key was right
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The id of the backend sending `request`, if its key checks out
+pub fn authenticate(request: &Request<'_>) -> Option<String> {
+    let service_id = request.headers().get_one("X-Service-ID").map(str::trim)?;
+    let key = request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+        .map(|(_, key)| key.trim())?;
+
+    match SERVICE_CLIENTS.get(service_id) {
+        Some(expected) if constant_time_eq(expected, &key_digest(key)) => Some(service_id.to_string()),
+        _ => None,
+    }
+}
+
+/// Request guard for another backend, the `Service` client type. It names
+/// itself in `X-Service-ID` and proves it with `Authorization: Bearer <key>`
+#[derive(Debug)]
+pub struct ServiceClient {
+    pub service_id: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ServiceClient {
+    type Error = Status;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(request) {
+            Some(service_id) => Outcome::Success(ServiceClient { service_id }),
+            None => Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+        }
+    }
+}
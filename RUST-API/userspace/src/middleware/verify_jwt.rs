@@ -1,18 +1,64 @@
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome, Request};
 use jsonwebtoken::{decode, DecodingKey, Validation};
-use serde::{Deserialize, Serialize};
+use lazy_static::lazy_static;
+use sqlx::PgPool;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: Uuid,
-    exp: usize,
+use crate::middleware::cors::ClientType;
+use crate::utils::create_jwt::Claims;
+use crate::utils::session::is_session_active;
+
+/// Places an access token can be read from
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TokenSource {
+    Cookie,
+    Bearer,
+}
+
+lazy_static! {
+    /// Order in which token sources are checked, from `AUTH_TOKEN_SOURCES`
+    /// (e.g. "bearer,cookie"). The first source that carries a token wins.
+    static ref TOKEN_SOURCES: Vec<TokenSource> = load_token_sources();
+}
+
+fn load_token_sources() -> Vec<TokenSource> {
+    let sources: Vec<TokenSource> = std::env::var("AUTH_TOKEN_SOURCES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|source| match source.trim().to_lowercase().as_str() {
+            "cookie" => Some(TokenSource::Cookie),
+            "bearer" => Some(TokenSource::Bearer),
+            _ => None,
+        })
+        .collect();
+
+    if sources.is_empty() {
+        vec![TokenSource::Cookie, TokenSource::Bearer]
+    } else {
+        sources
+    }
+}
+
+fn extract_token(request: &Request<'_>) -> Option<String> {
+    TOKEN_SOURCES.iter().find_map(|source| match source {
+        TokenSource::Cookie => request
+            .cookies()
+            .get_private("auth_token")
+            .map(|cookie| cookie.value().to_string()),
+        TokenSource::Bearer => request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+            .map(|(_, token)| token.trim().to_string()),
+    })
 }
 
 #[derive(Debug)]
 pub struct AuthenticatedUser {
     pub user_id: Uuid,
+    pub session_id: Uuid,
 }
 
 #[rocket::async_trait]
@@ -20,24 +66,46 @@ impl<'r> FromRequest<'r> for AuthenticatedUser {
     type Error = Status;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        if let Some(cookie) = request.cookies().get_private("auth_token") {
-            let token = cookie.value();
-            match decode_token(token) {
-                Ok(user_id) => Outcome::Success(AuthenticatedUser { user_id }),
-                Err(_) => Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
-            }
-        } else {
-            Outcome::Error((Status::Unauthorized, Status::Unauthorized))
+        let claims = match extract_token(request) {
+            Some(token) => match decode_token(&token) {
+                Ok(claims) => claims,
+                Err(_) => return Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+            },
+            None => return Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+        };
+
+        let pool = match request.rocket().state::<PgPool>() {
+            Some(pool) => pool,
+            None => return Outcome::Error((Status::InternalServerError, Status::InternalServerError)),
+        };
+
+        // The token alone is not enough, its session must still be live
+        match is_session_active(pool, claims.jti, claims.sub).await {
+            Ok(true) => Outcome::Success(AuthenticatedUser {
+                user_id: claims.sub,
+                session_id: claims.jti,
+            }),
+            Ok(false) => Outcome::Error((Status::Unauthorized, Status::Unauthorized)),
+            Err(_) => Outcome::Error((Status::InternalServerError, Status::InternalServerError)),
         }
     }
 }
 
-fn decode_token(token: &str) -> Result<Uuid, ()> {
+/// The user named by the request's access token and the client type their
+/// session was opened from, without checking the session. Good enough to
+/// tell clients apart, never for access decisions
+pub fn token_client(request: &Request<'_>) -> Option<(Uuid, Option<ClientType>)> {
+    extract_token(request)
+        .and_then(|token| decode_token(&token).ok())
+        .map(|claims| (claims.sub, claims.client.as_deref().map(ClientType::from)))
+}
+
+fn decode_token(token: &str) -> Result<Claims, ()> {
     let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
     let decoding_key = DecodingKey::from_secret(secret.as_bytes());
 
     let token_data = decode::<Claims>(token, &decoding_key, &Validation::default())
         .map_err(|_| ())?;
 
-    Ok(token_data.claims.sub)
+    Ok(token_data.claims)
 }
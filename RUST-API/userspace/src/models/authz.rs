@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::utils::scope::Resource;
+
+/// "Can this user perform this action", optionally inside an organization
+/// and on a specific resource
+#[derive(Debug, Clone, Deserialize, Serialize, Validate)]
+pub struct AuthzCheck {
+    pub user_id: Uuid,
+    /// Permission name, e.g. `posts.delete`
+    #[validate(length(min = 1, max = 50))]
+    pub action: String,
+    #[serde(default)]
+    pub organization_id: Option<Uuid>,
+    /// Without one, only grants that aren't scoped to a resource count
+    #[serde(default)]
+    pub resource: Option<Resource>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct AuthzBatchCheck {
+    #[validate(length(min = 1, max = 100))]
+    pub checks: Vec<AuthzCheck>,
+}
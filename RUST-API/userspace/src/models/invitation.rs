@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use validator::Validate;
+use chrono::{DateTime, Utc};
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub organization_id: Option<Uuid>,
+    pub email: Option<String>,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub invited_by: Option<Uuid>,
+    pub max_uses: i32,
+    pub use_count: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+fn default_max_uses() -> i32 {
+    1
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct CreateInvitation {
+    /// Only this address can redeem the invitation; it's also mailed there
+    #[validate(email)]
+    pub email: Option<String>,
+    /// Roles granted on redemption
+    #[serde(default)]
+    pub role_ids: Vec<Uuid>,
+    #[serde(default = "default_max_uses")]
+    #[validate(range(min = 1, max = 10000))]
+    pub max_uses: i32,
+    /// Defaults to `INVITATION_TTL_DAYS` from now
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct AcceptInvitation {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RevokeInvitation {
+    pub id: Uuid,
+}
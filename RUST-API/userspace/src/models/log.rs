@@ -5,6 +5,8 @@ use chrono::{DateTime, Utc};
 use validator::Validate;
 use rocket::serde::json::Value;
 
+use crate::middleware::request_context::RequestContext;
+
 #[derive(Debug, Serialize, Deserialize, FromRow, Validate)]
 pub struct Log {
     pub id: Uuid,
@@ -22,6 +24,10 @@ pub struct CreateLog {
     pub action: String,
 
     pub details: Value,
+
+    /// Stored in the log's own columns, not in `details`
+    #[serde(skip)]
+    pub context: Option<RequestContext>,
 }
 
 // ToDo: Make logging more "vocal"
\ No newline at end of file
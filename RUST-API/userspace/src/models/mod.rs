@@ -4,4 +4,10 @@ pub mod log;
 pub mod role_permissions;
 pub mod user_roles;
 pub mod permission;
-pub mod user_profile;
\ No newline at end of file
+pub mod user_profile;
+pub mod session;
+pub mod two_factor;
+pub mod webauthn;
+pub mod organization;
+pub mod invitation;
+pub mod authz;
\ No newline at end of file
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+use chrono::{DateTime, Utc};
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    pub slug: String,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct OrganizationMember {
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Slugs end up in URLs, so keep them to lowercase letters, digits and dashes
+fn validate_slug(slug: &str) -> Result<(), ValidationError> {
+    let valid = slug
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("slug"))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct CreateOrganization {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[validate(length(min = 3, max = 50), custom = "validate_slug")]
+    pub slug: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct UpdateOrganization {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct AssignOrganizationRole {
+    pub role_id: Uuid,
+}
\ No newline at end of file
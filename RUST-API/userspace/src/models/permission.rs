@@ -4,17 +4,33 @@ use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use validator::Validate;
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Permission {
     pub id: Uuid,
     pub name: String,
+    pub description: Option<String>,
     pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreatePermission {
     #[validate(length(min = 3, max = 50))]
     pub name: String,
+    #[serde(default)]
+    #[validate(length(max = 500))]
+    pub description: Option<String>,
+}
+
+/// Replaces the name and description, leaving `description` out clears it
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdatePermission {
+    #[validate(length(min = 3, max = 50))]
+    pub name: String,
+    #[serde(default)]
+    #[validate(length(max = 500))]
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
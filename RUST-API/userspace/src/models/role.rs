@@ -4,19 +4,55 @@ use uuid::Uuid;
 use validator::Validate;
 use chrono::{DateTime, Utc};
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Role {
     pub id: Uuid,
     pub name: String,
+    pub parent_id: Option<Uuid>,
+    pub description: Option<String>,
+    /// Whether organization admins may give the role to their members
+    pub organization_assignable: bool,
     pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Validate)]
 pub struct CreateRole {
     pub name: String,
+    #[serde(default)]
+    #[validate(length(max = 500))]
+    pub description: Option<String>,
+    /// Whether organization admins may give the role to their members
+    #[serde(default)]
+    pub organization_assignable: bool,
+}
+
+/// Replaces the name, description and organization flag, leaving
+/// `description` out clears it
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct UpdateRole {
+    #[validate(length(min = 1, max = 50))]
+    pub name: String,
+    #[serde(default)]
+    #[validate(length(max = 500))]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub organization_assignable: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DeleteRole {
     pub id: Uuid,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SetRoleParent {
+    pub role_id: Uuid,
+    pub parent_id: Uuid,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ClearRoleParent {
+    pub role_id: Uuid,
 }
\ No newline at end of file
@@ -2,17 +2,60 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct RolePermission {
     pub role_id: Uuid,
     pub permission_id: Uuid,
+    pub resource_type: String,
+    pub resource_id: String,
     pub created_at: DateTime<Utc>,
 }
 
+/// Attaches a permission to a role, or detaches the grant with the same scope
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct AssignPermission {
     pub role_id: Uuid,
     pub permission_id: Uuid,
+    /// Resource type the grant is limited to, `*` (the default) for any
+    #[serde(default = "wildcard")]
+    #[validate(length(min = 1, max = 50), custom = "validate_resource_type")]
+    pub resource_type: String,
+    /// Resource id or path the grant is limited to, `*` (the default) for any
+    /// and `parent/*` for everything below `parent`
+    #[serde(default = "wildcard")]
+    #[validate(length(min = 1, max = 255), custom = "validate_resource_id")]
+    pub resource_id: String,
+}
+
+fn wildcard() -> String {
+    "*".to_string()
+}
+
+pub fn validate_resource_type(resource_type: &str) -> Result<(), ValidationError> {
+    let valid = resource_type == "*"
+        || resource_type
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("resource_type"))
+    }
+}
+
+/// `*` may only stand alone or as the last path segment
+pub fn validate_resource_id(resource_id: &str) -> Result<(), ValidationError> {
+    let body = resource_id.strip_suffix("/*").unwrap_or(resource_id);
+    let valid = resource_id == "*"
+        || (!body.is_empty() && !body.contains('*') && !body.contains(':'));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("resource_id"))
+    }
 }
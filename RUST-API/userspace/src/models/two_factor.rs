@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use validator::Validate;
+
+#[allow(dead_code)]
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct UserTotp {
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct TwoFactorCode {
+    #[validate(length(min = 6, max = 6))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct VerifyTwoFactorRequest {
+    pub challenge_token: String,
+    /// Code from the authenticator app
+    #[validate(length(min = 6, max = 6))]
+    pub code: Option<String>,
+    /// One of the recovery codes handed out at enrollment, used instead of `code`
+    pub recovery_code: Option<String>,
+    /// Return the tokens in the response body instead of setting cookies
+    #[serde(default)]
+    pub token_in_body: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResetTwoFactor {
+    pub id: Uuid,
+}
@@ -14,6 +14,7 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
+    pub email_verified_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Validate)]
@@ -24,6 +25,12 @@ pub struct RegisterRequest {
     pub username: String,
     #[validate(length(min = 8))]
     pub password: String,
+    /// Invitation code, required when `REGISTRATION_MODE=invite_only`
+    #[serde(default)]
+    pub invite_code: Option<String>,
+    /// Return the tokens in the response body instead of setting cookies
+    #[serde(default)]
+    pub token_in_body: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Validate)]
@@ -32,6 +39,9 @@ pub struct LoginRequest {
     pub email: String,
     #[validate(length(min = 8))]
     pub password: String,
+    /// Return the tokens in the response body instead of setting cookies
+    #[serde(default)]
+    pub token_in_body: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Validate)]
@@ -39,10 +49,49 @@ pub struct DeleteUser {
     pub id: Uuid
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UnlockUser {
+    pub id: Uuid,
+}
+
 #[derive(Debug, Deserialize, Serialize, Validate)]
 pub struct UpdateUser {
     #[validate(email)]
     pub email: Option<String>,
     #[validate(length(min = 3, max = 30))]
     pub username: Option<String>
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct ResendVerificationRequest {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct ForgotPasswordRequest {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    #[validate(length(min = 8))]
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    #[validate(length(min = 8))]
+    pub new_password: String,
+    /// Revoke every session except the one making this request
+    #[serde(default)]
+    pub sign_out_other_sessions: bool,
+}
@@ -5,6 +5,7 @@ use uuid::Uuid;
 use validator::Validate;
 use rocket::serde::json::Value;
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct UserProfile {
     pub id: Uuid,
@@ -19,6 +20,7 @@ pub struct UserProfile {
     pub updated_at: DateTime<Utc>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize, Serialize, Validate)]
 pub struct CreateUserProfile {
     pub user_id: Uuid,
@@ -46,6 +48,7 @@ pub struct UpdateUserProfile {
     pub social_links: Option<Value>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DeleteUserProfile {
     pub id: Uuid,
@@ -4,10 +4,13 @@ use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use validator::Validate;
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct UserRole {
     pub user_id: Uuid,
     pub role_id: Uuid,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -15,4 +18,10 @@ pub struct UserRole {
 pub struct AssignRole {
     pub user_id: Uuid,
     pub role_id: Uuid,
+    /// The grant only takes effect from this moment, immediately when missing
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+    /// The grant stops counting at this moment and is swept afterwards
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
 }
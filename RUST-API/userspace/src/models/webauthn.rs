@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use validator::Validate;
+use webauthn_rs::prelude::{PublicKeyCredential, RegisterPublicKeyCredential};
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct WebauthnCredential {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct FinishPasskeyRegistration {
+    pub ceremony_id: Uuid,
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    pub credential: RegisterPublicKeyCredential,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct StartPasskeyLogin {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FinishPasskeyLogin {
+    pub ceremony_id: Uuid,
+    pub credential: PublicKeyCredential,
+    /// Return the tokens in the response body instead of setting cookies
+    #[serde(default)]
+    pub token_in_body: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct RenamePasskey {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+}
@@ -0,0 +1,159 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use validator::Validate;
+
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::require_permission::{InvitationsCreate, InvitationsRead, InvitationsRevoke, Require};
+use crate::models::invitation::{CreateInvitation, RevokeInvitation};
+use crate::utils::api_error::ApiError;
+use crate::utils::auth::{has_permission, holds_role_grants};
+use crate::utils::invitations::{create_invitation as store_invitation, invitation_email};
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::mailer::Mailer;
+
+#[post("/invitation", format = "json", data = "<invitation_data>")]
+pub async fn create_invitation(
+    pool: &State<PgPool>,
+    mailer: &State<Mailer>,
+    admin_user: Require<InvitationsCreate>,
+    invitation_data: Json<CreateInvitation>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let invitation = invitation_data.into_inner();
+
+    invitation.validate()?;
+
+    // Pre-assigning roles is assigning roles, so it needs that permission too
+    if !invitation.role_ids.is_empty()
+        && !has_permission(pool.inner(), admin_user.user_id, "users.roles.assign").await?
+    {
+        return Err(ApiError::forbidden("PERMISSION_DENIED", "Giving invitations roles needs the users.roles.assign permission."));
+    }
+
+    // Same rule as assigning the roles directly
+    for role_id in &invitation.role_ids {
+        if !holds_role_grants(pool.inner(), admin_user.user_id, *role_id).await? {
+            return Err(ApiError::forbidden("ROLE_NOT_ASSIGNABLE", "You can only assign roles whose permissions you hold."));
+        }
+    }
+
+    let created = store_invitation(pool.inner(), None, &invitation, admin_user.user_id).await?;
+
+    let sent = match &invitation.email {
+        Some(email) => mailer
+            .send(&invitation_email(email, None, &created.link, created.expires_at))
+            .await
+            .is_ok(),
+        None => false,
+    };
+
+    let log = LogBuilder::new(LogAction::Create, "invitation", &context)
+        .with_user(admin_user.user_id)
+        .with_resource_id(created.id.to_string())
+        .with_new_state(&json!({
+            "email": invitation.email,
+            "role_ids": invitation.role_ids,
+            "max_uses": invitation.max_uses,
+            "expires_at": created.expires_at
+        }))?
+        .with_additional_details(&json!({
+            "delivered": sent
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Invitation created!",
+        "id": created.id,
+        "code": created.code,
+        "link": created.link,
+        "email": invitation.email,
+        "role_ids": invitation.role_ids,
+        "max_uses": invitation.max_uses,
+        "expires_at": created.expires_at,
+        "created_at": created.created_at,
+        "email_sent": sent
+    })))
+}
+
+#[get("/invitations")]
+pub async fn get_invitations(
+    pool: &State<PgPool>,
+    _admin_user: Require<InvitationsRead>,
+) -> Result<Json<Value>, ApiError> {
+    let invitations = sqlx::query!(
+        r#"
+        SELECT i.id, i.email, i.invited_by, i.max_uses, i.use_count, i.expires_at, i.created_at,
+               ARRAY_AGG(r.name) FILTER (WHERE r.name IS NOT NULL) AS roles
+        FROM invitations i
+        LEFT JOIN invitation_roles ir ON i.id = ir.invitation_id
+        LEFT JOIN roles r ON ir.role_id = r.id
+        WHERE i.organization_id IS NULL
+        GROUP BY i.id
+        ORDER BY i.created_at DESC
+        "#
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let now = chrono::Utc::now();
+
+    let invitations_json: Vec<Value> = invitations.iter().map(|invitation| {
+        let expired = invitation.expires_at.is_some_and(|expires_at| expires_at <= now);
+
+        json!({
+            "id": invitation.id,
+            "email": invitation.email,
+            "roles": invitation.roles.clone().unwrap_or_default(),
+            "invited_by": invitation.invited_by,
+            "max_uses": invitation.max_uses,
+            "use_count": invitation.use_count,
+            "usable": !expired && invitation.use_count < invitation.max_uses,
+            "expires_at": invitation.expires_at,
+            "created_at": invitation.created_at,
+        })
+    }).collect();
+
+    Ok(Json(json!({
+        "message": "Found invitations!",
+        "invitations": invitations_json
+    })))
+}
+
+#[delete("/invitation", format = "json", data = "<invitation_data>")]
+pub async fn revoke_invitation(
+    pool: &State<PgPool>,
+    admin_user: Require<InvitationsRevoke>,
+    invitation_data: Json<RevokeInvitation>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let invitation = sqlx::query!(
+        r#"
+        DELETE FROM invitations
+        WHERE id = $1 AND organization_id IS NULL
+        RETURNING email, max_uses, use_count, expires_at
+        "#,
+        invitation_data.id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("INVITATION_NOT_FOUND", "No pending invitation with that id."))?;
+
+    let log = LogBuilder::new(LogAction::Delete, "invitation", &context)
+        .with_user(admin_user.user_id)
+        .with_resource_id(invitation_data.id.to_string())
+        .with_previous_state(&json!({
+            "email": invitation.email,
+            "max_uses": invitation.max_uses,
+            "use_count": invitation.use_count,
+            "expires_at": invitation.expires_at
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({ "message": "Invitation revoked" })))
+}
@@ -0,0 +1,32 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+
+use crate::middleware::require_permission::{Require, MigrationsRead};
+use crate::utils::api_error::ApiError;
+use crate::utils::migrations::{status, MigrationState};
+
+#[get("/migrations")]
+pub async fn get_migrations(
+    pool: &State<PgPool>,
+    _admin_user: Require<MigrationsRead>
+) -> Result<Json<Value>, ApiError> {
+    let migrations = status(pool.inner())
+        .await?;
+
+    let pending = migrations
+        .iter()
+        .filter(|migration| migration.state == MigrationState::Pending)
+        .count();
+    let up_to_date = migrations
+        .iter()
+        .all(|migration| migration.state == MigrationState::Applied);
+
+    Ok(Json(json!({
+        "message": "Found migrations!",
+        "up_to_date": up_to_date,
+        "pending": pending,
+        "migrations": migrations
+    })))
+}
@@ -2,6 +2,8 @@ use rocket::Route;
 pub mod permissions;
 pub mod roles;
 pub mod users;
+pub mod invitations;
+pub mod migrations;
 
 #[options("/<_..>")]
 fn all_options() {
@@ -13,14 +15,28 @@ pub fn routes() -> Vec<Route> {
         roles::make_role,
         roles::delete_role,
         roles::get_all_roles,
+        roles::get_role,
+        roles::update_role,
         roles::assign_permission_to_role,
+        roles::remove_permission_from_role,
+        roles::set_role_parent,
+        roles::clear_role_parent,
+        roles::get_role_permissions,
         permissions::make_permission,
         permissions::delete_permission,
         permissions::get_all_permissions,
+        permissions::get_permission,
+        permissions::update_permission,
         users::assign_role_to_user,
         users::get_all_users,
         users::delete_user,
         users::remove_user_from_role,
+        users::reset_user_two_factor,
+        users::unlock_user,
+        invitations::create_invitation,
+        invitations::get_invitations,
+        invitations::revoke_invitation,
+        migrations::get_migrations,
         all_options
     ]
 }
\ No newline at end of file
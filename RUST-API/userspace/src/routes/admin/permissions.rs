@@ -1,88 +1,192 @@
 use rocket::serde::json::{Json, json};
 use rocket::State;
-use rocket::http::Status;
 use rocket::serde::json::Value;
 use sqlx::PgPool;
+use uuid::Uuid;
 use validator::Validate;
 
-use crate::models::log::CreateLog;
+use crate::middleware::request_context::RequestContext;
 use crate::models::permission::CreatePermission;
 use crate::models::permission::DeletePermission;
-use crate::middleware::verify_jwt::AuthenticatedUser;
-use crate::utils::auth::is_admin;
+use crate::models::permission::UpdatePermission;
+use crate::middleware::require_permission::{
+    Require, PermissionsCreate, PermissionsDelete, PermissionsRead, PermissionsUpdate,
+};
+use crate::utils::api_error::ApiError;
+use crate::utils::authz::AuthzCache;
 use crate::utils::logger::{log_action, LogAction, LogBuilder};
 
+fn permission_not_found() -> ApiError {
+    ApiError::not_found("PERMISSION_NOT_FOUND", "No permission with that id.")
+}
+
 #[post("/permission", format = "json", data = "<permission_data>")]
 pub async fn make_permission(
     pool: &State<PgPool>,
-    user: AuthenticatedUser,
+    user: Require<PermissionsCreate>,
     permission_data: Json<CreatePermission>,
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), user.user_id).await? {
-        return Err(Status::Forbidden); // Block access if not Admin
-    }
-
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
     let permission = permission_data.into_inner();
 
-    if let Err(_) = permission.validate() {
-        return Err(Status::BadRequest);
-    }
+    permission.validate()?;
 
     let result = sqlx::query!(
         r#"
-        INSERT INTO roles (name)
-        VALUES ($1)
-        RETURNING id, name, created_at
+        INSERT INTO permissions (name, description)
+        VALUES ($1, $2)
+        RETURNING id, name, description, created_at
         "#,
         permission.name,
+        permission.description,
     )
     .fetch_one(pool.inner())
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::Database(err) if err.is_unique_violation() => Status::Conflict,
-        _ => Status::InternalServerError,
-    })?;
+    .await?;
 
     // Log successful
-    let log = CreateLog {
-        user_id: Some(user.user_id),
-        action: "permission_created_successfully".to_string(),
-        details: json!({
+    let log = LogBuilder::new(LogAction::Custom("created_successfully".to_string()), "permission", &context)
+        .with_user(user.user_id)
+        .with_additional_details(&json!({
             "permission_id": result.id,
-        }),
-    };
+        }))?
+        .build();
     let _ = log_action(pool.inner(), &log).await;
 
     Ok(Json(json!({
         "id": result.id,
         "name": result.name,
+        "description": result.description,
         "created_at": result.created_at
     })))
 }
 
+#[get("/permission/<id>")]
+pub async fn get_permission(
+    pool: &State<PgPool>,
+    _admin_user: Require<PermissionsRead>,
+    id: Uuid,
+) -> Result<Json<Value>, ApiError> {
+    let permission = sqlx::query!(
+        r#"
+        SELECT id, name, description, created_at, updated_at
+        FROM permissions
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(permission_not_found)?;
+
+    let roles = sqlx::query!(
+        r#"
+        SELECT r.id, r.name, rp.resource_type, rp.resource_id
+        FROM role_permissions rp
+        JOIN roles r ON rp.role_id = r.id
+        WHERE rp.permission_id = $1
+        ORDER BY r.name, rp.resource_type, rp.resource_id
+        "#,
+        id
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let roles_json: Vec<Value> = roles.iter().map(|role| {
+        json!({
+            "id": role.id,
+            "name": role.name,
+            "resource_type": role.resource_type,
+            "resource_id": role.resource_id,
+        })
+    }).collect();
+
+    Ok(Json(json!({
+        "id": permission.id,
+        "name": permission.name,
+        "description": permission.description,
+        "created_at": permission.created_at,
+        "updated_at": permission.updated_at,
+        "role_count": roles_json.len(),
+        "roles": roles_json
+    })))
+}
+
+#[put("/permission/<id>", format = "json", data = "<permission_data>")]
+pub async fn update_permission(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    admin_user: Require<PermissionsUpdate>,
+    id: Uuid,
+    permission_data: Json<UpdatePermission>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let permission = permission_data.into_inner();
+
+    permission.validate()?;
+
+    // Routes check permissions by name, so renaming one the API itself uses
+    // takes that access away until it's renamed back
+    let result = sqlx::query!(
+        r#"
+        UPDATE permissions p
+        SET name = $1, description = $2, updated_at = CURRENT_TIMESTAMP
+        FROM permissions old
+        WHERE p.id = old.id AND p.id = $3
+        RETURNING p.id, p.name, p.description, p.updated_at,
+                  old.name AS previous_name, old.description AS previous_description
+        "#,
+        permission.name,
+        permission.description,
+        id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(permission_not_found)?;
+
+    cache.clear();
+
+    let log = LogBuilder::new(LogAction::Update, "permission", &context)
+        .with_user(admin_user.user_id)
+        .with_resource_id(id.to_string())
+        .with_previous_state(&json!({
+            "name": result.previous_name,
+            "description": result.previous_description
+        }))?
+        .with_new_state(&json!({
+            "name": result.name,
+            "description": result.description
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "id": result.id,
+        "name": result.name,
+        "description": result.description,
+        "updated_at": result.updated_at
+    })))
+}
+
 #[get("/permissions")]
 pub async fn get_all_permissions(
     pool: &State<PgPool>,
-    admin_user: AuthenticatedUser
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), admin_user.user_id).await.map_err(|_| Status::InternalServerError)? {
-        return Err(Status::Forbidden);
-    }
-
+    _admin_user: Require<PermissionsRead>
+) -> Result<Json<Value>, ApiError> {
     let permissions = sqlx::query!(
         r#"
-        SELECT id, name FROM permissions
+        SELECT id, name, description FROM permissions
         "#
     )
     .fetch_all(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
     // Convert the query result into JSON
     let permissions_json: Vec<Value> = permissions.iter().map(|permission| {
         json!({
             "id": permission.id,
             "name": permission.name,
+            "description": permission.description,
         })
     }).collect();
 
@@ -95,13 +199,11 @@ pub async fn get_all_permissions(
 #[delete("/permission", format = "json", data = "<permission_data>")]
 pub async fn delete_permission(
     pool: &State<PgPool>,
-    user: AuthenticatedUser,
+    cache: &State<AuthzCache>,
+    user: Require<PermissionsDelete>,
     permission_data: Json<DeletePermission>,
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), user.user_id).await? {
-        return Err(Status::Forbidden);
-    }
-
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
     // Fetch permission data and associations before deletion
     let permission_info = sqlx::query!(
         r#"
@@ -117,14 +219,12 @@ pub async fn delete_permission(
         permission_data.id
     )
     .fetch_optional(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?
-    .ok_or(Status::NotFound)?;
+    .await?
+    .ok_or_else(permission_not_found)?;
 
     let mut tx = pool.inner()
         .begin()
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+        .await?;
 
     // Delete from role_permissions first
     let role_permissions_count = sqlx::query!(
@@ -135,8 +235,7 @@ pub async fn delete_permission(
         permission_data.id,
     )
     .execute(&mut *tx)
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
     // Delete the permission itself
     let permission_result = sqlx::query!(
@@ -148,12 +247,13 @@ pub async fn delete_permission(
         permission_data.id,
     )
     .fetch_optional(&mut *tx)
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
+
+    let permission = permission_result.ok_or_else(permission_not_found)?;
 
-    let permission = permission_result.ok_or(Status::NotFound)?;
+    cache.clear();
 
-    let log = LogBuilder::new(LogAction::Delete, "permission")
+    let log = LogBuilder::new(LogAction::Delete, "permission", &context)
         .with_user(user.user_id)
         .with_resource_id(permission_data.id.to_string())
         .with_previous_state(&json!({
@@ -163,23 +263,20 @@ pub async fn delete_permission(
                 "roles_count": permission_info.role_count,
                 "associated_roles": permission_info.role_names,
             }
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_additional_details(&json!({
             "deleted_by": user.user_id,
             "deletion_timestamp": chrono::Utc::now().to_rfc3339(),
             "cascade_deletions": {
                 "role_permissions_removed": role_permissions_count.rows_affected()
             }
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .build();
 
     let _ = log_action(pool.inner(), &log).await;
 
     tx.commit()
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+        .await?;
 
     Ok(Json(json!({
         "id": permission.id,
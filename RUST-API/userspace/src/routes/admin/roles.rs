@@ -1,77 +1,202 @@
 use rocket::serde::json::{Json, json};
 use rocket::State;
-use rocket::http::Status;
 use rocket::serde::json::Value;
 use sqlx::PgPool;
+use uuid::Uuid;
 use validator::Validate;
 
-use crate::models::log::CreateLog;
+use crate::middleware::request_context::RequestContext;
 use crate::models::role::CreateRole;
 use crate::models::role::DeleteRole;
+use crate::models::role::UpdateRole;
+use crate::models::role::{ClearRoleParent, SetRoleParent};
 use crate::models::role_permissions::AssignPermission;
-use crate::utils::auth::is_admin;
-use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::middleware::require_permission::{
+    Require, RolesCreate, RolesDelete, RolesGrant, RolesRead, RolesRevoke, RolesUpdate,
+};
+use crate::utils::api_error::ApiError;
+use crate::utils::authz::AuthzCache;
+use crate::utils::auth::{covers_all, creates_role_cycle, effective_grants, holds_role_grants};
 use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::scope::format_grant;
+
+fn role_not_found() -> ApiError {
+    ApiError::not_found("ROLE_NOT_FOUND", "No role with that id.")
+}
+
+fn exceeds_permissions() -> ApiError {
+    ApiError::forbidden("ROLE_EXCEEDS_PERMISSIONS", "You can only give roles permissions you hold.")
+}
 
 #[post("/role", format = "json", data = "<role_data>")]
 pub async fn make_role(
     pool: &State<PgPool>,
-    user: AuthenticatedUser,
+    user: Require<RolesCreate>,
     role_data: Json<CreateRole>,
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), user.user_id).await? {
-        return Err(Status::Forbidden); // Block access if not Admin
-    }
-
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
     let role = role_data.into_inner();
 
-    if let Err(_) = role.validate() {
-        return Err(Status::BadRequest);
-    }
+    role.validate()?;
 
     let result = sqlx::query!(
         r#"
-        INSERT INTO roles (name)
-        VALUES ($1)
-        RETURNING id, name, created_at
+        INSERT INTO roles (name, description, organization_assignable)
+        VALUES ($1, $2, $3)
+        RETURNING id, name, description, organization_assignable, created_at
         "#,
         role.name,
+        role.description,
+        role.organization_assignable,
     )
     .fetch_one(pool.inner())
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::Database(err) if err.is_unique_violation() => Status::Conflict,
-        _ => Status::InternalServerError,
-    })?;
+    .await?;
 
     // Log successful
-    let log = CreateLog {
-        user_id: Some(user.user_id),
-        action: "role_created_successfully".to_string(),
-        details: json!({
+    let log = LogBuilder::new(LogAction::Custom("created_successfully".to_string()), "role", &context)
+        .with_user(user.user_id)
+        .with_additional_details(&json!({
             "role_id": result.id,
-        }),
-    };
+        }))?
+        .build();
     let _ = log_action(pool.inner(), &log).await;
 
     Ok(Json(json!({
         "id": result.id,
         "name": result.name,
+        "description": result.description,
+        "organization_assignable": result.organization_assignable,
         "created_at": result.created_at
     })))
 }
 
 
+#[get("/role/<id>")]
+pub async fn get_role(
+    pool: &State<PgPool>,
+    _admin_user: Require<RolesRead>,
+    id: Uuid,
+) -> Result<Json<Value>, ApiError> {
+    let role = sqlx::query!(
+        r#"
+        SELECT r.id, r.name, r.description, r.parent_id, r.organization_assignable, r.created_at, r.updated_at,
+               (SELECT COUNT(DISTINCT ur.user_id) FROM user_roles ur WHERE ur.role_id = r.id) AS member_count,
+               (SELECT COUNT(*) FROM organization_member_roles omr WHERE omr.role_id = r.id) AS organization_member_count
+        FROM roles r
+        WHERE r.id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(role_not_found)?;
+
+    // Only what's attached to the role itself, inherited grants are under /permissions
+    let permissions = sqlx::query!(
+        r#"
+        SELECT p.id, p.name, rp.resource_type, rp.resource_id
+        FROM role_permissions rp
+        JOIN permissions p ON rp.permission_id = p.id
+        WHERE rp.role_id = $1
+        ORDER BY p.name, rp.resource_type, rp.resource_id
+        "#,
+        id
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let permissions_json: Vec<Value> = permissions.iter().map(|permission| {
+        json!({
+            "id": permission.id,
+            "name": permission.name,
+            "resource_type": permission.resource_type,
+            "resource_id": permission.resource_id,
+            "grant": format_grant(&permission.resource_type, &permission.resource_id, &permission.name),
+        })
+    }).collect();
+
+    Ok(Json(json!({
+        "id": role.id,
+        "name": role.name,
+        "description": role.description,
+        "parent_id": role.parent_id,
+        "organization_assignable": role.organization_assignable,
+        "created_at": role.created_at,
+        "updated_at": role.updated_at,
+        "member_count": role.member_count,
+        "organization_member_count": role.organization_member_count,
+        "permissions": permissions_json
+    })))
+}
+
+#[put("/role/<id>", format = "json", data = "<role_data>")]
+pub async fn update_role(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    admin_user: Require<RolesUpdate>,
+    id: Uuid,
+    role_data: Json<UpdateRole>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let role = role_data.into_inner();
+
+    role.validate()?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE roles r
+        SET name = $1, description = $2, organization_assignable = $3, updated_at = CURRENT_TIMESTAMP
+        FROM roles old
+        WHERE r.id = old.id AND r.id = $4
+        RETURNING r.id, r.name, r.description, r.organization_assignable, r.updated_at,
+                  old.name AS previous_name, old.description AS previous_description,
+                  old.organization_assignable AS previous_organization_assignable
+        "#,
+        role.name,
+        role.description,
+        role.organization_assignable,
+        id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(role_not_found)?;
+
+    cache.clear();
+
+    let log = LogBuilder::new(LogAction::Update, "role", &context)
+        .with_user(admin_user.user_id)
+        .with_resource_id(id.to_string())
+        .with_previous_state(&json!({
+            "name": result.previous_name,
+            "description": result.previous_description,
+            "organization_assignable": result.previous_organization_assignable
+        }))?
+        .with_new_state(&json!({
+            "name": result.name,
+            "description": result.description,
+            "organization_assignable": result.organization_assignable
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "id": result.id,
+        "name": result.name,
+        "description": result.description,
+        "organization_assignable": result.organization_assignable,
+        "updated_at": result.updated_at
+    })))
+}
+
 #[delete("/role", format = "json", data = "<role_data>")]
 pub async fn delete_role(
     pool: &State<PgPool>,
-    user: AuthenticatedUser,
+    cache: &State<AuthzCache>,
+    user: Require<RolesDelete>,
     role_data: Json<DeleteRole>,
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), user.user_id).await? {
-        return Err(Status::Forbidden);
-    }
-
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
     let role_info = sqlx::query!(
         r#"
         SELECT r.name, r.created_at,
@@ -86,14 +211,12 @@ pub async fn delete_role(
         role_data.id
     )
     .fetch_optional(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?
-    .ok_or(Status::NotFound)?;
+    .await?
+    .ok_or_else(role_not_found)?;
 
     let mut tx = pool.inner()
         .begin()
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+        .await?;
 
     // Delete from role_permissions and get count
     let role_permissions_count = sqlx::query!(
@@ -104,8 +227,7 @@ pub async fn delete_role(
         role_data.id,
     )
     .execute(&mut *tx)
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
     // Delete from user_roles and get count
     let user_roles_count = sqlx::query!(
@@ -116,8 +238,7 @@ pub async fn delete_role(
         role_data.id,
     )
     .execute(&mut *tx)
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
     // Delete the role itself
     let role_result = sqlx::query!(
@@ -129,17 +250,17 @@ pub async fn delete_role(
         role_data.id,
     )
     .fetch_optional(&mut *tx)
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
-    let role = role_result.ok_or(Status::NotFound)?;
+    let role = role_result.ok_or_else(role_not_found)?;
 
     tx.commit()
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+        .await?;
+
+    cache.clear();
 
     // Log successful
-    let log = LogBuilder::new(LogAction::Delete, "role")
+    let log = LogBuilder::new(LogAction::Delete, "role", &context)
         .with_user(user.user_id)
         .with_resource_id(role_data.id.to_string())
         .with_previous_state(&json!({
@@ -149,13 +270,11 @@ pub async fn delete_role(
                 "permissions_count": role_info.permission_count,
                 "users_count": role_info.user_count
             }
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_additional_details(&json!({
             "deleted_by": user.user_id,
             "deletion_timestamp": chrono::Utc::now().to_rfc3339(),
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .build();
 
     let _ = log_action(pool.inner(), &log).await;
@@ -174,72 +293,135 @@ pub async fn delete_role(
 #[post("/permission/role", format="json", data="<permission_data>")]
 pub async fn assign_permission_to_role(
     pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
     permission_data: Json<AssignPermission>,
-    admin_user: AuthenticatedUser
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), admin_user.user_id).await.map_err(|_| Status::InternalServerError)? {
-        return Err(Status::Forbidden);
-    }
-
+    admin_user: Require<RolesGrant>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
     let permission = permission_data.into_inner();
 
     // Validate input
-    if let Err(_) = permission.validate() {
-        return Err(Status::BadRequest);
+    permission.validate()?;
+
+    let permission_name = sqlx::query_scalar!("SELECT name FROM permissions WHERE id = $1", permission.permission_id)
+        .fetch_optional(pool.inner())
+        .await?
+        .ok_or_else(|| ApiError::not_found("ROLE_OR_PERMISSION_NOT_FOUND", "The role or permission doesn't exist."))?;
+
+    // Nobody hands out a grant they don't hold themselves
+    let held = effective_grants(pool.inner(), admin_user.user_id, None).await?;
+    if !covers_all(&held, &[(permission_name, permission.resource_type.clone(), permission.resource_id.clone())]) {
+        return Err(exceeds_permissions());
     }
 
-    let result = sqlx::query!(
+    sqlx::query!(
         r#"
-        INSERT INTO role_permissions (role_id, permission_id)
-        VALUES ($1, $2)
-        "#, permission.role_id, permission.permission_id
+        INSERT INTO role_permissions (role_id, permission_id, resource_type, resource_id)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        permission.role_id,
+        permission.permission_id,
+        permission.resource_type,
+        permission.resource_id
     )
     .execute(pool.inner())
-    .await;
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(err) if err.is_unique_violation() => {
+            ApiError::conflict("GRANT_EXISTS", "The role already has this permission with this scope.")
+        }
+        sqlx::Error::Database(err) if err.is_foreign_key_violation() => {
+            ApiError::not_found("ROLE_OR_PERMISSION_NOT_FOUND", "The role or permission doesn't exist.")
+        }
+        e => e.into(),
+    })?;
 
-    if result.is_err() {
-        return Err(Status::InternalServerError);
-    }
+    cache.clear();
 
     // Log successful
-    let log = CreateLog {
-        user_id: Some(admin_user.user_id),
-        action: "permission_added_to_role_successfully".to_string(),
-        details: json!({
+    let log = LogBuilder::new(LogAction::Custom("added_to_role_successfully".to_string()), "permission", &context)
+        .with_user(admin_user.user_id)
+        .with_additional_details(&json!({
             "role_id": permission.role_id,
             "permission_id": permission.permission_id,
-        }),
-    };
+            "resource_type": permission.resource_type,
+            "resource_id": permission.resource_id,
+        }))?
+        .build();
     let _ = log_action(pool.inner(), &log).await;
 
     Ok(Json(json!({ "message": "Permission successfully assigned to role!" })))
 }
 
+#[delete("/permission/role", format = "json", data = "<permission_data>")]
+pub async fn remove_permission_from_role(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    permission_data: Json<AssignPermission>,
+    admin_user: Require<RolesRevoke>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
+    let permission = permission_data.into_inner();
+
+    permission.validate()?;
+
+    let removed = sqlx::query!(
+        r#"
+        DELETE FROM role_permissions
+        WHERE role_id = $1 AND permission_id = $2 AND resource_type = $3 AND resource_id = $4
+        RETURNING created_at
+        "#,
+        permission.role_id,
+        permission.permission_id,
+        permission.resource_type,
+        permission.resource_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("GRANT_NOT_FOUND", "The role doesn't have this permission with this scope."))?;
+
+    cache.clear();
+
+    let log = LogBuilder::new(LogAction::Delete, "role_permission", &context)
+        .with_user(admin_user.user_id)
+        .with_resource_id(format!("{}:{}", permission.role_id, permission.permission_id))
+        .with_previous_state(&json!({
+            "role_id": permission.role_id,
+            "permission_id": permission.permission_id,
+            "resource_type": permission.resource_type,
+            "resource_id": permission.resource_id,
+            "granted_at": removed.created_at
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({ "message": "Permission successfully removed from role!" })))
+}
+
 
 
 #[get("/roles")]
 pub async fn get_all_roles(
     pool: &State<PgPool>,
-    admin_user: AuthenticatedUser
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), admin_user.user_id).await.map_err(|_| Status::InternalServerError)? {
-        return Err(Status::Forbidden);
-    }
-
+    _admin_user: Require<RolesRead>
+) -> Result<Json<Value>, ApiError> {
     let roles = sqlx::query!(
         r#"
-        SELECT id, name FROM roles
+        SELECT id, name, description, parent_id, organization_assignable FROM roles
         "#
     )
     .fetch_all(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
     // Convert the query result into JSON
     let roles_json: Vec<Value> = roles.iter().map(|role| { // Changed variable name from `roles` to `role`
         json!({
             "id": role.id,
             "name": role.name,
+            "description": role.description,
+            "parent_id": role.parent_id,
+            "organization_assignable": role.organization_assignable,
         })
     }).collect();
 
@@ -247,4 +429,218 @@ pub async fn get_all_roles(
         "message": "Found roles!",
         "roles": roles_json
     })))
-}
\ No newline at end of file
+}
+
+#[put("/role/parent", format = "json", data = "<parent_data>")]
+pub async fn set_role_parent(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    admin_user: Require<RolesGrant>,
+    parent_data: Json<SetRoleParent>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let parent = parent_data.into_inner();
+
+    if parent.role_id == parent.parent_id {
+        return Err(ApiError::bad_request("INVALID_PARENT", "A role can't inherit from itself."));
+    }
+
+    // The role would hand out everything the new parent chain grants
+    if !holds_role_grants(pool.inner(), admin_user.user_id, parent.parent_id).await? {
+        return Err(exceeds_permissions());
+    }
+
+    let mut tx = pool.inner()
+        .begin()
+        .await?;
+
+    // Serialize hierarchy changes so two concurrent updates can't build a loop together
+    sqlx::query!("LOCK TABLE roles IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+
+    let role = sqlx::query!(
+        "SELECT name, parent_id FROM roles WHERE id = $1",
+        parent.role_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(role_not_found)?;
+
+    let parent_role = sqlx::query!(
+        "SELECT name FROM roles WHERE id = $1",
+        parent.parent_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::not_found("PARENT_ROLE_NOT_FOUND", "No role with the parent id."))?;
+
+    if creates_role_cycle(&mut *tx, parent.role_id, parent.parent_id).await? {
+        return Err(ApiError::conflict("ROLE_CYCLE", "The parent already inherits from this role."));
+    }
+
+    sqlx::query!(
+        "UPDATE roles SET parent_id = $1 WHERE id = $2",
+        parent.parent_id,
+        parent.role_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit()
+        .await?;
+
+    cache.clear();
+
+    let log = LogBuilder::new(LogAction::Update, "role", &context)
+        .with_user(admin_user.user_id)
+        .with_resource_id(parent.role_id.to_string())
+        .with_previous_state(&json!({
+            "parent_id": role.parent_id
+        }))?
+        .with_new_state(&json!({
+            "parent_id": parent.parent_id
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": format!("{} now inherits from {}", role.name, parent_role.name),
+        "id": parent.role_id,
+        "parent_id": parent.parent_id
+    })))
+}
+
+#[delete("/role/parent", format = "json", data = "<parent_data>")]
+pub async fn clear_role_parent(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    admin_user: Require<RolesGrant>,
+    parent_data: Json<ClearRoleParent>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let previous = sqlx::query!(
+        r#"
+        UPDATE roles r
+        SET parent_id = NULL
+        FROM roles old
+        WHERE r.id = old.id AND r.id = $1
+        RETURNING old.parent_id
+        "#,
+        parent_data.role_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(role_not_found)?;
+
+    cache.clear();
+
+    let log = LogBuilder::new(LogAction::Update, "role", &context)
+        .with_user(admin_user.user_id)
+        .with_resource_id(parent_data.role_id.to_string())
+        .with_previous_state(&json!({
+            "parent_id": previous.parent_id
+        }))?
+        .with_new_state(&json!({
+            "parent_id": null
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Role no longer inherits permissions",
+        "id": parent_data.role_id,
+        "parent_id": null
+    })))
+}
+
+#[get("/role/<id>/permissions")]
+pub async fn get_role_permissions(
+    pool: &State<PgPool>,
+    _admin_user: Require<RolesRead>,
+    id: Uuid,
+) -> Result<Json<Value>, ApiError> {
+    // Walk up from the role itself; depth 0 is the role, 1 its parent and so on
+    let ancestors = sqlx::query!(
+        r#"
+        WITH RECURSIVE ancestors (id, depth) AS (
+            SELECT id, 0 FROM roles WHERE id = $1
+            UNION ALL
+            SELECT r.parent_id, a.depth + 1
+            FROM roles r
+            JOIN ancestors a ON r.id = a.id
+            WHERE r.parent_id IS NOT NULL AND a.depth < 32
+        )
+        SELECT r.id, r.name, a.depth AS "depth!"
+        FROM ancestors a
+        JOIN roles r ON a.id = r.id
+        ORDER BY a.depth
+        "#,
+        id
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let role = ancestors.first().ok_or_else(role_not_found)?;
+    let ancestor_ids: Vec<Uuid> = ancestors.iter().map(|a| a.id).collect();
+
+    // Report each grant once, credited to the closest role holding it
+    let permissions = sqlx::query!(
+        r#"
+        SELECT p.id, p.name, rp.role_id AS "granted_by!", rp.resource_type, rp.resource_id
+        FROM permissions p
+        JOIN role_permissions rp ON p.id = rp.permission_id
+        WHERE rp.role_id = ANY($1)
+        "#,
+        &ancestor_ids
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let mut permissions_json: Vec<Value> = Vec::new();
+    let mut seen: Vec<String> = Vec::new();
+
+    for ancestor in &ancestors {
+        for permission in permissions.iter().filter(|p| p.granted_by == ancestor.id) {
+            let grant = format_grant(&permission.resource_type, &permission.resource_id, &permission.name);
+            if seen.contains(&grant) {
+                continue;
+            }
+            seen.push(grant.clone());
+
+            permissions_json.push(json!({
+                "id": permission.id,
+                "name": permission.name,
+                "resource_type": permission.resource_type,
+                "resource_id": permission.resource_id,
+                "grant": grant,
+                "inherited": ancestor.depth > 0,
+                "granted_by": {
+                    "id": ancestor.id,
+                    "name": ancestor.name
+                }
+            }));
+        }
+    }
+
+    permissions_json.sort_by(|a, b| a["grant"].as_str().cmp(&b["grant"].as_str()));
+
+    let ancestors_json: Vec<Value> = ancestors.iter().skip(1).map(|ancestor| {
+        json!({
+            "id": ancestor.id,
+            "name": ancestor.name,
+        })
+    }).collect();
+
+    Ok(Json(json!({
+        "message": "Found permissions!",
+        "role": {
+            "id": role.id,
+            "name": role.name
+        },
+        "ancestors": ancestors_json,
+        "permissions": permissions_json
+    })))
+}
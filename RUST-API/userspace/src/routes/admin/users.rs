@@ -1,77 +1,117 @@
 use rocket::serde::json::{Json, json};
 use rocket::State;
-use rocket::http::Status;
 use rocket::serde::json::Value;
 use sqlx::PgPool;
 use validator::Validate;
 
-use crate::middleware::verify_jwt::AuthenticatedUser;
-use crate::models::log::CreateLog;
-use crate::utils::auth::is_admin;
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::require_permission::{Require, UsersDelete, UsersRead, UsersRolesAssign, UsersRolesRevoke, UsersTwoFactorReset, UsersUnlock};
 use crate::models::user_roles::AssignRole;
-use crate::models::user::DeleteUser;
+use crate::models::user::{DeleteUser, UnlockUser};
+use crate::models::two_factor::ResetTwoFactor;
+use crate::utils::api_error::ApiError;
+use crate::utils::authz::AuthzCache;
+use crate::utils::auth::holds_role_grants;
+use crate::utils::login_attempts::{AttemptKey, LoginThrottle};
 use crate::utils::logger::{log_action, LogAction, LogBuilder};
 
+fn user_not_found() -> ApiError {
+    ApiError::not_found("USER_NOT_FOUND", "No user with that id.")
+}
+
 #[post("/role/user", format="json", data="<user_data>")]
 pub async fn assign_role_to_user(
     pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
     user_data: Json<AssignRole>,
-    admin_user: AuthenticatedUser
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), admin_user.user_id).await.map_err(|_| Status::InternalServerError)? {
-        return Err(Status::Forbidden);
-    }
-
+    admin_user: Require<UsersRolesAssign>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
     let user = user_data.into_inner();
 
     // Proper validation error handling
-    if let Err(_) = user.validate() {
-        return Err(Status::BadRequest);
+    user.validate()?;
+
+    // Nobody hands out a role granting more than they hold themselves
+    if !holds_role_grants(pool.inner(), admin_user.user_id, user.role_id).await? {
+        return Err(ApiError::forbidden("ROLE_NOT_ASSIGNABLE", "You can only assign roles whose permissions you hold."));
     }
 
-    let result = sqlx::query!(
+    let now = chrono::Utc::now();
+
+    // An assignment that is already over, or ends before it starts, is a mistake
+    if let Some(expires_at) = user.expires_at {
+        if expires_at <= now || user.starts_at.is_some_and(|starts_at| expires_at <= starts_at) {
+            return Err(ApiError::bad_request("INVALID_ASSIGNMENT_WINDOW", "expires_at must be in the future and after starts_at."));
+        }
+    }
+
+    // Assigning a role the user already holds sets its window instead. That
+    // covers expired assignments the sweeper hasn't removed yet, and turning a
+    // permanent assignment into a time-bound one or back
+    let assignment = sqlx::query!(
         r#"
-        INSERT INTO user_roles (user_id, role_id)
-        VALUES ($1, $2)
-        "#, user.user_id, user.role_id
+        WITH previous AS (
+            SELECT starts_at, expires_at
+            FROM user_roles
+            WHERE user_id = $1 AND role_id = $2
+        )
+        INSERT INTO user_roles (user_id, role_id, starts_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id, role_id) DO UPDATE
+        SET starts_at = EXCLUDED.starts_at, expires_at = EXCLUDED.expires_at
+        RETURNING
+            EXISTS (SELECT 1 FROM previous) AS "existed!",
+            (SELECT starts_at FROM previous) AS previous_starts_at,
+            (SELECT expires_at FROM previous) AS previous_expires_at
+        "#, user.user_id, user.role_id, user.starts_at, user.expires_at
     )
-    .execute(pool.inner())
-    .await;
+    .fetch_one(pool.inner())
+    .await?;
+
+    cache.forget_user(user.user_id);
 
     // Log successful
-    let log = CreateLog {
-        user_id: Some(admin_user.user_id),
-        action: "user_added_to_role_successfull".to_string(),
-        details: json!({
+    let log = LogBuilder::new(LogAction::Custom("added_to_role_successfull".to_string()), "user", &context)
+        .with_user(admin_user.user_id)
+        .with_additional_details(&json!({
             "user_id": user.user_id,
             "role_id": user.role_id,
-        }),
-    };
+            "starts_at": user.starts_at,
+            "expires_at": user.expires_at,
+            "previous": assignment.existed.then(|| json!({
+                "starts_at": assignment.previous_starts_at,
+                "expires_at": assignment.previous_expires_at,
+            })),
+        }))?
+        .build();
     let _ = log_action(pool.inner(), &log).await;
 
-    match result {
-        Ok(_) => Ok(Json(json!({ "message": "User successfully assigned to role!" }))),
-        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => Err(Status::Conflict),
-        Err(_) => Err(Status::InternalServerError),
-    }
+    let message = if assignment.existed {
+        "Role assignment updated!"
+    } else {
+        "User successfully assigned to role!"
+    };
+
+    Ok(Json(json!({
+        "message": message,
+        "starts_at": user.starts_at,
+        "expires_at": user.expires_at
+    })))
 }
 
 #[delete("/role/user", format="json", data="<user_data>")]
 pub async fn remove_user_from_role(
     pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
     user_data: Json<AssignRole>,
-    admin_user: AuthenticatedUser
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), admin_user.user_id).await.map_err(|_| Status::InternalServerError)? {
-        return Err(Status::Forbidden);
-    }
-
+    admin_user: Require<UsersRolesRevoke>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
     let user = user_data.into_inner();
 
     // Proper validation error handling
-    if let Err(_) = user.validate() {
-        return Err(Status::BadRequest);
-    }
+    user.validate()?;
 
     let result = sqlx::query!(
         r#"
@@ -83,40 +123,37 @@ pub async fn remove_user_from_role(
     .execute(pool.inner())
     .await;
 
+    cache.forget_user(user.user_id);
+
     // Log successful
-    let log = CreateLog {
-        user_id: Some(admin_user.user_id),
-        action: "user_removed_from_role_successfull".to_string(),
-        details: json!({
+    let log = LogBuilder::new(LogAction::Custom("removed_from_role_successfull".to_string()), "user", &context)
+        .with_user(admin_user.user_id)
+        .with_additional_details(&json!({
             "user_id": user.user_id,
             "role_id": user.role_id,
-        }),
-    };
+        }))?
+        .build();
     let _ = log_action(pool.inner(), &log).await;
 
     match result {
         Ok(_) => Ok(Json(json!({ "message": "User successfully removed from role!" }))),
-        Err(_) => Err(Status::InternalServerError),
+        Err(e) => Err(e.into()),
     }
 }
 
 #[get("/users")]
 pub async fn get_all_users(
     pool: &State<PgPool>,
-    admin_user: AuthenticatedUser
-) -> Result<Json<Value>, Status> {
-    if !is_admin(pool.inner(), admin_user.user_id).await.map_err(|_| Status::InternalServerError)? {
-        return Err(Status::Forbidden);
-    }
-
+    admin_user: Require<UsersRead>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
     let users = sqlx::query!(
         r#"
         SELECT id, username, email FROM users
         "#
     )
     .fetch_all(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
     // Convert the query result into JSON
     let users_json: Vec<Value> = users.iter().map(|user| {
@@ -128,14 +165,13 @@ pub async fn get_all_users(
     }).collect();
 
     // Create log entry for the successful users fetch
-    let log = LogBuilder::new(LogAction::Read, "users")
+    let log = LogBuilder::new(LogAction::Read, "users", &context)
         .with_user(admin_user.user_id)
         .with_additional_details(&json!({
             "total_users_fetched": users.len(),
             "fetched_by": admin_user.user_id,
             "timestamp": chrono::Utc::now().to_rfc3339(),
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .build();
 
     let _ = log_action(pool.inner(), &log).await;
@@ -148,15 +184,12 @@ pub async fn get_all_users(
 
 #[delete("/user", format="json", data="<user_data>")]
 pub async fn delete_user(
-    admin_user: AuthenticatedUser,
+    admin_user: Require<UsersDelete>,
     pool: &State<PgPool>,
-    user_data: Json<DeleteUser>
-) -> Result<Json<Value>, Status> {
-    // Ensure the requesting user has admin privileges
-    if !is_admin(pool.inner(), admin_user.user_id).await.map_err(|_| Status::InternalServerError)? {
-        return Err(Status::Forbidden);
-    }
-
+    cache: &State<AuthzCache>,
+    user_data: Json<DeleteUser>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
     let user = user_data.into_inner();
     
     // Fetch user and profile data before deletion for logging
@@ -171,17 +204,14 @@ pub async fn delete_user(
         user.id
     )
     .fetch_optional(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?
-    .ok_or(Status::NotFound)?;
+    .await?
+    .ok_or_else(user_not_found)?;
     
     // Validate incoming request data
-    if let Err(_) = user.validate() {
-        return Err(Status::BadRequest);
-    }
+    user.validate()?;
 
     // Start a transaction
-    let mut tx = pool.inner().begin().await.map_err(|_| Status::InternalServerError)?;
+    let mut tx = pool.inner().begin().await?;
 
     // Delete user_role first (foreign key relationship)
     let role_delete_result = sqlx::query!(
@@ -193,10 +223,10 @@ pub async fn delete_user(
     .execute(&mut *tx)
     .await;
 
-    if let Err(_) = role_delete_result {
+    if let Err(e) = role_delete_result {
         // Rollback the transaction if role deletion fails
         let _ = tx.rollback().await;
-        return Err(Status::InternalServerError);
+        return Err(e.into());
     }
 
     // Delete user from the database (user_profiles will be cascade deleted)
@@ -210,7 +240,7 @@ pub async fn delete_user(
     .await;
 
     // Log successful deletion with extended profile information
-    let log = LogBuilder::new(LogAction::Delete, "user")
+    let log = LogBuilder::new(LogAction::Delete, "user", &context)
         .with_user(admin_user.user_id)
         .with_resource_id(user.id.to_string())
         .with_previous_state(&json!({
@@ -225,13 +255,11 @@ pub async fn delete_user(
                 "timezone": user_to_delete.timezone,
                 "social_links": user_to_delete.social_links
             }
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_additional_details(&json!({
             "deleted_by": admin_user.user_id,
             "deletion_timestamp": chrono::Utc::now().to_rfc3339(),
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .build();
 
     let _ = log_action(pool.inner(), &log).await;
@@ -240,16 +268,104 @@ pub async fn delete_user(
         Ok(query_result) => {
             if query_result.rows_affected() == 0 {
                 let _ = tx.rollback().await;
-                Err(Status::NotFound)
+                Err(user_not_found())
             } else {
                 // Commit the transaction if both operations succeeded
-                tx.commit().await.map_err(|_| Status::InternalServerError)?;
+                tx.commit().await?;
+                cache.forget_user(user.id);
                 Ok(Json(json!({ "message": "User and associated data successfully deleted!" })))
             }
         },
-        Err(_) => {
+        Err(e) => {
             let _ = tx.rollback().await;
-            Err(Status::InternalServerError)
+            Err(e.into())
         }
     }
-}
\ No newline at end of file
+}
+
+#[delete("/user/2fa", format="json", data="<user_data>")]
+pub async fn reset_user_two_factor(
+    admin_user: Require<UsersTwoFactorReset>,
+    pool: &State<PgPool>,
+    user_data: Json<ResetTwoFactor>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
+    let user = user_data.into_inner();
+
+    let mut tx = pool.inner().begin().await?;
+
+    let recovery_codes_result = sqlx::query!(
+        "DELETE FROM totp_recovery_codes WHERE user_id = $1",
+        user.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let totp = sqlx::query!(
+        "DELETE FROM user_totp WHERE user_id = $1 RETURNING confirmed_at",
+        user.id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(user_not_found)?;
+
+    tx.commit().await?;
+
+    let log = LogBuilder::new(LogAction::Custom("2fa_reset".to_string()), "user", &context)
+        .with_user(admin_user.user_id)
+        .with_resource_id(user.id.to_string())
+        .with_previous_state(&json!({
+            "two_factor_confirmed_at": totp.confirmed_at,
+        }))?
+        .with_additional_details(&json!({
+            "reset_by": admin_user.user_id,
+            "reset_timestamp": chrono::Utc::now().to_rfc3339(),
+            "recovery_codes_removed": recovery_codes_result.rows_affected()
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({ "message": "Two-factor authentication reset for user!" })))
+}
+
+#[delete("/user/lockout", format="json", data="<user_data>")]
+pub async fn unlock_user(
+    admin_user: Require<UsersUnlock>,
+    pool: &State<PgPool>,
+    throttle: &State<LoginThrottle>,
+    user_data: Json<UnlockUser>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
+    let user = user_data.into_inner();
+
+    let email = sqlx::query_scalar!("SELECT email FROM users WHERE id = $1", user.id)
+        .fetch_optional(pool.inner())
+        .await?
+        .ok_or_else(user_not_found)?;
+
+    // Failures from the user's addresses keep counting, only the account is reset
+    let record = throttle.clear(&AttemptKey::account(&email)).await?;
+    let was_locked = record.as_ref().is_some_and(|record| record.locked_until.is_some());
+
+    let log = LogBuilder::new(LogAction::Custom("unlocked".to_string()), "user", &context)
+        .with_user(admin_user.user_id)
+        .with_resource_id(user.id.to_string())
+        .with_previous_state(&json!({
+            "failures": record.as_ref().map(|record| record.failures),
+            "locked_until": record.as_ref().and_then(|record| record.locked_until),
+        }))?
+        .with_additional_details(&json!({
+            "unlocked_by": admin_user.user_id,
+            "unlock_timestamp": chrono::Utc::now().to_rfc3339(),
+            "was_locked": was_locked
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Lockout lifted for user!",
+        "was_locked": was_locked
+    })))
+}
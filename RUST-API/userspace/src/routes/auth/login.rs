@@ -1,13 +1,19 @@
 use rocket::serde::json::{Json, json};
-use rocket::time::Duration;
 use rocket::State;
-use rocket::http::{Status, Cookie, CookieJar, SameSite};
+use rocket::http::CookieJar;
 use rocket::serde::json::Value;
 use sqlx::PgPool;
+use uuid::Uuid;
 use validator::Validate;
+use crate::middleware::request_context::RequestContext;
 use crate::models::user::LoginRequest;
-use crate::utils::hashing::verify_password;
-use crate::utils::create_jwt::create_token;
+use crate::utils::api_error::ApiError;
+use crate::utils::hashing::{verify_dummy_password, verify_password};
+use crate::utils::login_attempts::{account_locked_email, AttemptKey, LoginThrottle};
+use crate::utils::mailer::Mailer;
+use crate::utils::session::{set_session_cookies, start_session};
+use crate::utils::totp::{challenge_ttl, issue_challenge_token};
+use crate::utils::verification::email_verification_required;
 
 use crate::utils::logger::{log_action, LogAction, LogBuilder};
 
@@ -15,95 +21,234 @@ use crate::utils::logger::{log_action, LogAction, LogBuilder};
 pub async fn login(
     cookies: &CookieJar<'_>,
     pool: &State<PgPool>,
+    mailer: &State<Mailer>,
+    throttle: &State<LoginThrottle>,
     login_data: Json<LoginRequest>,
-) -> Result<Json<Value>, Status> {
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
     let credentials = login_data.into_inner();
 
-    if let Err(_) = credentials.validate() {
-        return Err(Status::BadRequest);
-    }
+    credentials.validate()?;
+
+    // Refuse locked accounts and addresses, and slow down repeated guesses
+    let attempt_keys = AttemptKey::for_login(&credentials.email, context.ip_address);
+    throttle.admit(&attempt_keys).await?;
 
     // Fetch the user from the database
     let user = sqlx::query!(
         r#"
-        SELECT id, email, username, password_hash, created_at, last_login
+        SELECT id, password_hash, email_verified_at
         FROM users 
         WHERE email = $1
         "#,
         credentials.email
     )
     .fetch_optional(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?
-    .ok_or(Status::Unauthorized)?;
+    .await?;
+
+    let user = match user {
+        Some(user) => user,
+        None => {
+            verify_dummy_password(&credentials.password);
+            record_failed_login(pool.inner(), &context, mailer.inner(), throttle.inner(), &attempt_keys, &credentials.email, None).await?;
+            return Err(invalid_credentials());
+        }
+    };
 
     // Verify the password
-    if !verify_password(&credentials.password, &user.password_hash)
-        .map_err(|_| Status::InternalServerError)? {
+    if !verify_password(&credentials.password, &user.password_hash)? {
         
         // Log failed login attempt
-        let failed_log = LogBuilder::new(LogAction::Custom("login_failed".to_string()), "auth")
+        let failed_log = LogBuilder::new(LogAction::Custom("login_failed".to_string()), "auth", &context)
             .with_user(user.id)
             .with_additional_details(&json!({
                 "email": credentials.email,
                 "timestamp": chrono::Utc::now().to_rfc3339(),
                 "failure_reason": "invalid_password"
-            }))
-            .map_err(|_| Status::InternalServerError)?
+            }))?
             .build();
 
         let _ = log_action(pool.inner(), &failed_log).await;
-        return Err(Status::Unauthorized);
+
+        record_failed_login(pool.inner(), &context, mailer.inner(), throttle.inner(), &attempt_keys, &credentials.email, Some(user.id)).await?;
+        return Err(invalid_credentials());
     }
 
-    // Update last login
-    sqlx::query!(
-        "UPDATE users SET last_login = CURRENT_TIMESTAMP WHERE id = $1",
+    // Refuse the login until the address is verified, if that's required
+    ensure_email_verified(pool.inner(), &context, user.id, &credentials.email, user.email_verified_at).await?;
+
+    // With two-factor enabled the password alone doesn't open a session,
+    // the client has to finish the login at /api/auth/2fa/verify
+    let two_factor_enabled = sqlx::query_scalar!(
+        "SELECT EXISTS (SELECT 1 FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL)",
         user.id
     )
-    .execute(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .fetch_one(pool.inner())
+    .await?
+    .unwrap_or(false);
 
-    // Generate a JWT for the authenticated user
-    let token = create_token(user.id)
-        .map_err(|_| Status::InternalServerError)?;
+    if two_factor_enabled {
+        let challenge_token = issue_challenge_token(pool.inner(), user.id).await?;
 
-    // Create a secure, HTTP-only cookie to store the JWT
-    let mut cookie = Cookie::new("auth_token", token);
-    cookie.set_path("/");
-    cookie.set_secure(true);
-    cookie.set_http_only(true);
-    cookie.set_same_site(SameSite::Strict);
-    cookie.set_max_age(Duration::days(7));
+        let log = LogBuilder::new(LogAction::Custom("login_second_factor_required".to_string()), "auth", &context)
+            .with_user(user.id)
+            .with_additional_details(&json!({
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+            }))?
+            .build();
 
-    // Add the cookie to the response
-    cookies.add_private(cookie);
+        let _ = log_action(pool.inner(), &log).await;
+
+        return Ok(Json(json!({
+            "message": "Second factor required",
+            "second_factor_required": true,
+            "challenge_token": challenge_token,
+            "expires_in": challenge_ttl().num_seconds()
+        })));
+    }
+
+    complete_login(pool.inner(), &context, cookies, throttle.inner(), user.id, credentials.token_in_body, "password").await
+}
+
+/// Refuse a login with otherwise valid credentials while the address is
+/// unverified, if `REQUIRE_EMAIL_VERIFICATION` is on
+pub async fn ensure_email_verified(
+    pool: &PgPool,
+    context: &RequestContext,
+    user_id: Uuid,
+    email: &str,
+    email_verified_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<(), ApiError> {
+    if !email_verification_required() || email_verified_at.is_some() {
+        return Ok(());
+    }
+
+    let failed_log = LogBuilder::new(LogAction::Custom("login_failed".to_string()), "auth", context)
+        .with_user(user_id)
+        .with_additional_details(&json!({
+            "email": email,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "failure_reason": "email_not_verified"
+        }))?
+        .build();
+
+    let _ = log_action(pool, &failed_log).await;
+    Err(ApiError::forbidden("EMAIL_NOT_VERIFIED", "Verify your email address before logging in."))
+}
+
+/// Unknown emails and wrong passwords get the same answer
+fn invalid_credentials() -> ApiError {
+    ApiError::unauthorized("INVALID_CREDENTIALS", "The email or password is incorrect.")
+}
+
+/// Count a failed login, logging every lockout it causes and mailing the
+/// owner of a locked account if that's enabled
+pub async fn record_failed_login(
+    pool: &PgPool,
+    context: &RequestContext,
+    mailer: &Mailer,
+    throttle: &LoginThrottle,
+    keys: &[AttemptKey],
+    email: &str,
+    user_id: Option<Uuid>,
+) -> Result<(), ApiError> {
+    for lockout in throttle.record_failure(keys).await? {
+        let is_account = matches!(lockout.key, AttemptKey::Account(_));
+
+        let notified = match user_id {
+            Some(_) if is_account && throttle.policy().notify => {
+                mailer.send(&account_locked_email(email, lockout.locked_until)).await.is_ok()
+            }
+            _ => false,
+        };
+
+        let mut log = LogBuilder::new(LogAction::Custom("login_locked".to_string()), "auth", context)
+            .with_resource_id(lockout.key.to_string())
+            .with_additional_details(&json!({
+                "key_type": lockout.key.kind(),
+                "failures": lockout.failures,
+                "locked_until": lockout.locked_until,
+                "owner_notified": notified,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+            }))?;
+
+        if let Some(user_id) = user_id.filter(|_| is_account) {
+            log = log.with_user(user_id);
+        }
+
+        let _ = log_action(pool, &log.build()).await;
+    }
+
+    Ok(())
+}
+
+/// Finish a login once every required factor has been checked: update the
+/// last login, open a session, hand out the tokens and log it. Earlier
+/// failures on the account stop counting once the session is issued
+pub async fn complete_login(
+    pool: &PgPool,
+    context: &RequestContext,
+    cookies: &CookieJar<'_>,
+    throttle: &LoginThrottle,
+    user_id: Uuid,
+    token_in_body: bool,
+    method: &str,
+) -> Result<Json<Value>, ApiError> {
+    // Update last login, keeping the previous one for the log
+    let user = sqlx::query!(
+        r#"
+        UPDATE users u
+        SET last_login = CURRENT_TIMESTAMP
+        FROM (SELECT id, last_login FROM users WHERE id = $1 FOR UPDATE) previous
+        WHERE u.id = previous.id
+        RETURNING u.id, u.email, u.username, u.created_at, previous.last_login
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ApiError::unauthorized("AUTH_REQUIRED", "The account no longer exists."))?;
+
+    // Open a server-side session with a short-lived access token
+    // and a rotating refresh token
+    let tokens = start_session(pool, user.id, context.client_type)
+        .await?;
+
+    // The address keeps its count so one known account can't reset it
+    throttle.clear(&AttemptKey::account(&user.email)).await?;
+
+    // Store them in private cookies unless the client asked for them in the body
+    if !token_in_body {
+        set_session_cookies(cookies, &tokens);
+    }
 
     // Log successful login
-    let log = LogBuilder::new(LogAction::Custom("login_successful".to_string()), "auth")
+    let log = LogBuilder::new(LogAction::Custom("login_successful".to_string()), "auth", context)
         .with_user(user.id)
         .with_resource_id(user.id.to_string())
         .with_previous_state(&json!({
             "last_login": user.last_login,
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_additional_details(&json!({
             "login_info": {
                 "email": user.email,
                 "username": user.username,
+                "method": method,
                 "timestamp": chrono::Utc::now().to_rfc3339(),
             },
             "account_info": {
                 "account_created_at": user.created_at,
             }
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .build();
 
-    let _ = log_action(pool.inner(), &log).await;
+    let _ = log_action(pool, &log).await;
+
+    if token_in_body {
+        return Ok(Json(tokens.to_response("Logged in successfully")));
+    }
 
     Ok(Json(json!({
         "message": "Logged in successfully"
     })))
-}
\ No newline at end of file
+}
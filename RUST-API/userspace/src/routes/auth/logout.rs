@@ -1,10 +1,38 @@
-use rocket::http::{Cookie, CookieJar};
+use rocket::http::CookieJar;
 use rocket::serde::json::{Json, json};
 use rocket::serde::json::Value;
+use rocket::State;
+use sqlx::PgPool;
+
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::utils::api_error::ApiError;
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::session::{clear_session_cookies, revoke_session};
 
 #[post("/logout")]
-pub fn logout(cookies: &CookieJar<'_>) -> Json<Value> {
-    // Remove the 'auth_token' cookie
-    cookies.remove_private(Cookie::from("auth_token"));
-    Json(json!({ "message": "Logged out successfully" }))
+pub async fn logout(
+    cookies: &CookieJar<'_>,
+    pool: &State<PgPool>,
+    user: AuthenticatedUser,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    // Revoke the session server-side so neither token can be replayed
+    revoke_session(pool.inner(), user.session_id)
+        .await?;
+
+    // Remove the 'auth_token' and 'refresh_token' cookies
+    clear_session_cookies(cookies);
+
+    let log = LogBuilder::new(LogAction::Custom("logout".to_string()), "auth", &context)
+        .with_user(user.user_id)
+        .with_resource_id(user.session_id.to_string())
+        .with_additional_details(&json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({ "message": "Logged out successfully" })))
 }
@@ -2,6 +2,11 @@ use rocket::Route;
 pub mod login;
 pub mod register;
 pub mod logout;
+pub mod refresh;
+pub mod verify_email;
+pub mod password_reset;
+pub mod two_factor;
+pub mod webauthn;
 
 #[options("/<_..>")]
 fn all_options() {
@@ -9,5 +14,23 @@ fn all_options() {
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![register::register, login::login, logout::logout, all_options]
-}
\ No newline at end of file
+    routes![
+        register::register,
+        login::login,
+        logout::logout,
+        refresh::refresh,
+        verify_email::verify_email,
+        verify_email::resend_verification,
+        password_reset::forgot_password,
+        password_reset::reset_password,
+        two_factor::enroll,
+        two_factor::confirm,
+        two_factor::verify,
+        two_factor::disable,
+        webauthn::start_registration,
+        webauthn::finish_registration,
+        webauthn::start_login,
+        webauthn::finish_login,
+        all_options
+    ]
+}
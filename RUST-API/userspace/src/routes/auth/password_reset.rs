@@ -0,0 +1,154 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::middleware::request_context::RequestContext;
+use crate::models::user::{ForgotPasswordRequest, ResetPasswordRequest};
+use crate::utils::api_error::ApiError;
+use crate::utils::hashing::hash_password;
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::mailer::Mailer;
+use crate::utils::password_reset::{create_reset_token, password_reset_email};
+use crate::utils::session::revoke_user_sessions;
+use crate::utils::tokens::hash_token;
+
+/// Create a reset token for the user and mail them the link
+async fn send_reset_link(pool: PgPool, mailer: Mailer, context: RequestContext, user_id: Uuid, email: String) {
+    let token = match create_reset_token(&pool, user_id).await {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Failed to create a password reset token: {}", e);
+            return;
+        }
+    };
+
+    let sent = mailer
+        .send(&password_reset_email(&email, &token))
+        .await
+        .is_ok();
+
+    let log = LogBuilder::new(LogAction::Custom("password_reset_requested".to_string()), "auth", &context)
+        .with_user(user_id)
+        .with_resource_id(user_id.to_string())
+        .with_additional_details(&json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "delivered": sent
+        }));
+
+    if let Ok(log) = log {
+        let _ = log_action(&pool, &log.build()).await;
+    }
+}
+
+#[post("/password/forgot", format = "json", data = "<forgot_data>")]
+pub async fn forgot_password(
+    pool: &State<PgPool>,
+    mailer: &State<Mailer>,
+    forgot_data: Json<ForgotPasswordRequest>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let request = forgot_data.into_inner();
+
+    request.validate()?;
+
+    let user = sqlx::query!(
+        "SELECT id, email FROM users WHERE email = $1",
+        request.email
+    )
+    .fetch_optional(pool.inner())
+    .await?;
+
+    // Answer the same way whether or not the account exists, so this
+    // endpoint can't be used to find out which emails are registered. The
+    // token and the mail are dealt with after the response, so the time it
+    // takes to answer doesn't give it away either
+    if let Some(user) = user {
+        rocket::tokio::spawn(send_reset_link(
+            pool.inner().clone(),
+            mailer.inner().clone(),
+            context,
+            user.id,
+            user.email,
+        ));
+    }
+
+    Ok(Json(json!({
+        "message": "If an account exists for this email, a password reset link is on its way"
+    })))
+}
+
+#[post("/password/reset", format = "json", data = "<reset_data>")]
+pub async fn reset_password(
+    pool: &State<PgPool>,
+    reset_data: Json<ResetPasswordRequest>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let request = reset_data.into_inner();
+
+    request.validate()?;
+
+    let password_hash = hash_password(&request.new_password)?;
+
+    let mut tx = pool.begin().await?;
+
+    // Consume the token; it only works once and only before it expires
+    let reset = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = CURRENT_TIMESTAMP
+        WHERE token_hash = $1
+        AND used_at IS NULL
+        AND expires_at > CURRENT_TIMESTAMP
+        RETURNING id, user_id
+        "#,
+        hash_token(&request.token)
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ApiError::bad_request("INVALID_RESET_TOKEN", "The reset link is invalid, used or expired."))?;
+
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+        password_hash,
+        reset.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Any other outstanding reset links for this user are now stale
+    sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = CURRENT_TIMESTAMP
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        reset.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Sign the user out everywhere, whoever knew the old password included
+    let sessions_revoked = revoke_user_sessions(&mut *tx, reset.user_id, None)
+        .await?;
+
+    tx.commit().await?;
+
+    let log = LogBuilder::new(LogAction::Custom("password_reset".to_string()), "auth", &context)
+        .with_user(reset.user_id)
+        .with_resource_id(reset.user_id.to_string())
+        .with_additional_details(&json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "reset_token_id": reset.id,
+            "sessions_revoked": sessions_revoked
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Password successfully reset! Please log in with your new password."
+    })))
+}
@@ -0,0 +1,78 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::http::CookieJar;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+
+use crate::middleware::request_context::RequestContext;
+use crate::models::session::RefreshRequest;
+use crate::utils::api_error::ApiError;
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::session::{
+    clear_session_cookies, rotate_refresh_token, set_session_cookies, RefreshOutcome,
+};
+
+#[post("/refresh", data = "<refresh_data>")]
+pub async fn refresh(
+    cookies: &CookieJar<'_>,
+    pool: &State<PgPool>,
+    refresh_data: Option<Json<RefreshRequest>>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    // A token sent in the body takes precedence over the cookie, and the
+    // new pair is returned the same way it was presented
+    let body_token = refresh_data.and_then(|data| data.into_inner().refresh_token);
+    let from_body = body_token.is_some();
+
+    let presented_token = match body_token {
+        Some(token) => token,
+        None => cookies
+            .get_private("refresh_token")
+            .map(|cookie| cookie.value().to_string())
+            .ok_or_else(|| ApiError::unauthorized("AUTH_REQUIRED", "No refresh token was sent."))?,
+    };
+
+    let outcome = rotate_refresh_token(pool.inner(), &presented_token)
+        .await?;
+
+    let (user_id, tokens) = match outcome {
+        RefreshOutcome::Rotated { user_id, tokens } => (user_id, tokens),
+        RefreshOutcome::Reused { user_id, session_id } => {
+            // Someone replayed a rotated token: the family was revoked, record it
+            let log = LogBuilder::new(LogAction::Custom("refresh_token_reused".to_string()), "auth", &context)
+                .with_user(user_id)
+                .with_resource_id(session_id.to_string())
+                .with_additional_details(&json!({
+                    "timestamp": chrono::Utc::now().to_rfc3339(),
+                    "session_revoked": true
+                }))?
+                .build();
+
+            let _ = log_action(pool.inner(), &log).await;
+
+            clear_session_cookies(cookies);
+            return Err(ApiError::unauthorized("REFRESH_TOKEN_REUSED", "The refresh token was already used, the session has been revoked."));
+        }
+        RefreshOutcome::Invalid => {
+            clear_session_cookies(cookies);
+            return Err(ApiError::unauthorized("INVALID_REFRESH_TOKEN", "The refresh token is invalid or has expired."));
+        }
+    };
+
+    let log = LogBuilder::new(LogAction::Custom("token_refreshed".to_string()), "auth", &context)
+        .with_user(user_id)
+        .with_resource_id(tokens.session_id.to_string())
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    if from_body {
+        return Ok(Json(tokens.to_response("Tokens refreshed successfully")));
+    }
+
+    set_session_cookies(cookies, &tokens);
+
+    Ok(Json(json!({
+        "message": "Tokens refreshed successfully"
+    })))
+}
@@ -1,34 +1,42 @@
 use rocket::serde::json::{Json, json};
-use rocket::time::Duration;
 use rocket::State;
-use rocket::http::{Status, Cookie, CookieJar, SameSite};
+use rocket::http::CookieJar;
 use rocket::serde::json::Value;
 use sqlx::PgPool;
 use validator::Validate;
 
+use crate::middleware::request_context::RequestContext;
 use crate::models::user::RegisterRequest;
+use crate::utils::api_error::ApiError;
 use crate::utils::hashing::hash_password;
-use crate::utils::create_jwt::create_token;
+use crate::utils::invitations::{invite_required, redeem_invitation};
+use crate::utils::session::{set_session_cookies, start_session};
 use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::mailer::Mailer;
+use crate::routes::auth::verify_email::send_verification_link;
+use crate::utils::verification::email_verification_required;
 
 #[post("/register", format = "json", data = "<user_data>")]
 pub async fn register(
     cookies: &CookieJar<'_>,
     pool: &State<PgPool>,
+    mailer: &State<Mailer>,
     user_data: Json<RegisterRequest>,
-) -> Result<Json<Value>, Status> {
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
     let user = user_data.into_inner();
 
-    if let Err(_) = user.validate() {
-        return Err(Status::BadRequest);
+    user.validate()?;
+
+    // Closed registration only lets invited people in
+    if invite_required() && user.invite_code.is_none() {
+        return Err(ApiError::forbidden("INVITE_REQUIRED", "Registration needs an invitation code."));
     }
 
-    let password_hash = hash_password(&user.password)
-        .map_err(|_| Status::InternalServerError)?;
+    let password_hash = hash_password(&user.password)?;
 
     // Start a transaction since we're doing multiple operations
-    let mut tx = pool.begin().await
-        .map_err(|_| Status::InternalServerError)?;
+    let mut tx = pool.begin().await?;
 
     let result = sqlx::query!(
         r#"
@@ -41,11 +49,7 @@ pub async fn register(
         password_hash,
     )
     .fetch_one(&mut *tx)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::Database(err) if err.is_unique_violation() => Status::Conflict,
-        _ => Status::InternalServerError,
-    })?;
+    .await?;
 
     // Create minimal user profile
     let profile_result = sqlx::query!(
@@ -57,30 +61,29 @@ pub async fn register(
         result.id  // Using the ID from the newly created user
     )
     .fetch_one(&mut *tx)
-    .await
-    .map_err(|_| Status::InternalServerError)?;
-
-    // Commit the transaction
-    tx.commit().await
-        .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
-    // Generate a JWT for the authenticated user
-    let token = create_token(result.id)
-        .map_err(|_| Status::InternalServerError)?;
+    // Grant what the invitation carries along with the account, or neither
+    let invitation = match &user.invite_code {
+        Some(code) => Some(redeem_invitation(&mut tx, code, result.id, &result.email, false).await?),
+        None => None,
+    };
 
-    // Create a secure, HTTP-only cookie to store the JWT
-    let mut cookie = Cookie::new("auth_token", token);
-    cookie.set_path("/");
-    cookie.set_secure(true);
-    cookie.set_http_only(true);
-    cookie.set_same_site(SameSite::Strict);
-    cookie.set_max_age(Duration::days(7));
+    // Commit the transaction
+    tx.commit().await?;
 
-    // Add the cookie to the response
-    cookies.add_private(cookie);
+    // Accounts start unverified, send the link to prove the address. The
+    // mail goes out after the response, so a slow mail server doesn't hold it up
+    rocket::tokio::spawn(send_verification_link(
+        pool.inner().clone(),
+        mailer.inner().clone(),
+        context.clone(),
+        result.id,
+        result.email.clone(),
+    ));
 
     // Log successful registration and profile creation
-    let log = LogBuilder::new(LogAction::Create, "user")
+    let log = LogBuilder::new(LogAction::Create, "user", &context)
         .with_user(result.id)
         .with_resource_id(result.id.to_string())
         .with_new_state(&json!({
@@ -88,16 +91,38 @@ pub async fn register(
             "username": result.username,
             "created_at": result.created_at,
             "profile_id": profile_result.id
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_additional_details(&json!({
             "registration_timestamp": chrono::Utc::now().to_rfc3339(),
-        }))
-        .map_err(|_| Status::InternalServerError)?
+            "invitation_id": invitation.as_ref().map(|i| i.id),
+            "organization_id": invitation.as_ref().and_then(|i| i.organization_id),
+            "role_ids": invitation.as_ref().map(|i| i.role_ids.clone()).unwrap_or_default(),
+        }))?
         .build();
 
     let _ = log_action(pool.inner(), &log).await;
 
+    // Without a verified address the user can't log in yet, so don't open a session
+    if email_verification_required() {
+        return Ok(Json(json!({
+            "message": "User registered with profile! Please verify your email before logging in."
+        })));
+    }
+
+    // Open a server-side session with a short-lived access token
+    // and a rotating refresh token
+    let tokens = start_session(pool.inner(), result.id, context.client_type)
+        .await?;
+
+    // Store them in private cookies unless the client asked for them in the body
+    if !user.token_in_body {
+        set_session_cookies(cookies, &tokens);
+    }
+
+    if user.token_in_body {
+        return Ok(Json(tokens.to_response("User registered with profile and logged in successfully!")));
+    }
+
     Ok(Json(json!({
         "message": "User registered with profile and logged in successfully!"
     })))
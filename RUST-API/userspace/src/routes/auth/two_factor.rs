@@ -0,0 +1,344 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::http::CookieJar;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::models::two_factor::{TwoFactorCode, VerifyTwoFactorRequest};
+use crate::routes::auth::login::{complete_login, record_failed_login};
+use crate::utils::api_error::ApiError;
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::login_attempts::{AttemptKey, LoginThrottle};
+use crate::utils::mailer::Mailer;
+use crate::utils::tokens::hash_token;
+use crate::utils::totp::{
+    decode_challenge_token, generate_recovery_codes, generate_secret, normalize_recovery_code,
+    otpauth_uri, verify_code, CHALLENGE_MAX_ATTEMPTS,
+};
+
+#[post("/2fa/enroll")]
+pub async fn enroll(
+    pool: &State<PgPool>,
+    user: AuthenticatedUser,
+) -> Result<Json<Value>, ApiError> {
+    let account = sqlx::query!(
+        r#"
+        SELECT u.email, t.confirmed_at AS "confirmed_at?"
+        FROM users u
+        LEFT JOIN user_totp t ON u.id = t.user_id
+        WHERE u.id = $1
+        "#,
+        user.user_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."))?;
+
+    // Re-enrolling would silently swap a working authenticator, disable first
+    if account.confirmed_at.is_some() {
+        return Err(ApiError::conflict("TWO_FACTOR_ALREADY_ENABLED", "Two-factor authentication is already enabled, disable it first."));
+    }
+
+    let secret = generate_secret();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_totp (user_id, secret)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE
+        SET secret = EXCLUDED.secret, created_at = CURRENT_TIMESTAMP
+        "#,
+        user.user_id,
+        secret
+    )
+    .execute(pool.inner())
+    .await?;
+
+    Ok(Json(json!({
+        "message": "Scan the URI with an authenticator app, then confirm with a code",
+        "secret": secret,
+        "otpauth_uri": otpauth_uri(&secret, &account.email)
+    })))
+}
+
+#[post("/2fa/confirm", format = "json", data = "<code_data>")]
+pub async fn confirm(
+    pool: &State<PgPool>,
+    user: AuthenticatedUser,
+    code_data: Json<TwoFactorCode>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let code = code_data.into_inner();
+
+    code.validate()?;
+
+    let totp = sqlx::query!(
+        "SELECT secret FROM user_totp WHERE user_id = $1 AND confirmed_at IS NULL",
+        user.user_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("TWO_FACTOR_NOT_PENDING", "There's no two-factor setup waiting to be confirmed."))?;
+
+    let step = verify_code(&totp.secret, &code.code)
+        .ok_or_else(|| ApiError::bad_request("INVALID_TOTP_CODE", "The code is incorrect or has expired."))?;
+    let recovery_codes = generate_recovery_codes();
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE user_totp
+        SET confirmed_at = CURRENT_TIMESTAMP, last_used_step = $2
+        WHERE user_id = $1
+        "#,
+        user.user_id,
+        step
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM totp_recovery_codes WHERE user_id = $1", user.user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Only hashes are stored, the codes are shown to the user this one time
+    for recovery_code in &recovery_codes {
+        sqlx::query!(
+            "INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES ($1, $2)",
+            user.user_id,
+            hash_token(&normalize_recovery_code(recovery_code))
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    let log = LogBuilder::new(LogAction::Custom("2fa_enabled".to_string()), "user", &context)
+        .with_user(user.user_id)
+        .with_resource_id(user.user_id.to_string())
+        .with_additional_details(&json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "recovery_codes_issued": recovery_codes.len()
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Two-factor authentication enabled! Store these recovery codes somewhere safe.",
+        "recovery_codes": recovery_codes
+    })))
+}
+
+fn invalid_second_factor() -> ApiError {
+    ApiError::unauthorized("INVALID_SECOND_FACTOR", "The code or recovery code is incorrect.")
+}
+
+fn invalid_challenge() -> ApiError {
+    ApiError::unauthorized("INVALID_CHALLENGE_TOKEN", "The login challenge is invalid or has expired.")
+}
+
+/// Accept `step` for the user only if it's past the last accepted one, which
+/// makes every code single-use
+async fn consume_totp_step<'e, E: sqlx::PgExecutor<'e>>(executor: E, user_id: Uuid, step: i64) -> Result<bool, ApiError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_totp
+        SET last_used_step = $2
+        WHERE user_id = $1
+        AND (last_used_step IS NULL OR last_used_step < $2)
+        "#,
+        user_id,
+        step
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[post("/2fa/verify", format = "json", data = "<verify_data>")]
+pub async fn verify(
+    cookies: &CookieJar<'_>,
+    pool: &State<PgPool>,
+    mailer: &State<Mailer>,
+    throttle: &State<LoginThrottle>,
+    verify_data: Json<VerifyTwoFactorRequest>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let request = verify_data.into_inner();
+
+    request.validate()?;
+
+    let challenge = decode_challenge_token(&request.challenge_token)
+        .map_err(|_| invalid_challenge())?;
+
+    let email = sqlx::query_scalar!("SELECT email FROM users WHERE id = $1", challenge.sub)
+        .fetch_optional(pool.inner())
+        .await?
+        .ok_or_else(invalid_challenge)?;
+
+    // Guessed codes count towards the same lockout as guessed passwords
+    let attempt_keys = AttemptKey::for_login(&email, context.ip_address);
+    throttle.admit(&attempt_keys).await?;
+
+    if request.code.is_none() && request.recovery_code.is_none() {
+        return Err(ApiError::bad_request("SECOND_FACTOR_REQUIRED", "Send either a code or a recovery code."));
+    }
+
+    // Use the challenge up before looking at the code. It has to be
+    // outstanding: not used, not expired and not worn out by wrong codes. The
+    // row stays locked until the transaction ends, so a concurrent request
+    // with the same token waits here and finds it used, before it can burn
+    // the user's code
+    let mut tx = pool.begin().await?;
+
+    sqlx::query_scalar!(
+        r#"
+        UPDATE two_factor_challenges
+        SET consumed_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND user_id = $2
+        AND consumed_at IS NULL
+        AND expires_at > CURRENT_TIMESTAMP
+        RETURNING id
+        "#,
+        challenge.jti,
+        challenge.sub
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(invalid_challenge)?;
+
+    let verified = match (&request.code, &request.recovery_code) {
+        (Some(code), _) => {
+            let totp = sqlx::query!(
+                "SELECT secret FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL",
+                challenge.sub
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(invalid_second_factor)?;
+
+            match verify_code(&totp.secret, code) {
+                Some(step) => consume_totp_step(&mut *tx, challenge.sub, step).await?,
+                None => false,
+            }
+        }
+        (None, Some(recovery_code)) => sqlx::query!(
+            r#"
+            UPDATE totp_recovery_codes
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            "#,
+            challenge.sub,
+            hash_token(&normalize_recovery_code(recovery_code))
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected() > 0,
+        (None, None) => unreachable!("checked above"),
+    };
+
+    if !verified {
+        // Give the challenge back with the failure counted, unless that was
+        // one too many
+        let failed_attempts = sqlx::query_scalar!(
+            r#"
+            UPDATE two_factor_challenges
+            SET failed_attempts = failed_attempts + 1,
+                consumed_at = CASE WHEN failed_attempts + 1 >= $2 THEN CURRENT_TIMESTAMP END
+            WHERE id = $1
+            RETURNING failed_attempts
+            "#,
+            challenge.jti,
+            CHALLENGE_MAX_ATTEMPTS
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        let failed_log = LogBuilder::new(LogAction::Custom("login_failed".to_string()), "auth", &context)
+            .with_user(challenge.sub)
+            .with_additional_details(&json!({
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "failure_reason": "invalid_second_factor",
+                "challenge_failed_attempts": failed_attempts
+            }))?
+            .build();
+
+        let _ = log_action(pool.inner(), &failed_log).await;
+
+        record_failed_login(pool.inner(), &context, mailer.inner(), throttle.inner(), &attempt_keys, &email, Some(challenge.sub)).await?;
+        return Err(invalid_second_factor());
+    }
+
+    tx.commit().await?;
+
+    let method = if request.code.is_some() { "password+totp" } else { "password+recovery_code" };
+
+    complete_login(pool.inner(), &context, cookies, throttle.inner(), challenge.sub, request.token_in_body, method).await
+}
+
+#[post("/2fa/disable", format = "json", data = "<code_data>")]
+pub async fn disable(
+    pool: &State<PgPool>,
+    user: AuthenticatedUser,
+    code_data: Json<TwoFactorCode>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let code = code_data.into_inner();
+
+    code.validate()?;
+
+    let totp = sqlx::query!(
+        "SELECT secret FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL",
+        user.user_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("TWO_FACTOR_NOT_ENABLED", "Two-factor authentication isn't enabled."))?;
+
+    // A session alone isn't enough to turn the second factor off, and a code
+    // that was already accepted doesn't count
+    let accepted = match verify_code(&totp.secret, &code.code) {
+        Some(step) => consume_totp_step(pool.inner(), user.user_id, step).await?,
+        None => false,
+    };
+
+    if !accepted {
+        return Err(ApiError::forbidden("INVALID_TOTP_CODE", "The code is incorrect or has expired."));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!("DELETE FROM totp_recovery_codes WHERE user_id = $1", user.user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user.user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    let log = LogBuilder::new(LogAction::Custom("2fa_disabled".to_string()), "user", &context)
+        .with_user(user.user_id)
+        .with_resource_id(user.user_id.to_string())
+        .with_additional_details(&json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Two-factor authentication disabled"
+    })))
+}
@@ -0,0 +1,118 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::middleware::request_context::RequestContext;
+use crate::models::user::{ResendVerificationRequest, VerifyEmailRequest};
+use crate::utils::api_error::ApiError;
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::mailer::Mailer;
+use crate::utils::verification::{decode_verification_token, verification_email};
+
+/// Bad, expired and outdated links all look the same
+fn invalid_link() -> ApiError {
+    ApiError::bad_request("INVALID_VERIFICATION_TOKEN", "The verification link is invalid or has expired.")
+}
+
+#[post("/verify-email", format = "json", data = "<verify_data>")]
+pub async fn verify_email(
+    pool: &State<PgPool>,
+    verify_data: Json<VerifyEmailRequest>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let claims = decode_verification_token(&verify_data.token)
+        .map_err(|_| invalid_link())?;
+
+    // Only verify if the address in the token is still the user's address
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP)
+        WHERE id = $1 AND email = $2
+        RETURNING email_verified_at
+        "#,
+        claims.sub,
+        claims.email
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(invalid_link)?;
+
+    let log = LogBuilder::new(LogAction::Custom("email_verified".to_string()), "user", &context)
+        .with_user(claims.sub)
+        .with_resource_id(claims.sub.to_string())
+        .with_new_state(&json!({
+            "email": claims.email,
+            "email_verified_at": result.email_verified_at
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Email successfully verified!"
+    })))
+}
+
+/// Mail the user a fresh verification link
+pub(crate) async fn send_verification_link(pool: PgPool, mailer: Mailer, context: RequestContext, user_id: Uuid, email: String) {
+    let email = match verification_email(user_id, &email) {
+        Ok(email) => email,
+        Err(e) => {
+            eprintln!("Failed to build a verification email: {}", e);
+            return;
+        }
+    };
+
+    let sent = mailer.send(&email).await.is_ok();
+
+    let log = LogBuilder::new(LogAction::Custom("verification_email_sent".to_string()), "user", &context)
+        .with_user(user_id)
+        .with_resource_id(user_id.to_string())
+        .with_additional_details(&json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "delivered": sent
+        }));
+
+    if let Ok(log) = log {
+        let _ = log_action(&pool, &log.build()).await;
+    }
+}
+
+#[post("/verify-email/resend", format = "json", data = "<resend_data>")]
+pub async fn resend_verification(
+    pool: &State<PgPool>,
+    mailer: &State<Mailer>,
+    resend_data: Json<ResendVerificationRequest>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let request = resend_data.into_inner();
+
+    request.validate()?;
+
+    let user = sqlx::query!(
+        "SELECT id, email FROM users WHERE email = $1 AND email_verified_at IS NULL",
+        request.email
+    )
+    .fetch_optional(pool.inner())
+    .await?;
+
+    // Answer the same way whether or not the address is known and unverified,
+    // and send the mail after the response so the timing doesn't tell either
+    if let Some(user) = user {
+        rocket::tokio::spawn(send_verification_link(
+            pool.inner().clone(),
+            mailer.inner().clone(),
+            context,
+            user.id,
+            user.email,
+        ));
+    }
+
+    Ok(Json(json!({
+        "message": "If the address belongs to an unverified account, a verification email is on its way"
+    })))
+}
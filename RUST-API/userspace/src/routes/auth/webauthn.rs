@@ -0,0 +1,287 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::http::CookieJar;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+use webauthn_rs::prelude::{
+    CredentialID, Passkey, PasskeyAuthentication, PasskeyRegistration, Webauthn,
+};
+
+use crate::middleware::cors::ClientType;
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::models::webauthn::{FinishPasskeyLogin, FinishPasskeyRegistration, StartPasskeyLogin};
+use crate::routes::auth::login::{complete_login, ensure_email_verified, record_failed_login};
+use crate::utils::api_error::ApiError;
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::login_attempts::{AttemptKey, LoginThrottle};
+use crate::utils::mailer::Mailer;
+use crate::utils::webauthn::{decoy_login_options, store_ceremony, take_ceremony};
+
+const REGISTRATION: &str = "registration";
+const AUTHENTICATION: &str = "authentication";
+
+fn invalid_ceremony() -> ApiError {
+    ApiError::bad_request("INVALID_CEREMONY", "The passkey registration is unknown or has expired.")
+}
+
+/// Unknown accounts, accounts without passkeys and bad assertions look the same
+fn passkey_login_failed() -> ApiError {
+    ApiError::unauthorized("PASSKEY_LOGIN_FAILED", "The passkey couldn't be verified.")
+}
+
+/// The relying party is the web client's origin, so other clients can't use passkeys
+fn require_web_client(context: &RequestContext) -> Result<(), ApiError> {
+    if context.client_type == Some(ClientType::Web) {
+        Ok(())
+    } else {
+        Err(ApiError::forbidden("WEB_CLIENT_REQUIRED", "Passkeys can only be used from the web client."))
+    }
+}
+
+#[post("/webauthn/register/start")]
+pub async fn start_registration(
+    pool: &State<PgPool>,
+    webauthn: &State<Webauthn>,
+    user: AuthenticatedUser,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    require_web_client(&context)?;
+
+    let account = sqlx::query!(
+        "SELECT email, username FROM users WHERE id = $1",
+        user.user_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."))?;
+
+    // Don't let the same authenticator be registered twice
+    let existing: Vec<CredentialID> = sqlx::query_scalar!(
+        "SELECT credential_id FROM webauthn_credentials WHERE user_id = $1",
+        user.user_id
+    )
+    .fetch_all(pool.inner())
+    .await?
+    .into_iter()
+    .map(CredentialID::from)
+    .collect();
+
+    let (options, state) = webauthn
+        .start_passkey_registration(user.user_id, &account.email, &account.username, Some(existing))?;
+
+    let state = rocket::serde::json::to_value(&state)?;
+
+    let ceremony_id = store_ceremony(pool.inner(), user.user_id, REGISTRATION, state)
+        .await?;
+
+    Ok(Json(json!({
+        "ceremony_id": ceremony_id,
+        "options": options
+    })))
+}
+
+#[post("/webauthn/register/finish", format = "json", data = "<registration_data>")]
+pub async fn finish_registration(
+    pool: &State<PgPool>,
+    webauthn: &State<Webauthn>,
+    user: AuthenticatedUser,
+    registration_data: Json<FinishPasskeyRegistration>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    require_web_client(&context)?;
+
+    let registration = registration_data.into_inner();
+
+    registration.validate()?;
+
+    let (ceremony_user_id, state) = take_ceremony(pool.inner(), registration.ceremony_id, REGISTRATION)
+        .await?
+        .ok_or_else(invalid_ceremony)?;
+
+    if ceremony_user_id != user.user_id {
+        return Err(invalid_ceremony());
+    }
+
+    let state: PasskeyRegistration = rocket::serde::json::from_value(state)?;
+
+    let passkey = webauthn
+        .finish_passkey_registration(&registration.credential, &state)
+        .map_err(|_| ApiError::bad_request("INVALID_PASSKEY", "The passkey couldn't be verified."))?;
+
+    let stored_passkey = rocket::serde::json::to_value(&passkey)?;
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO webauthn_credentials (user_id, credential_id, passkey, name)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, created_at
+        "#,
+        user.user_id,
+        passkey.cred_id().as_ref(),
+        stored_passkey,
+        registration.name
+    )
+    .fetch_one(pool.inner())
+    .await?;
+
+    let log = LogBuilder::new(LogAction::Create, "webauthn_credential", &context)
+        .with_user(user.user_id)
+        .with_resource_id(result.id.to_string())
+        .with_new_state(&json!({
+            "name": registration.name,
+            "created_at": result.created_at
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Passkey registered successfully!",
+        "id": result.id,
+        "name": registration.name,
+        "created_at": result.created_at
+    })))
+}
+
+#[post("/webauthn/login/start", format = "json", data = "<login_data>")]
+pub async fn start_login(
+    pool: &State<PgPool>,
+    webauthn: &State<Webauthn>,
+    throttle: &State<LoginThrottle>,
+    login_data: Json<StartPasskeyLogin>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    require_web_client(&context)?;
+
+    let request = login_data.into_inner();
+
+    request.validate()?;
+
+    // Anyone can start a login, so it's held back like a password login
+    throttle.admit(&AttemptKey::for_login(&request.email, context.ip_address)).await?;
+
+    let credentials = sqlx::query!(
+        r#"
+        SELECT c.user_id, c.passkey
+        FROM webauthn_credentials c
+        JOIN users u ON c.user_id = u.id
+        WHERE u.email = $1
+        "#,
+        request.email
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    // Unknown accounts and accounts without passkeys get made-up options and
+    // a ceremony that was never stored, so finishing fails like a bad assertion
+    let Some(user_id) = credentials.first().map(|c| c.user_id) else {
+        return Ok(Json(json!({
+            "ceremony_id": Uuid::new_v4(),
+            "options": decoy_login_options(&request.email)?
+        })));
+    };
+
+    let passkeys: Vec<Passkey> = credentials
+        .into_iter()
+        .filter_map(|c| rocket::serde::json::from_value(c.passkey).ok())
+        .collect();
+
+    let (options, state) = webauthn
+        .start_passkey_authentication(&passkeys)?;
+
+    let state = rocket::serde::json::to_value(&state)?;
+
+    let ceremony_id = store_ceremony(pool.inner(), user_id, AUTHENTICATION, state)
+        .await?;
+
+    Ok(Json(json!({
+        "ceremony_id": ceremony_id,
+        "options": options
+    })))
+}
+
+#[post("/webauthn/login/finish", format = "json", data = "<login_data>")]
+pub async fn finish_login(
+    cookies: &CookieJar<'_>,
+    pool: &State<PgPool>,
+    webauthn: &State<Webauthn>,
+    mailer: &State<Mailer>,
+    throttle: &State<LoginThrottle>,
+    login_data: Json<FinishPasskeyLogin>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    require_web_client(&context)?;
+
+    let request = login_data.into_inner();
+
+    let (user_id, state) = take_ceremony(pool.inner(), request.ceremony_id, AUTHENTICATION)
+        .await?
+        .ok_or_else(passkey_login_failed)?;
+
+    let state: PasskeyAuthentication = rocket::serde::json::from_value(state)?;
+
+    let account = sqlx::query!("SELECT email, email_verified_at FROM users WHERE id = $1", user_id)
+        .fetch_optional(pool.inner())
+        .await?
+        .ok_or_else(passkey_login_failed)?;
+
+    // Failed assertions count towards the same lockout as guessed passwords
+    let attempt_keys = AttemptKey::for_login(&account.email, context.ip_address);
+    throttle.admit(&attempt_keys).await?;
+
+    let result = webauthn.finish_passkey_authentication(&request.credential, &state).ok();
+
+    let stored = match &result {
+        Some(result) => sqlx::query!(
+            "SELECT id, passkey FROM webauthn_credentials WHERE user_id = $1 AND credential_id = $2",
+            user_id,
+            result.cred_id().as_ref()
+        )
+        .fetch_optional(pool.inner())
+        .await?,
+        None => None,
+    };
+
+    let (result, stored) = match (result, stored) {
+        (Some(result), Some(stored)) => (result, stored),
+        _ => {
+            let failed_log = LogBuilder::new(LogAction::Custom("login_failed".to_string()), "auth", &context)
+                .with_user(user_id)
+                .with_additional_details(&json!({
+                    "timestamp": chrono::Utc::now().to_rfc3339(),
+                    "failure_reason": "invalid_passkey_assertion"
+                }))?
+                .build();
+
+            let _ = log_action(pool.inner(), &failed_log).await;
+
+            record_failed_login(pool.inner(), &context, mailer.inner(), throttle.inner(), &attempt_keys, &account.email, Some(user_id)).await?;
+            return Err(passkey_login_failed());
+        }
+    };
+
+    // Keep the signature counter and backup state up to date
+    let mut passkey: Passkey = rocket::serde::json::from_value(stored.passkey)?;
+    passkey.update_credential(&result);
+
+    let updated_passkey = rocket::serde::json::to_value(&passkey)?;
+
+    sqlx::query!(
+        r#"
+        UPDATE webauthn_credentials
+        SET passkey = $1, last_used_at = CURRENT_TIMESTAMP
+        WHERE id = $2
+        "#,
+        updated_passkey,
+        stored.id
+    )
+    .execute(pool.inner())
+    .await?;
+
+    ensure_email_verified(pool.inner(), &context, user_id, &account.email, account.email_verified_at).await?;
+
+    complete_login(pool.inner(), &context, cookies, throttle.inner(), user_id, request.token_in_body, "passkey").await
+}
@@ -0,0 +1,66 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::http::Header;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use validator::Validate;
+
+use crate::middleware::service_client::ServiceClient;
+use crate::models::authz::{AuthzBatchCheck, AuthzCheck};
+use crate::utils::api_error::ApiError;
+use crate::utils::authz::{decide, AuthzCache};
+
+/// A decision along with how long the caller may reuse it
+#[derive(Responder)]
+pub struct CachedDecision {
+    inner: Json<Value>,
+    cache_control: Header<'static>,
+}
+
+impl CachedDecision {
+    fn new(body: Value, cache: &AuthzCache) -> Self {
+        CachedDecision {
+            inner: Json(body),
+            cache_control: Header::new("Cache-Control", format!("private, max-age={}", cache.ttl().as_secs())),
+        }
+    }
+}
+
+#[post("/check", format = "json", data = "<check_data>")]
+pub async fn check(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    _service: ServiceClient,
+    check_data: Json<AuthzCheck>,
+) -> Result<CachedDecision, ApiError> {
+    let check = check_data.into_inner();
+
+    check.validate()?;
+
+    let decision = decide(pool.inner(), cache.inner(), &check).await?;
+
+    Ok(CachedDecision::new(decision, cache.inner()))
+}
+
+#[post("/check/batch", format = "json", data = "<batch_data>")]
+pub async fn check_batch(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    _service: ServiceClient,
+    batch_data: Json<AuthzBatchCheck>,
+) -> Result<CachedDecision, ApiError> {
+    let batch = batch_data.into_inner();
+
+    batch.validate()?;
+    for check in &batch.checks {
+        check.validate()?;
+    }
+
+    // Answers come back in the order the checks were asked
+    let mut decisions = Vec::with_capacity(batch.checks.len());
+    for check in &batch.checks {
+        decisions.push(decide(pool.inner(), cache.inner(), check).await?);
+    }
+
+    Ok(CachedDecision::new(json!({ "decisions": decisions }), cache.inner()))
+}
@@ -0,0 +1,15 @@
+use rocket::Route;
+pub mod check;
+
+#[options("/<_..>")]
+fn all_options() {
+    /* Empty */
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        check::check,
+        check::check_batch,
+        all_options
+    ]
+}
@@ -0,0 +1,128 @@
+use rocket::http::Status;
+use rocket::serde::json::{Json, json};
+use rocket::serde::json::Value;
+use rocket::{Catcher, Request};
+
+use crate::utils::api_error::error_body;
+
+/// Errors raised outside the handlers: failed request guards, bodies Rocket
+/// couldn't parse, unknown paths. Each status has one code
+fn error(req: &Request, status: Status, code: &str, message: &str, details: &str) -> Json<Value> {
+    Json(error_body(req, status, code, message, json!(details)))
+}
+
+#[catch(400)]
+fn bad_request(req: &Request) -> Json<Value> {
+    error(
+        req,
+        Status::BadRequest,
+        "BAD_REQUEST",
+        "The request couldn't be understood.",
+        "Check the request and try again.",
+    )
+}
+
+#[catch(401)]
+fn unauthorized(req: &Request) -> Json<Value> {
+    error(
+        req,
+        Status::Unauthorized,
+        "AUTH_REQUIRED",
+        "You need to sign in first.",
+        "Send a valid access token in the auth_token cookie or an Authorization: Bearer header.",
+    )
+}
+
+#[catch(403)]
+fn forbidden(req: &Request) -> Json<Value> {
+    error(
+        req,
+        Status::Forbidden,
+        "PERMISSION_DENIED",
+        "You're not allowed to do that.",
+        "Ask an administrator for access.",
+    )
+}
+
+#[catch(404)]
+fn not_found(req: &Request) -> Json<Value> {
+    error(
+        req,
+        Status::NotFound,
+        "RESOURCE_NOT_FOUND",
+        "The path you're looking for seems to be missing in the void.",
+        "Check the URL and try again, or navigate back to safety.",
+    )
+}
+
+#[catch(409)]
+fn conflict(req: &Request) -> Json<Value> {
+    error(
+        req,
+        Status::Conflict,
+        "CONFLICT",
+        "The request conflicts with the current state.",
+        "Reload the resource and try again.",
+    )
+}
+
+#[catch(413)]
+fn payload_too_large(req: &Request) -> Json<Value> {
+    error(
+        req,
+        Status::PayloadTooLarge,
+        "PAYLOAD_TOO_LARGE",
+        "The request body is too large.",
+        "Send a smaller body.",
+    )
+}
+
+#[catch(422)]
+fn unprocessable_entity(req: &Request) -> Json<Value> {
+    error(
+        req,
+        Status::UnprocessableEntity,
+        "MALFORMED_BODY",
+        "The request body couldn't be read.",
+        "Send valid JSON with every required field, using the documented types.",
+    )
+}
+
+#[catch(429)]
+fn too_many_requests(req: &Request) -> Json<Value> {
+    error(
+        req,
+        Status::TooManyRequests,
+        "RATE_LIMITED",
+        "Too many requests.",
+        "Wait a moment before trying again.",
+    )
+}
+
+#[catch(500)]
+fn internal_error(req: &Request) -> Json<Value> {
+    let mut body = error_body(
+        req,
+        Status::InternalServerError,
+        "INTERNAL_SERVER_ERROR",
+        "Something went wrong on our end.",
+        json!("Our team has been notified and is working on it."),
+    );
+    body["error"]["path"] = json!(req.uri().path().to_string());
+
+    Json(body)
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![
+        bad_request,
+        unauthorized,
+        forbidden,
+        not_found,
+        conflict,
+        payload_too_large,
+        unprocessable_entity,
+        too_many_requests,
+        internal_error
+    ]
+}
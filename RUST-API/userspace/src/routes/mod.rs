@@ -1,7 +1,10 @@
-use rocket::Route;
+use rocket::{Catcher, Route};
 pub mod auth;
 pub mod admin;
 pub mod user;
+pub mod orgs;
+pub mod authz;
+pub mod catchers;
 
 pub fn auth_routes() -> Vec<Route> {
     auth::routes()
@@ -13,4 +16,16 @@ pub fn admin_routes() -> Vec<Route> {
 
 pub fn user_routes() -> Vec<Route> {
     user::routes()
+}
+
+pub fn org_routes() -> Vec<Route> {
+    orgs::routes()
+}
+
+pub fn authz_routes() -> Vec<Route> {
+    authz::routes()
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers::catchers()
 }
\ No newline at end of file
@@ -0,0 +1,239 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::require_permission::{OrgMembersInvite, RequireOrg};
+use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::models::invitation::{AcceptInvitation, CreateInvitation};
+use crate::routes::orgs::members::{builtin_role_id, check_assignable_role};
+use crate::utils::api_error::ApiError;
+use crate::utils::authz::AuthzCache;
+use crate::utils::auth::has_org_permission;
+use crate::utils::invitations::{create_invitation as store_invitation, invitation_email, redeem_invitation};
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::mailer::Mailer;
+
+/// Role given on acceptance when the invitation doesn't name one
+const DEFAULT_MEMBER_ROLE: &str = "Org Member";
+
+#[post("/<org_id>/invitations", format = "json", data = "<invitation_data>")]
+pub async fn create_invitation(
+    pool: &State<PgPool>,
+    mailer: &State<Mailer>,
+    org_id: Uuid,
+    member: RequireOrg<OrgMembersInvite>,
+    invitation_data: Json<CreateInvitation>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let mut invitation = invitation_data.into_inner();
+
+    invitation.validate()?;
+
+    let organization = sqlx::query!(
+        "SELECT name FROM organizations WHERE id = $1",
+        org_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("ORGANIZATION_NOT_FOUND", "No organization with that id."))?;
+
+    // Pre-assigning roles is assigning roles, so it needs that permission and
+    // follows the same rules as assigning them to a member
+    if !invitation.role_ids.is_empty() {
+        if !has_org_permission(pool.inner(), member.user_id, org_id, "org.members.roles.assign").await? {
+            return Err(ApiError::forbidden("PERMISSION_DENIED", "Giving invitations roles needs the org.members.roles.assign permission."));
+        }
+
+        for role_id in &invitation.role_ids {
+            check_assignable_role(pool.inner(), org_id, member.user_id, *role_id).await?;
+        }
+    } else {
+        invitation.role_ids.push(builtin_role_id(pool.inner(), DEFAULT_MEMBER_ROLE).await?);
+    }
+
+    if let Some(email) = &invitation.email {
+        let already_member = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM organization_members m
+                JOIN users u ON m.user_id = u.id
+                WHERE m.organization_id = $1 AND LOWER(u.email) = LOWER($2)
+            )
+            "#,
+            org_id,
+            email
+        )
+        .fetch_one(pool.inner())
+        .await?
+        .unwrap_or(false);
+
+        if already_member {
+            return Err(ApiError::conflict("ALREADY_MEMBER", "The user is already a member of this organization."));
+        }
+    }
+
+    let created = store_invitation(pool.inner(), Some(org_id), &invitation, member.user_id).await?;
+
+    let sent = match &invitation.email {
+        Some(email) => mailer
+            .send(&invitation_email(email, Some(&organization.name), &created.link, created.expires_at))
+            .await
+            .is_ok(),
+        None => false,
+    };
+
+    let log = LogBuilder::new(LogAction::Create, "invitation", &context)
+        .with_user(member.user_id)
+        .with_resource_id(created.id.to_string())
+        .with_new_state(&json!({
+            "organization_id": org_id,
+            "email": invitation.email,
+            "role_ids": invitation.role_ids,
+            "max_uses": invitation.max_uses,
+            "expires_at": created.expires_at
+        }))?
+        .with_additional_details(&json!({
+            "delivered": sent
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Invitation created!",
+        "id": created.id,
+        "code": created.code,
+        "link": created.link,
+        "email": invitation.email,
+        "role_ids": invitation.role_ids,
+        "max_uses": invitation.max_uses,
+        "expires_at": created.expires_at,
+        "created_at": created.created_at,
+        "email_sent": sent
+    })))
+}
+
+#[get("/<org_id>/invitations")]
+pub async fn get_invitations(
+    pool: &State<PgPool>,
+    org_id: Uuid,
+    _member: RequireOrg<OrgMembersInvite>,
+) -> Result<Json<Value>, ApiError> {
+    let invitations = sqlx::query!(
+        r#"
+        SELECT i.id, i.email, i.invited_by, i.max_uses, i.use_count, i.expires_at, i.created_at,
+               ARRAY_AGG(r.name) FILTER (WHERE r.name IS NOT NULL) AS roles
+        FROM invitations i
+        LEFT JOIN invitation_roles ir ON i.id = ir.invitation_id
+        LEFT JOIN roles r ON ir.role_id = r.id
+        WHERE i.organization_id = $1
+        AND i.use_count < i.max_uses
+        AND (i.expires_at IS NULL OR i.expires_at > CURRENT_TIMESTAMP)
+        GROUP BY i.id
+        ORDER BY i.created_at DESC
+        "#,
+        org_id
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let invitations_json: Vec<Value> = invitations.iter().map(|invitation| {
+        json!({
+            "id": invitation.id,
+            "email": invitation.email,
+            "roles": invitation.roles.clone().unwrap_or_default(),
+            "invited_by": invitation.invited_by,
+            "max_uses": invitation.max_uses,
+            "use_count": invitation.use_count,
+            "expires_at": invitation.expires_at,
+            "created_at": invitation.created_at,
+        })
+    }).collect();
+
+    Ok(Json(json!({
+        "message": "Found invitations!",
+        "invitations": invitations_json
+    })))
+}
+
+#[delete("/<org_id>/invitations/<id>")]
+pub async fn revoke_invitation(
+    pool: &State<PgPool>,
+    org_id: Uuid,
+    id: Uuid,
+    member: RequireOrg<OrgMembersInvite>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let invitation = sqlx::query!(
+        r#"
+        DELETE FROM invitations
+        WHERE id = $1 AND organization_id = $2 AND use_count < max_uses
+        RETURNING email, max_uses, use_count, expires_at
+        "#,
+        id,
+        org_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("INVITATION_NOT_FOUND", "No pending invitation with that id."))?;
+
+    let log = LogBuilder::new(LogAction::Delete, "invitation", &context)
+        .with_user(member.user_id)
+        .with_resource_id(id.to_string())
+        .with_previous_state(&json!({
+            "organization_id": org_id,
+            "email": invitation.email,
+            "max_uses": invitation.max_uses,
+            "use_count": invitation.use_count,
+            "expires_at": invitation.expires_at
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({ "message": "Invitation revoked" })))
+}
+
+#[post("/invitations/accept", format = "json", data = "<accept_data>")]
+pub async fn accept_invitation(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    user: AuthenticatedUser,
+    accept_data: Json<AcceptInvitation>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let request = accept_data.into_inner();
+
+    let email = sqlx::query_scalar!("SELECT email FROM users WHERE id = $1", user.user_id)
+        .fetch_one(pool.inner())
+        .await?;
+
+    let mut tx = pool.begin().await?;
+
+    let invitation = redeem_invitation(&mut tx, &request.token, user.user_id, &email, true).await?;
+
+    tx.commit().await?;
+
+    cache.forget_user(user.user_id);
+
+    let log = LogBuilder::new(LogAction::Custom("invitation_accepted".to_string()), "invitation", &context)
+        .with_user(user.user_id)
+        .with_resource_id(invitation.id.to_string())
+        .with_additional_details(&json!({
+            "organization_id": invitation.organization_id,
+            "role_ids": invitation.role_ids,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Invitation accepted! Welcome aboard.",
+        "organization_id": invitation.organization_id
+    })))
+}
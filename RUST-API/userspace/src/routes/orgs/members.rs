@@ -0,0 +1,231 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::serde::json::Value;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::require_permission::{
+    OrgMembersRead, OrgMembersRemove, OrgMembersRolesAssign, OrgMembersRolesRevoke, RequireOrg,
+};
+use crate::models::organization::AssignOrganizationRole;
+use crate::utils::api_error::ApiError;
+use crate::utils::authz::AuthzCache;
+use crate::utils::auth::{covers_all, effective_grants, role_grants};
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+
+/// Look up a role the organization routes hand out by name. Admins can rename
+/// or delete any role, so a missing one is reported instead of leaving a
+/// creator or new member without a role
+pub(crate) async fn builtin_role_id<'e, E: PgExecutor<'e>>(executor: E, name: &str) -> Result<Uuid, ApiError> {
+    sqlx::query_scalar!("SELECT id FROM roles WHERE name = $1", name)
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| ApiError::Internal(anyhow::anyhow!("The '{}' role is missing, restore it or rename a role to it", name)))
+}
+
+/// Organization admins may only hand out roles marked as assignable in
+/// organizations, which keeps global roles such as Admin out, and only when
+/// they already hold everything the role grants inside the organization
+pub(crate) async fn check_assignable_role(
+    pool: &PgPool,
+    org_id: Uuid,
+    assigner_id: Uuid,
+    role_id: Uuid,
+) -> Result<(), ApiError> {
+    let assignable = sqlx::query_scalar!("SELECT organization_assignable FROM roles WHERE id = $1", role_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| ApiError::not_found("ROLE_NOT_FOUND", "No role with that id."))?;
+
+    if !assignable {
+        return Err(ApiError::forbidden("ROLE_NOT_ASSIGNABLE", "The role can't be given out inside an organization."));
+    }
+
+    let held = effective_grants(pool, assigner_id, Some(org_id)).await?;
+
+    if !covers_all(&held, &role_grants(pool, role_id).await?) {
+        return Err(ApiError::forbidden("ROLE_EXCEEDS_PERMISSIONS", "You can only give out roles whose permissions you hold in this organization."));
+    }
+
+    Ok(())
+}
+
+#[get("/<org_id>/members")]
+pub async fn get_members(
+    pool: &State<PgPool>,
+    org_id: Uuid,
+    _member: RequireOrg<OrgMembersRead>,
+) -> Result<Json<Value>, ApiError> {
+    let members = sqlx::query!(
+        r#"
+        SELECT u.id, u.username, u.email, m.created_at AS joined_at,
+               ARRAY_AGG(r.name) FILTER (WHERE r.name IS NOT NULL) AS roles
+        FROM organization_members m
+        JOIN users u ON m.user_id = u.id
+        LEFT JOIN organization_member_roles omr
+            ON m.organization_id = omr.organization_id AND m.user_id = omr.user_id
+        LEFT JOIN roles r ON omr.role_id = r.id
+        WHERE m.organization_id = $1
+        GROUP BY u.id, u.username, u.email, m.created_at
+        ORDER BY m.created_at
+        "#,
+        org_id
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let members_json: Vec<Value> = members.iter().map(|member| {
+        json!({
+            "id": member.id,
+            "username": member.username,
+            "email": member.email,
+            "joined_at": member.joined_at,
+            "roles": member.roles.clone().unwrap_or_default(),
+        })
+    }).collect();
+
+    Ok(Json(json!({
+        "message": "Found members!",
+        "members": members_json
+    })))
+}
+
+#[delete("/<org_id>/members/<user_id>")]
+pub async fn remove_member(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    org_id: Uuid,
+    user_id: Uuid,
+    member: RequireOrg<OrgMembersRemove>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    // The member's organization roles go with the membership
+    let removed = sqlx::query!(
+        r#"
+        DELETE FROM organization_members
+        WHERE organization_id = $1 AND user_id = $2
+        RETURNING created_at
+        "#,
+        org_id,
+        user_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("MEMBER_NOT_FOUND", "The user isn't a member of this organization."))?;
+
+    cache.forget_user(user_id);
+
+    let log = LogBuilder::new(LogAction::Delete, "organization_member", &context)
+        .with_user(member.user_id)
+        .with_resource_id(format!("{}:{}", org_id, user_id))
+        .with_previous_state(&json!({
+            "organization_id": org_id,
+            "user_id": user_id,
+            "joined_at": removed.created_at
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({ "message": "Member successfully removed from organization!" })))
+}
+
+#[post("/<org_id>/members/<user_id>/roles", format = "json", data = "<role_data>")]
+pub async fn assign_member_role(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    org_id: Uuid,
+    user_id: Uuid,
+    member: RequireOrg<OrgMembersRolesAssign>,
+    role_data: Json<AssignOrganizationRole>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let role = role_data.into_inner();
+
+    role.validate()?;
+
+    check_assignable_role(pool.inner(), org_id, member.user_id, role.role_id).await?;
+
+    // The foreign key fails when the user isn't a member
+    sqlx::query!(
+        r#"
+        INSERT INTO organization_member_roles (organization_id, user_id, role_id)
+        VALUES ($1, $2, $3)
+        "#,
+        org_id,
+        user_id,
+        role.role_id
+    )
+    .execute(pool.inner())
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(err) if err.is_unique_violation() => {
+            ApiError::conflict("ROLE_ALREADY_ASSIGNED", "The member already has this role.")
+        }
+        sqlx::Error::Database(err) if err.is_foreign_key_violation() => {
+            ApiError::not_found("MEMBER_OR_ROLE_NOT_FOUND", "The user isn't a member or the role doesn't exist.")
+        }
+        e => e.into(),
+    })?;
+
+    cache.forget_user(user_id);
+
+    let log = LogBuilder::new(LogAction::Create, "organization_member_role", &context)
+        .with_user(member.user_id)
+        .with_resource_id(format!("{}:{}", org_id, user_id))
+        .with_new_state(&json!({
+            "organization_id": org_id,
+            "user_id": user_id,
+            "role_id": role.role_id
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({ "message": "Role successfully assigned to member!" })))
+}
+
+#[delete("/<org_id>/members/<user_id>/roles/<role_id>")]
+pub async fn revoke_member_role(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    org_id: Uuid,
+    user_id: Uuid,
+    role_id: Uuid,
+    member: RequireOrg<OrgMembersRolesRevoke>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM organization_member_roles
+        WHERE organization_id = $1 AND user_id = $2 AND role_id = $3
+        "#,
+        org_id,
+        user_id,
+        role_id
+    )
+    .execute(pool.inner())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found("ROLE_NOT_ASSIGNED", "The member doesn't have this role."));
+    }
+
+    cache.forget_user(user_id);
+
+    let log = LogBuilder::new(LogAction::Delete, "organization_member_role", &context)
+        .with_user(member.user_id)
+        .with_resource_id(format!("{}:{}", org_id, user_id))
+        .with_previous_state(&json!({
+            "organization_id": org_id,
+            "user_id": user_id,
+            "role_id": role_id
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({ "message": "Role successfully removed from member!" })))
+}
@@ -0,0 +1,28 @@
+use rocket::Route;
+pub mod organizations;
+pub mod members;
+pub mod invitations;
+
+#[options("/<_..>")]
+fn all_options() {
+    /* Empty */
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        organizations::create_organization,
+        organizations::get_organizations,
+        organizations::get_organization,
+        organizations::update_organization,
+        organizations::delete_organization,
+        members::get_members,
+        members::remove_member,
+        members::assign_member_role,
+        members::revoke_member_role,
+        invitations::create_invitation,
+        invitations::get_invitations,
+        invitations::revoke_invitation,
+        invitations::accept_invitation,
+        all_options
+    ]
+}
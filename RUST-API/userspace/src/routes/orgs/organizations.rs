@@ -0,0 +1,257 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::require_permission::{OrgDelete, OrgRead, OrgUpdate, RequireOrg};
+use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::models::organization::{CreateOrganization, UpdateOrganization};
+use crate::routes::orgs::members::builtin_role_id;
+use crate::utils::api_error::ApiError;
+use crate::utils::authz::AuthzCache;
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+
+/// Role given to whoever creates an organization
+const CREATOR_ROLE: &str = "Org Admin";
+
+fn organization_not_found() -> ApiError {
+    ApiError::not_found("ORGANIZATION_NOT_FOUND", "No organization with that id.")
+}
+
+#[post("/", format = "json", data = "<organization_data>")]
+pub async fn create_organization(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    user: AuthenticatedUser,
+    organization_data: Json<CreateOrganization>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let organization = organization_data.into_inner();
+
+    organization.validate()?;
+
+    let mut tx = pool.begin().await?;
+
+    let creator_role_id = builtin_role_id(&mut *tx, CREATOR_ROLE).await?;
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO organizations (name, slug, created_by)
+        VALUES ($1, $2, $3)
+        RETURNING id, name, slug, created_at
+        "#,
+        organization.name,
+        organization.slug,
+        user.user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO organization_members (organization_id, user_id) VALUES ($1, $2)",
+        result.id,
+        user.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // The creator manages the organization until they hand that over
+    sqlx::query!(
+        "INSERT INTO organization_member_roles (organization_id, user_id, role_id) VALUES ($1, $2, $3)",
+        result.id,
+        user.user_id,
+        creator_role_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    cache.forget_user(user.user_id);
+
+    let log = LogBuilder::new(LogAction::Create, "organization", &context)
+        .with_user(user.user_id)
+        .with_resource_id(result.id.to_string())
+        .with_new_state(&json!({
+            "name": result.name,
+            "slug": result.slug,
+            "created_at": result.created_at
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "id": result.id,
+        "name": result.name,
+        "slug": result.slug,
+        "created_at": result.created_at
+    })))
+}
+
+#[get("/")]
+pub async fn get_organizations(
+    pool: &State<PgPool>,
+    user: AuthenticatedUser,
+) -> Result<Json<Value>, ApiError> {
+    let organizations = sqlx::query!(
+        r#"
+        SELECT o.id, o.name, o.slug, m.created_at AS joined_at,
+               ARRAY_AGG(r.name) FILTER (WHERE r.name IS NOT NULL) AS roles
+        FROM organization_members m
+        JOIN organizations o ON m.organization_id = o.id
+        LEFT JOIN organization_member_roles omr
+            ON m.organization_id = omr.organization_id AND m.user_id = omr.user_id
+        LEFT JOIN roles r ON omr.role_id = r.id
+        WHERE m.user_id = $1
+        GROUP BY o.id, o.name, o.slug, m.created_at
+        ORDER BY o.name
+        "#,
+        user.user_id
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let organizations_json: Vec<Value> = organizations.iter().map(|organization| {
+        json!({
+            "id": organization.id,
+            "name": organization.name,
+            "slug": organization.slug,
+            "joined_at": organization.joined_at,
+            "roles": organization.roles.clone().unwrap_or_default(),
+        })
+    }).collect();
+
+    Ok(Json(json!({
+        "message": "Found organizations!",
+        "organizations": organizations_json
+    })))
+}
+
+#[get("/<org_id>")]
+pub async fn get_organization(
+    pool: &State<PgPool>,
+    org_id: Uuid,
+    _member: RequireOrg<OrgRead>,
+) -> Result<Json<Value>, ApiError> {
+    let organization = sqlx::query!(
+        r#"
+        SELECT o.id, o.name, o.slug, o.created_by, o.created_at, o.updated_at,
+               (SELECT COUNT(*) FROM organization_members m WHERE m.organization_id = o.id) AS member_count
+        FROM organizations o
+        WHERE o.id = $1
+        "#,
+        org_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(organization_not_found)?;
+
+    Ok(Json(json!({
+        "id": organization.id,
+        "name": organization.name,
+        "slug": organization.slug,
+        "created_by": organization.created_by,
+        "created_at": organization.created_at,
+        "updated_at": organization.updated_at,
+        "member_count": organization.member_count
+    })))
+}
+
+#[put("/<org_id>", format = "json", data = "<organization_data>")]
+pub async fn update_organization(
+    pool: &State<PgPool>,
+    org_id: Uuid,
+    member: RequireOrg<OrgUpdate>,
+    organization_data: Json<UpdateOrganization>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    let organization = organization_data.into_inner();
+
+    organization.validate()?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE organizations o
+        SET name = $1, updated_at = CURRENT_TIMESTAMP
+        FROM organizations old
+        WHERE o.id = old.id AND o.id = $2
+        RETURNING o.id, o.name, o.slug, o.updated_at, old.name AS previous_name
+        "#,
+        organization.name,
+        org_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(organization_not_found)?;
+
+    let log = LogBuilder::new(LogAction::Update, "organization", &context)
+        .with_user(member.user_id)
+        .with_resource_id(org_id.to_string())
+        .with_previous_state(&json!({
+            "name": result.previous_name
+        }))?
+        .with_new_state(&json!({
+            "name": result.name
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "id": result.id,
+        "name": result.name,
+        "slug": result.slug,
+        "updated_at": result.updated_at
+    })))
+}
+
+#[delete("/<org_id>")]
+pub async fn delete_organization(
+    pool: &State<PgPool>,
+    cache: &State<AuthzCache>,
+    org_id: Uuid,
+    member: RequireOrg<OrgDelete>,
+    context: RequestContext,
+) -> Result<Json<Value>, ApiError> {
+    // Members, their organization roles and open invitations cascade with it
+    let organization = sqlx::query!(
+        r#"
+        DELETE FROM organizations
+        WHERE id = $1
+        RETURNING id, name, slug, created_at
+        "#,
+        org_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(organization_not_found)?;
+
+    cache.clear();
+
+    let log = LogBuilder::new(LogAction::Delete, "organization", &context)
+        .with_user(member.user_id)
+        .with_resource_id(org_id.to_string())
+        .with_previous_state(&json!({
+            "name": organization.name,
+            "slug": organization.slug,
+            "created_at": organization.created_at
+        }))?
+        .with_additional_details(&json!({
+            "deleted_by": member.user_id,
+            "deletion_timestamp": chrono::Utc::now().to_rfc3339(),
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "id": organization.id,
+        "name": organization.name,
+        "slug": organization.slug,
+        "created_at": organization.created_at
+    })))
+}
@@ -1,26 +1,25 @@
 use rocket::serde::json::{Json, json};
 use rocket::State;
-use rocket::http::Status;
 use rocket::serde::json::Value;
 use sqlx::PgPool;
 
 use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::utils::api_error::ApiError;
 
 #[get("/")]
 pub async fn get_user(
     pool: &State<PgPool>,
     cookie_user: AuthenticatedUser
-) -> Result<Json<Value>, Status> {
+) -> Result<Json<Value>, ApiError> {
     let user = sqlx::query!(
         r#"
-        SELECT id, username, email, created_at, updated_at, last_login
+        SELECT id, username, email, created_at, updated_at, last_login, email_verified_at
         FROM users
         WHERE id = $1
         "#, cookie_user.user_id
     )
     .fetch_optional(pool.inner()) // Use fetch_optional instead of fetch_all
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
     // Check if user exists
     if let Some(user) = user {
@@ -32,10 +31,11 @@ pub async fn get_user(
                 "email": user.email,
                 "created_at": user.created_at,
                 "updated_at": user.updated_at,
-                "last_login": user.last_login
+                "last_login": user.last_login,
+                "email_verified_at": user.email_verified_at
             }
         })))
     } else {
-        Err(Status::NotFound) // Return 404 if user is not found
+        Err(ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."))
     }
 }
@@ -3,6 +3,9 @@ pub mod get_user;
 pub mod update_email;
 pub mod update_username;
 pub mod update_profile;
+pub mod update_password;
+pub mod passkeys;
+pub mod permissions;
 
 #[options("/<_..>")]
 fn all_options() {
@@ -15,6 +18,11 @@ pub fn routes() -> Vec<Route> {
             update_email::update_email,
             update_username::update_username,
             update_profile::update_profile,
+            update_password::update_password,
+            passkeys::get_passkeys,
+            passkeys::rename_passkey,
+            passkeys::delete_passkey,
+            permissions::get_my_permissions,
             all_options
         ]
 }
\ No newline at end of file
@@ -0,0 +1,120 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::models::webauthn::{RenamePasskey, WebauthnCredential};
+use crate::utils::api_error::ApiError;
+use crate::utils::logger::{LogBuilder, LogAction, log_action};
+
+#[get("/passkeys")]
+pub async fn get_passkeys(
+    user: AuthenticatedUser,
+    pool: &State<PgPool>
+) -> Result<Json<Value>, ApiError> {
+    let passkeys = sqlx::query_as!(
+        WebauthnCredential,
+        r#"
+        SELECT id, name, created_at, last_used_at
+        FROM webauthn_credentials
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user.user_id
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    Ok(Json(json!({
+        "message": "Found passkeys!",
+        "passkeys": passkeys
+    })))
+}
+
+#[put("/passkeys/<id>", format="json", data="<passkey_data>")]
+pub async fn rename_passkey(
+    id: Uuid,
+    user: AuthenticatedUser,
+    pool: &State<PgPool>,
+    passkey_data: Json<RenamePasskey>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
+    let passkey = passkey_data.into_inner();
+
+    passkey.validate()?;
+
+    let current = sqlx::query!(
+        "SELECT name FROM webauthn_credentials WHERE id = $1 AND user_id = $2",
+        id,
+        user.user_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("PASSKEY_NOT_FOUND", "No passkey with that id."))?;
+
+    sqlx::query!(
+        "UPDATE webauthn_credentials SET name = $1 WHERE id = $2 AND user_id = $3",
+        passkey.name,
+        id,
+        user.user_id
+    )
+    .execute(pool.inner())
+    .await?;
+
+    let log = LogBuilder::new(LogAction::Update, "webauthn_credential", &context)
+        .with_user(user.user_id)
+        .with_resource_id(id.to_string())
+        .with_previous_state(&json!({
+            "name": current.name
+        }))?
+        .with_new_state(&json!({
+            "name": passkey.name
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Passkey successfully renamed!"
+    })))
+}
+
+#[delete("/passkeys/<id>")]
+pub async fn delete_passkey(
+    id: Uuid,
+    user: AuthenticatedUser,
+    pool: &State<PgPool>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM webauthn_credentials
+        WHERE id = $1 AND user_id = $2
+        RETURNING name, created_at
+        "#,
+        id,
+        user.user_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("PASSKEY_NOT_FOUND", "No passkey with that id."))?;
+
+    let log = LogBuilder::new(LogAction::Delete, "webauthn_credential", &context)
+        .with_user(user.user_id)
+        .with_resource_id(id.to_string())
+        .with_previous_state(&json!({
+            "name": deleted.name,
+            "created_at": deleted.created_at
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Passkey successfully removed!"
+    })))
+}
@@ -0,0 +1,183 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::utils::api_error::ApiError;
+use crate::utils::auth::effective_grants;
+use crate::utils::scope::format_grant;
+
+/// The `If-None-Match` header, if the client sent one
+pub struct IfNoneMatch(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(
+            request.headers().get_one("If-None-Match").map(str::to_string),
+        ))
+    }
+}
+
+impl IfNoneMatch {
+    fn matches(&self, etag: &str) -> bool {
+        match &self.0 {
+            Some(header) => header
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag),
+            None => false,
+        }
+    }
+}
+
+#[derive(Responder)]
+pub enum EffectivePermissions {
+    Fresh {
+        inner: Json<Value>,
+        etag: Header<'static>,
+        cache_control: Header<'static>,
+    },
+    #[response(status = 304)]
+    NotModified {
+        inner: (),
+        etag: Header<'static>,
+        cache_control: Header<'static>,
+    },
+}
+
+/// Clients must revalidate every time, the ETag makes that cheap
+fn cache_headers(etag: &str) -> (Header<'static>, Header<'static>) {
+    (
+        Header::new("ETag", etag.to_string()),
+        Header::new("Cache-Control", "private, no-cache"),
+    )
+}
+
+#[get("/me/permissions?<organization_id>")]
+pub async fn get_my_permissions(
+    pool: &State<PgPool>,
+    user: AuthenticatedUser,
+    organization_id: Option<Uuid>,
+    if_none_match: IfNoneMatch,
+) -> Result<EffectivePermissions, ApiError> {
+    let global_roles = sqlx::query!(
+        r#"
+        SELECT r.id, r.name, ur.starts_at, ur.expires_at
+        FROM user_roles ur
+        JOIN roles r ON ur.role_id = r.id
+        WHERE ur.user_id = $1
+        AND (ur.starts_at IS NULL OR ur.starts_at <= CURRENT_TIMESTAMP)
+        AND (ur.expires_at IS NULL OR ur.expires_at > CURRENT_TIMESTAMP)
+        ORDER BY r.name
+        "#,
+        user.user_id
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let organization_roles = sqlx::query!(
+        r#"
+        SELECT r.id, r.name
+        FROM organization_member_roles omr
+        JOIN roles r ON omr.role_id = r.id
+        WHERE omr.user_id = $1 AND omr.organization_id = $2
+        ORDER BY r.name
+        "#,
+        user.user_id,
+        organization_id
+    )
+    .fetch_all(pool.inner())
+    .await?;
+
+    let grants = effective_grants(pool.inner(), user.user_id, organization_id).await?;
+
+    let mut roles_json: Vec<Value> = global_roles.iter().map(|role| {
+        json!({
+            "id": role.id,
+            "name": role.name,
+            "source": "global",
+            "starts_at": role.starts_at,
+            "expires_at": role.expires_at,
+        })
+    }).collect();
+
+    roles_json.extend(organization_roles.iter().map(|role| {
+        json!({
+            "id": role.id,
+            "name": role.name,
+            "source": "organization",
+            "starts_at": null,
+            "expires_at": null,
+        })
+    }));
+
+    // Grants come closest role first, so the first time a grant shows up is
+    // the role it's credited to
+    let mut permissions_json: Vec<Value> = Vec::new();
+    let mut seen: Vec<String> = Vec::new();
+
+    for grant in &grants {
+        let scoped = format_grant(&grant.resource_type, &grant.resource_id, &grant.permission);
+        if seen.contains(&scoped) {
+            continue;
+        }
+        seen.push(scoped.clone());
+
+        permissions_json.push(json!({
+            "name": grant.permission,
+            "resource_type": grant.resource_type,
+            "resource_id": grant.resource_id,
+            "grant": scoped,
+            "source": grant.source,
+            "inherited": grant.inherited,
+            "granted_by": {
+                "id": grant.role_id,
+                "name": grant.role_name
+            }
+        }));
+    }
+
+    permissions_json.sort_by(|a, b| a["grant"].as_str().cmp(&b["grant"].as_str()));
+
+    // The version changes with any role, expiry or grant change that affects
+    // the answer, so a stale client notices on its next request
+    let body = json!({
+        "roles": roles_json,
+        "permissions": permissions_json,
+    });
+    let digest: String = Sha256::digest(body.to_string().as_bytes())
+        .iter()
+        .take(16)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let etag = format!("\"{}\"", digest);
+    let (etag_header, cache_control) = cache_headers(&etag);
+
+    if if_none_match.matches(&etag) {
+        return Ok(EffectivePermissions::NotModified {
+            inner: (),
+            etag: etag_header,
+            cache_control,
+        });
+    }
+
+    Ok(EffectivePermissions::Fresh {
+        inner: Json(json!({
+            "message": "Found permissions!",
+            "organization_id": organization_id,
+            "version": digest,
+            "roles": body["roles"],
+            "permissions": body["permissions"]
+        })),
+        etag: etag_header,
+        cache_control,
+    })
+}
@@ -1,26 +1,29 @@
 use rocket::serde::json::{Json, json};
 use rocket::State;
-use rocket::http::Status;
 use rocket::serde::json::Value;
 use sqlx::PgPool;
 use validator::Validate;
 
+use crate::middleware::request_context::RequestContext;
 use crate::middleware::verify_jwt::AuthenticatedUser;
 use crate::models::user::UpdateUser;
+use crate::routes::auth::verify_email::send_verification_link;
+use crate::utils::api_error::ApiError;
 use crate::utils::logger::{LogBuilder, LogAction, log_action};
+use crate::utils::mailer::Mailer;
 
 #[put("/email", format="json", data="<email_data>")]
 pub async fn update_email(
     user: AuthenticatedUser, 
     pool: &State<PgPool>, 
-    email_data: Json<UpdateUser>
-) -> Result<Json<Value>, Status> {
+    mailer: &State<Mailer>,
+    email_data: Json<UpdateUser>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
     let email = email_data.into_inner();
 
     // Validate the incoming email data
-    if email.validate().is_err() {
-        return Err(Status::BadRequest);
-    }
+    email.validate()?;
 
     // First, get the current email
     let current_user = sqlx::query!(
@@ -28,46 +31,51 @@ pub async fn update_email(
         user.user_id
     )
     .fetch_optional(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?
-    .ok_or(Status::NotFound)?;
+    .await?
+    .ok_or_else(|| ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."))?;
 
     // Perform the update
     let result = sqlx::query!(
         r#"
             UPDATE users
-            SET email = $1
+            SET email = $1, email_verified_at = NULL
             WHERE id = $2
         "#,
         email.email, 
         user.user_id
     )
     .execute(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
     // Check if any rows were updated
     if result.rows_affected() == 0 {
-        return Err(Status::NotFound);
+        return Err(ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."));
+    }
+
+    // The new address has to be verified again, the link is mailed after the response
+    if let Some(new_email) = &email.email {
+        rocket::tokio::spawn(send_verification_link(
+            pool.inner().clone(),
+            mailer.inner().clone(),
+            context.clone(),
+            user.user_id,
+            new_email.clone(),
+        ));
     }
 
     // Create detailed log entry
-    let log = LogBuilder::new(LogAction::Update, "user")
+    let log = LogBuilder::new(LogAction::Update, "user", &context)
         .with_user(user.user_id)
         .with_resource_id(user.user_id.to_string())
         .with_previous_state(&json!({
             "email": current_user.email
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_new_state(&json!({
             "email": email.email
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_additional_details(&json!({
-            "timestamp": chrono::Utc::now().to_rfc3339(),
-            "ip_address": "TODO: Add IP address capture"
-        }))
-        .map_err(|_| Status::InternalServerError)?
+            "timestamp": chrono::Utc::now().to_rfc3339()
+        }))?
         .build();
 
     let _ = log_action(pool.inner(), &log).await;
@@ -0,0 +1,104 @@
+use rocket::serde::json::{Json, json};
+use rocket::State;
+use rocket::serde::json::Value;
+use sqlx::PgPool;
+use validator::Validate;
+
+use crate::middleware::request_context::RequestContext;
+use crate::middleware::verify_jwt::AuthenticatedUser;
+use crate::models::user::ChangePasswordRequest;
+use crate::routes::auth::login::record_failed_login;
+use crate::utils::api_error::ApiError;
+use crate::utils::hashing::{hash_password, verify_password};
+use crate::utils::logger::{LogBuilder, LogAction, log_action};
+use crate::utils::login_attempts::{AttemptKey, LoginThrottle};
+use crate::utils::mailer::Mailer;
+use crate::utils::session::revoke_user_sessions;
+
+#[put("/password", format="json", data="<password_data>")]
+pub async fn update_password(
+    user: AuthenticatedUser,
+    pool: &State<PgPool>,
+    mailer: &State<Mailer>,
+    throttle: &State<LoginThrottle>,
+    password_data: Json<ChangePasswordRequest>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
+    let passwords = password_data.into_inner();
+
+    passwords.validate()?;
+
+    let current_user = sqlx::query!(
+        "SELECT email, password_hash FROM users WHERE id = $1",
+        user.user_id
+    )
+    .fetch_optional(pool.inner())
+    .await?
+    .ok_or_else(|| ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."))?;
+
+    // Guessing the current password counts towards the same lockout as a login
+    let attempt_keys = AttemptKey::for_login(&current_user.email, context.ip_address);
+    throttle.admit(&attempt_keys).await?;
+
+    // The current password must be known, a stolen session alone isn't enough
+    if !verify_password(&passwords.current_password, &current_user.password_hash)? {
+
+        let failed_log = LogBuilder::new(LogAction::Custom("password_change_failed".to_string()), "user", &context)
+            .with_user(user.user_id)
+            .with_resource_id(user.user_id.to_string())
+            .with_additional_details(&json!({
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "failure_reason": "invalid_current_password"
+            }))?
+            .build();
+
+        let _ = log_action(pool.inner(), &failed_log).await;
+
+        record_failed_login(pool.inner(), &context, mailer.inner(), throttle.inner(), &attempt_keys, &current_user.email, Some(user.user_id)).await?;
+        return Err(ApiError::forbidden("INVALID_CURRENT_PASSWORD", "The current password is incorrect."));
+    }
+
+    let password_hash = hash_password(&passwords.new_password)?;
+
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        "UPDATE users SET password_hash = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+        password_hash,
+        user.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."));
+    }
+
+    let sessions_revoked = if passwords.sign_out_other_sessions {
+        revoke_user_sessions(&mut *tx, user.user_id, Some(user.session_id))
+            .await?
+    } else {
+        0
+    };
+
+    tx.commit().await?;
+
+    throttle.clear(&AttemptKey::account(&current_user.email)).await?;
+
+    // Never log the passwords or their hashes
+    let log = LogBuilder::new(LogAction::Custom("password_changed".to_string()), "user", &context)
+        .with_user(user.user_id)
+        .with_resource_id(user.user_id.to_string())
+        .with_additional_details(&json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "signed_out_other_sessions": passwords.sign_out_other_sessions,
+            "sessions_revoked": sessions_revoked
+        }))?
+        .build();
+
+    let _ = log_action(pool.inner(), &log).await;
+
+    Ok(Json(json!({
+        "message": "Password successfully updated!"
+    })))
+}
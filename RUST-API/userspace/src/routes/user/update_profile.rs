@@ -1,28 +1,28 @@
 use rocket::serde::json::{Json, json, Value};
 use rocket::serde::json::serde_json::Map;
 use rocket::State;
-use rocket::http::Status;
 use sqlx::PgPool;
 use validator::Validate;
 use sqlx::postgres::PgArguments;
 use sqlx::Arguments;
 
+use crate::middleware::request_context::RequestContext;
 use crate::middleware::verify_jwt::AuthenticatedUser;
 use crate::models::user_profile::UpdateUserProfile;
+use crate::utils::api_error::ApiError;
 use crate::utils::logger::{LogBuilder, LogAction, log_action};
 
 #[put("/profile", format = "json", data = "<profile_data>")]
 pub async fn update_profile(
     user: AuthenticatedUser,
     pool: &State<PgPool>,
-    profile_data: Json<UpdateUserProfile>
-) -> Result<Json<Value>, Status> {
+    profile_data: Json<UpdateUserProfile>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
     let profile = profile_data.into_inner();
 
     // Validate the incoming profile data
-    if profile.validate().is_err() {
-        return Err(Status::BadRequest);
-    }
+    profile.validate()?;
 
     // Get current profile first
     let current_profile = sqlx::query!(
@@ -36,9 +36,8 @@ pub async fn update_profile(
         user.user_id
     )
     .fetch_optional(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?
-    .ok_or(Status::NotFound)?;
+    .await?
+    .ok_or_else(|| ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."))?;
 
     // Handle social links merging
     let merged_social_links = if let Some(ref new_links) = profile.social_links {
@@ -128,15 +127,14 @@ pub async fn update_profile(
     // Perform the update
     let result = sqlx::query_with(&query, args)
         .execute(pool.inner())
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+        .await?;
 
     if result.rows_affected() == 0 {
-        return Err(Status::NotFound);
+        return Err(ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."));
     }
 
     // Create detailed log entry
-    let log = LogBuilder::new(LogAction::Update, "user_profile")
+    let log = LogBuilder::new(LogAction::Update, "user_profile", &context)
         .with_user(user.user_id)
         .with_resource_id(user.user_id.to_string())
         .with_previous_state(&json!({
@@ -146,8 +144,7 @@ pub async fn update_profile(
             "language": current_profile.language,
             "timezone": current_profile.timezone,
             "social_links": current_profile.social_links.clone()
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_new_state(&json!({
             "display_name": profile_for_log.display_name,
             "bio": profile_for_log.bio,
@@ -155,13 +152,10 @@ pub async fn update_profile(
             "language": profile_for_log.language,
             "timezone": profile_for_log.timezone,
             "social_links": profile_for_log.social_links
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_additional_details(&json!({
-            "timestamp": chrono::Utc::now().to_rfc3339(),
-            "ip_address": "TODO: Add IP address capture"
-        }))
-        .map_err(|_| Status::InternalServerError)?
+            "timestamp": chrono::Utc::now().to_rfc3339()
+        }))?
         .build();
 
     let _ = log_action(pool.inner(), &log).await;
@@ -1,25 +1,25 @@
 use rocket::serde::json::{Json, json};
 use rocket::State;
-use rocket::http::Status;
 use rocket::serde::json::Value;
 use sqlx::PgPool;
 use validator::Validate;
 
+use crate::middleware::request_context::RequestContext;
 use crate::middleware::verify_jwt::AuthenticatedUser;
 use crate::models::user::UpdateUser;
+use crate::utils::api_error::ApiError;
 use crate::utils::logger::{LogBuilder, LogAction, log_action};
 
 #[put("/username", format="json", data="<username_data>")]
 pub async fn update_username(
     user: AuthenticatedUser, 
     pool: &State<PgPool>, 
-    username_data: Json<UpdateUser>
-) -> Result<Json<Value>, Status> {
+    username_data: Json<UpdateUser>,
+    context: RequestContext
+) -> Result<Json<Value>, ApiError> {
     let username = username_data.into_inner();
 
-    if username.validate().is_err() {
-        return Err(Status::BadRequest);
-    }
+    username.validate()?;
 
     // Get current username first
     let current_user = sqlx::query!(
@@ -27,9 +27,8 @@ pub async fn update_username(
         user.user_id
     )
     .fetch_optional(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?
-    .ok_or(Status::NotFound)?;
+    .await?
+    .ok_or_else(|| ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."))?;
 
     let result = sqlx::query!(
         "UPDATE users SET username = $1 WHERE id = $2",
@@ -37,25 +36,22 @@ pub async fn update_username(
         user.user_id
     )
     .execute(pool.inner())
-    .await
-    .map_err(|_| Status::InternalServerError)?;
+    .await?;
 
     if result.rows_affected() == 0 {
-        return Err(Status::NotFound);
+        return Err(ApiError::not_found("USER_NOT_FOUND", "The account no longer exists."));
     }
 
     // Enhanced logging
-    let log = LogBuilder::new(LogAction::Update, "user")
+    let log = LogBuilder::new(LogAction::Update, "user", &context)
         .with_user(user.user_id)
         .with_resource_id(user.user_id.to_string())
         .with_previous_state(&json!({
             "username": current_user.username
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .with_new_state(&json!({
             "username": username.username
-        }))
-        .map_err(|_| Status::InternalServerError)?
+        }))?
         .build();
 
     let _ = log_action(pool.inner(), &log).await;
@@ -0,0 +1,431 @@
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::serde_json::{self, Map};
+use rocket::serde::json::{json, Json, Value};
+use thiserror::Error;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::middleware::request_id::RequestId;
+
+/// Error returned by route handlers. Every variant is sent as the same JSON
+/// body as the catchers, with a machine-readable `code` clients can branch on:
+///
+/// ```json
+/// {
+///     "status": 409,
+///     "error": { "message": "...", "details": "...", "code": "EMAIL_TAKEN" },
+///     "request_id": "...",
+///     "timestamp": "2025-04-05T09:00:00+00:00"
+/// }
+/// ```
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("{message}")]
+    BadRequest { code: &'static str, message: String },
+    /// A request body failed validation, `details` lists the failures per field
+    #[error("Validation failed: {0}")]
+    Validation(#[from] ValidationErrors),
+    #[error("{message}")]
+    Unauthorized { code: &'static str, message: String },
+    #[error("{message}")]
+    Forbidden { code: &'static str, message: String },
+    #[error("{message}")]
+    NotFound { code: &'static str, message: String },
+    #[error("{message}")]
+    Conflict { code: &'static str, message: String },
+    /// Sent with a `Retry-After` header, in seconds
+    #[error("{message}")]
+    TooManyRequests { code: &'static str, message: String, retry_after: u64 },
+    /// Missing rows become 404 and unique violations 409, anything else 500
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error("Password hashing failed: {0}")]
+    Hashing(argon2::password_hash::Error),
+    /// Tokens that can't be decoded become 401, failing to create one is a 500
+    #[error(transparent)]
+    Token(#[from] jsonwebtoken::errors::Error),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+/// Unique constraints clients want to tell apart, with their code and message.
+/// Other unique violations are reported as `ALREADY_EXISTS`
+const UNIQUE_CONSTRAINTS: &[(&str, &str, &str)] = &[
+    ("users_email_key", "EMAIL_TAKEN", "An account with this email already exists."),
+    ("users_username_key", "USERNAME_TAKEN", "This username is already taken."),
+    ("roles_name_key", "ROLE_NAME_TAKEN", "A role with this name already exists."),
+    ("permissions_name_key", "PERMISSION_NAME_TAKEN", "A permission with this name already exists."),
+    ("organizations_slug_key", "SLUG_TAKEN", "An organization with this slug already exists."),
+    ("webauthn_credentials_credential_id_key", "PASSKEY_EXISTS", "This passkey is already registered."),
+];
+
+impl ApiError {
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::BadRequest { code, message: message.into() }
+    }
+
+    pub fn unauthorized(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Unauthorized { code, message: message.into() }
+    }
+
+    pub fn forbidden(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Forbidden { code, message: message.into() }
+    }
+
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::NotFound { code, message: message.into() }
+    }
+
+    pub fn conflict(code: &'static str, message: impl Into<String>) -> Self {
+        ApiError::Conflict { code, message: message.into() }
+    }
+
+    pub fn too_many_requests(code: &'static str, message: impl Into<String>, retry_after: u64) -> Self {
+        ApiError::TooManyRequests { code, message: message.into(), retry_after }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::BadRequest { .. } | ApiError::Validation(_) => Status::BadRequest,
+            ApiError::Unauthorized { .. } => Status::Unauthorized,
+            ApiError::Forbidden { .. } => Status::Forbidden,
+            ApiError::NotFound { .. } => Status::NotFound,
+            ApiError::Conflict { .. } => Status::Conflict,
+            ApiError::TooManyRequests { .. } => Status::TooManyRequests,
+            ApiError::Database(sqlx::Error::RowNotFound) => Status::NotFound,
+            ApiError::Database(sqlx::Error::Database(err)) if err.is_unique_violation() => Status::Conflict,
+            ApiError::Token(err) if is_invalid_token(err) => Status::Unauthorized,
+            ApiError::Database(_) | ApiError::Hashing(_) | ApiError::Token(_) | ApiError::Internal(_) => {
+                Status::InternalServerError
+            }
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest { code, .. }
+            | ApiError::Unauthorized { code, .. }
+            | ApiError::Forbidden { code, .. }
+            | ApiError::NotFound { code, .. }
+            | ApiError::Conflict { code, .. }
+            | ApiError::TooManyRequests { code, .. } => code,
+            ApiError::Validation(_) => "VALIDATION_FAILED",
+            ApiError::Token(err) if matches!(err.kind(), jsonwebtoken::errors::ErrorKind::ExpiredSignature) => {
+                "TOKEN_EXPIRED"
+            }
+            _ => match (self.unique_constraint(), self.status().code) {
+                (Some((_, code, _)), _) => code,
+                (None, 404) => "RESOURCE_NOT_FOUND",
+                (None, 409) => "ALREADY_EXISTS",
+                (None, 401) => "INVALID_TOKEN",
+                _ => "INTERNAL_SERVER_ERROR",
+            },
+        }
+    }
+
+    /// What the client is told. Internal causes stay in the server log
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::Validation(_) => "The request body is invalid.".to_string(),
+            ApiError::BadRequest { message, .. }
+            | ApiError::Unauthorized { message, .. }
+            | ApiError::Forbidden { message, .. }
+            | ApiError::NotFound { message, .. }
+            | ApiError::Conflict { message, .. }
+            | ApiError::TooManyRequests { message, .. } => message.clone(),
+            _ => match (self.unique_constraint(), self.status().code) {
+                (Some((_, _, message)), _) => message.to_string(),
+                (None, 404) => "The resource doesn't exist.".to_string(),
+                (None, 409) => "A resource with the same unique value already exists.".to_string(),
+                (None, 401) => "The token is invalid or has expired.".to_string(),
+                _ => "Something went wrong on our end.".to_string(),
+            },
+        }
+    }
+
+    fn unique_constraint(&self) -> Option<&'static (&'static str, &'static str, &'static str)> {
+        match self {
+            ApiError::Database(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                let constraint = err.constraint()?;
+                UNIQUE_CONSTRAINTS.iter().find(|(name, _, _)| *name == constraint)
+            }
+            _ => None,
+        }
+    }
+
+    fn details(&self) -> Value {
+        match self {
+            ApiError::Validation(errors) => {
+                let mut fields = Map::new();
+                collect_field_errors(errors, "", &mut fields);
+                Value::Object(fields)
+            }
+            _ => json!(default_details(self.status())),
+        }
+    }
+}
+
+/// Rocket's own statuses (guard failures, helpers that still return `Status`)
+/// get the generic code for that status
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        match status.code {
+            400 => ApiError::bad_request("BAD_REQUEST", "The request couldn't be understood."),
+            401 => ApiError::unauthorized("AUTH_REQUIRED", "You need to sign in first."),
+            403 => ApiError::forbidden("PERMISSION_DENIED", "You're not allowed to do that."),
+            404 => ApiError::not_found("RESOURCE_NOT_FOUND", "The resource doesn't exist."),
+            409 => ApiError::conflict("CONFLICT", "The request conflicts with the current state."),
+            _ => ApiError::Internal(anyhow::anyhow!("Handler returned status {}", status)),
+        }
+    }
+}
+
+impl From<argon2::password_hash::Error> for ApiError {
+    fn from(err: argon2::password_hash::Error) -> Self {
+        ApiError::Hashing(err)
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> Self {
+        ApiError::Internal(err.into())
+    }
+}
+
+impl From<webauthn_rs::prelude::WebauthnError> for ApiError {
+    fn from(err: webauthn_rs::prelude::WebauthnError) -> Self {
+        ApiError::Internal(err.into())
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+
+        if status == Status::InternalServerError {
+            eprintln!("[{}] {} {} failed: {}", RequestId::of(request), request.method(), request.uri(), self);
+        }
+
+        let body = error_body(request, status, self.code(), &self.message(), self.details());
+        let mut response = (status, Json(body)).respond_to(request)?;
+
+        if let ApiError::TooManyRequests { retry_after, .. } = self {
+            response.set_raw_header("Retry-After", retry_after.to_string());
+        }
+
+        Ok(response)
+    }
+}
+
+/// The JSON error body shared by `ApiError` and the catchers
+pub fn error_body(request: &Request<'_>, status: Status, code: &str, message: &str, details: Value) -> Value {
+    json!({
+        "status": status.code,
+        "error": {
+            "message": message,
+            "details": details,
+            "code": code
+        },
+        "request_id": RequestId::of(request),
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    })
+}
+
+fn default_details(status: Status) -> &'static str {
+    match status.code {
+        400 => "Check the request and try again.",
+        401 => "Check your credentials and sign in again.",
+        403 => "Ask an administrator for access.",
+        404 => "Check the URL and try again.",
+        409 => "Change the conflicting value, or reload the resource and try again.",
+        429 => "Wait until the time given in the Retry-After header before trying again.",
+        _ => "Our team has been notified and is working on it.",
+    }
+}
+
+fn is_invalid_token(err: &jsonwebtoken::errors::Error) -> bool {
+    use jsonwebtoken::errors::ErrorKind;
+
+    matches!(
+        err.kind(),
+        ErrorKind::InvalidToken
+            | ErrorKind::InvalidSignature
+            | ErrorKind::InvalidAlgorithm
+            | ErrorKind::ExpiredSignature
+            | ErrorKind::ImmatureSignature
+            | ErrorKind::InvalidIssuer
+            | ErrorKind::InvalidAudience
+            | ErrorKind::InvalidSubject
+            | ErrorKind::MissingRequiredClaim(_)
+            | ErrorKind::Base64(_)
+            | ErrorKind::Json(_)
+            | ErrorKind::Utf8(_)
+    )
+}
+
+/// Flatten nested validation errors into `"field": [{ "code", "message", "params" }]`,
+/// with nested fields joined by dots and list items by their index
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, fields: &mut Map<String, Value>) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() { field.to_string() } else { format!("{}.{}", prefix, field) };
+
+        match kind {
+            ValidationErrorsKind::Field(field_errors) => {
+                let entries: Vec<Value> = field_errors
+                    .iter()
+                    .map(|error| {
+                        // The rejected value is left out, it may be a password
+                        let params: Map<String, Value> = error
+                            .params
+                            .iter()
+                            .filter(|(name, _)| *name != "value")
+                            .map(|(name, value)| (name.to_string(), value.clone()))
+                            .collect();
+
+                        json!({
+                            "code": error.code,
+                            "message": error.message,
+                            "params": params
+                        })
+                    })
+                    .collect();
+                fields.insert(path, Value::Array(entries));
+            }
+            ValidationErrorsKind::Struct(nested) => collect_field_errors(nested, &path, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    collect_field_errors(nested, &format!("{}[{}]", path, index), fields);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::errors::{Error as JwtError, ErrorKind};
+    use sqlx::PgPool;
+    use validator::{Validate, ValidationError};
+
+    #[derive(Validate)]
+    struct Signup {
+        #[validate(email)]
+        email: String,
+        #[validate]
+        profile: Profile,
+    }
+
+    #[derive(Validate)]
+    struct Profile {
+        #[validate(length(min = 1))]
+        display_name: String,
+    }
+
+    #[test]
+    fn constructed_errors_keep_their_code() {
+        let cases = [
+            (ApiError::bad_request("INVALID_SCOPE", "."), Status::BadRequest, "INVALID_SCOPE"),
+            (ApiError::unauthorized("INVALID_CREDENTIALS", "."), Status::Unauthorized, "INVALID_CREDENTIALS"),
+            (ApiError::forbidden("ROLE_NOT_ASSIGNABLE", "."), Status::Forbidden, "ROLE_NOT_ASSIGNABLE"),
+            (ApiError::not_found("USER_NOT_FOUND", "."), Status::NotFound, "USER_NOT_FOUND"),
+            (ApiError::conflict("EMAIL_TAKEN", "."), Status::Conflict, "EMAIL_TAKEN"),
+            (ApiError::too_many_requests("TOO_MANY_ATTEMPTS", ".", 30), Status::TooManyRequests, "TOO_MANY_ATTEMPTS"),
+        ];
+
+        for (error, status, code) in cases {
+            assert_eq!(error.status(), status);
+            assert_eq!(error.code(), code);
+            assert_eq!(error.message(), ".");
+        }
+    }
+
+    #[test]
+    fn rocket_statuses_get_the_generic_code() {
+        let cases = [
+            (Status::BadRequest, "BAD_REQUEST"),
+            (Status::Unauthorized, "AUTH_REQUIRED"),
+            (Status::Forbidden, "PERMISSION_DENIED"),
+            (Status::NotFound, "RESOURCE_NOT_FOUND"),
+            (Status::Conflict, "CONFLICT"),
+        ];
+
+        for (status, code) in cases {
+            let error = ApiError::from(status);
+            assert_eq!(error.status(), status);
+            assert_eq!(error.code(), code);
+        }
+
+        let error = ApiError::from(Status::ServiceUnavailable);
+        assert_eq!(error.status(), Status::InternalServerError);
+        assert_eq!(error.code(), "INTERNAL_SERVER_ERROR");
+    }
+
+    #[test]
+    fn token_errors_are_401_unless_creating_one_failed() {
+        let expired = ApiError::from(JwtError::from(ErrorKind::ExpiredSignature));
+        assert_eq!(expired.status(), Status::Unauthorized);
+        assert_eq!(expired.code(), "TOKEN_EXPIRED");
+
+        let invalid = ApiError::from(JwtError::from(ErrorKind::InvalidSignature));
+        assert_eq!(invalid.status(), Status::Unauthorized);
+        assert_eq!(invalid.code(), "INVALID_TOKEN");
+
+        let unsigned = ApiError::from(JwtError::from(ErrorKind::InvalidRsaKey("".to_string())));
+        assert_eq!(unsigned.status(), Status::InternalServerError);
+        assert_eq!(unsigned.code(), "INTERNAL_SERVER_ERROR");
+    }
+
+    #[test]
+    fn internal_causes_arent_sent_to_the_client() {
+        let error = ApiError::from(anyhow::anyhow!("connection refused on 10.0.0.3"));
+        assert_eq!(error.status(), Status::InternalServerError);
+        assert_eq!(error.code(), "INTERNAL_SERVER_ERROR");
+        assert_eq!(error.message(), "Something went wrong on our end.");
+
+        let missing = ApiError::from(sqlx::Error::RowNotFound);
+        assert_eq!(missing.status(), Status::NotFound);
+        assert_eq!(missing.code(), "RESOURCE_NOT_FOUND");
+    }
+
+    #[test]
+    fn validation_failures_are_listed_per_field() {
+        let signup = Signup {
+            email: "not an email".to_string(),
+            profile: Profile { display_name: String::new() },
+        };
+        let error = ApiError::from(signup.validate().unwrap_err());
+
+        assert_eq!(error.status(), Status::BadRequest);
+        assert_eq!(error.code(), "VALIDATION_FAILED");
+
+        let details = error.details();
+        assert_eq!(details["email"][0]["code"], "email");
+        assert_eq!(details["profile.display_name"][0]["code"], "length");
+        // The rejected value may be a password, it's never echoed back
+        assert!(details["profile.display_name"][0]["params"].get("value").is_none());
+    }
+
+    #[test]
+    fn list_items_are_named_by_index() {
+        let mut item = ValidationErrors::new();
+        item.add("name", ValidationError::new("length"));
+        let mut errors = ValidationErrors::new();
+        errors.errors_mut().insert("roles", ValidationErrorsKind::List([(2, Box::new(item))].into()));
+
+        let details = ApiError::from(errors).details();
+        assert_eq!(details["roles[2].name"][0]["code"], "length");
+    }
+
+    #[sqlx::test]
+    async fn unique_violations_are_named_after_their_constraint(pool: PgPool) {
+        let insert = || sqlx::query("INSERT INTO roles (name) VALUES ('Duplicate')").execute(&pool);
+        insert().await.unwrap();
+
+        let error = ApiError::from(insert().await.unwrap_err());
+        assert_eq!(error.status(), Status::Conflict);
+        assert_eq!(error.code(), "ROLE_NAME_TAKEN");
+        assert_eq!(error.message(), "A role with this name already exists.");
+    }
+}
@@ -1,22 +1,190 @@
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use uuid::Uuid;
 use rocket::http::Status;
 
-/// Check if a user is an admin
-pub async fn is_admin(pool: &PgPool, user_id: Uuid) -> Result<bool, Status> {
-    let is_admin = sqlx::query_scalar!(
+use crate::utils::scope::{action_matches, grant_covers, scope_matches, Resource};
+
+/// The role and grant through which a user holds a permission
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub role_id: Uuid,
+    pub role_name: String,
+    /// `global` for roles held everywhere, `organization` for roles held in the organization
+    pub source: String,
+    /// Whether the role holds the permission through one of its ancestors
+    pub inherited: bool,
+    /// The granted permission, which may be a wildcard covering the one asked for
+    pub permission: String,
+    pub resource_type: String,
+    pub resource_id: String,
+}
+
+/// Every grant a user holds through their active global roles, their roles
+/// inside `organization_id` when given, and every role those inherit from,
+/// closest role first
+pub async fn effective_grants(
+    pool: &PgPool,
+    user_id: Uuid,
+    organization_id: Option<Uuid>,
+) -> Result<Vec<Grant>, Status> {
+    // Depth is capped so the walk ends even if a cycle ever slips in
+    let grants = sqlx::query!(
+        r#"
+        WITH RECURSIVE role_tree (role_id, source, depth) AS (
+            SELECT role_id, 'global'::text, 0 FROM user_roles
+            WHERE user_id = $1
+            AND (starts_at IS NULL OR starts_at <= CURRENT_TIMESTAMP)
+            AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            UNION
+            SELECT role_id, 'organization'::text, 0 FROM organization_member_roles
+            WHERE user_id = $1 AND organization_id = $2
+            UNION
+            SELECT r.parent_id, rt.source, rt.depth + 1
+            FROM roles r
+            JOIN role_tree rt ON r.id = rt.role_id
+            WHERE r.parent_id IS NOT NULL AND rt.depth < 32
+        )
+        SELECT r.id, r.name, rt.source AS "source!", rt.depth AS "depth!",
+               p.name AS permission, rp.resource_type, rp.resource_id
+        FROM role_tree rt
+        JOIN roles r ON rt.role_id = r.id
+        JOIN role_permissions rp ON rt.role_id = rp.role_id
+        JOIN permissions p ON rp.permission_id = p.id
+        ORDER BY rt.depth, p.name, rp.resource_type, rp.resource_id, r.name
+        "#,
+        user_id,
+        organization_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|_| Status::InternalServerError)?;
+
+    Ok(grants
+        .into_iter()
+        .map(|grant| Grant {
+            role_id: grant.id,
+            role_name: grant.name,
+            source: grant.source,
+            inherited: grant.depth > 0,
+            permission: grant.permission,
+            resource_type: grant.resource_type,
+            resource_id: grant.resource_id,
+        })
+        .collect())
+}
+
+/// Find the closest role through which a user holds a permission on a
+/// resource, see [`effective_grants`]. Without a resource only unscoped
+/// grants count
+pub async fn find_grant(
+    pool: &PgPool,
+    user_id: Uuid,
+    organization_id: Option<Uuid>,
+    permission: &str,
+    resource: Option<&Resource>,
+) -> Result<Option<Grant>, Status> {
+    Ok(effective_grants(pool, user_id, organization_id)
+        .await?
+        .into_iter()
+        .find(|grant| {
+            action_matches(&grant.permission, permission)
+                && scope_matches(&grant.resource_type, &grant.resource_id, resource)
+        }))
+}
+
+/// Check if a user holds a permission through any of their active roles or
+/// the roles those inherit from
+pub async fn has_permission(pool: &PgPool, user_id: Uuid, permission: &str) -> Result<bool, Status> {
+    Ok(find_grant(pool, user_id, None, permission, None).await?.is_some())
+}
+
+/// Check if a user holds a permission inside an organization, through the
+/// roles they hold in it or their global roles, including inherited ones
+pub async fn has_org_permission(
+    pool: &PgPool,
+    user_id: Uuid,
+    organization_id: Uuid,
+    permission: &str,
+) -> Result<bool, Status> {
+    Ok(find_grant(pool, user_id, Some(organization_id), permission, None).await?.is_some())
+}
+
+/// The grants a role hands out, its own and those of every role it inherits
+/// from, as (permission, resource type, resource id)
+pub async fn role_grants(pool: &PgPool, role_id: Uuid) -> Result<Vec<(String, String, String)>, Status> {
+    let grants = sqlx::query!(
+        r#"
+        WITH RECURSIVE role_tree (role_id, depth) AS (
+            SELECT $1::uuid, 0
+            UNION
+            SELECT r.parent_id, rt.depth + 1
+            FROM roles r
+            JOIN role_tree rt ON r.id = rt.role_id
+            WHERE r.parent_id IS NOT NULL AND rt.depth < 32
+        )
+        SELECT DISTINCT p.name AS permission, rp.resource_type, rp.resource_id
+        FROM role_tree rt
+        JOIN role_permissions rp ON rt.role_id = rp.role_id
+        JOIN permissions p ON rp.permission_id = p.id
+        "#,
+        role_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|_| Status::InternalServerError)?;
+
+    Ok(grants
+        .into_iter()
+        .map(|grant| (grant.permission, grant.resource_type, grant.resource_id))
+        .collect())
+}
+
+/// Whether `held` covers every one of `wanted`, each as (permission,
+/// resource type, resource id)
+pub fn covers_all(held: &[Grant], wanted: &[(String, String, String)]) -> bool {
+    wanted.iter().all(|(permission, resource_type, resource_id)| {
+        held.iter().any(|grant| {
+            grant_covers(
+                (&grant.permission, &grant.resource_type, &grant.resource_id),
+                (permission, resource_type, resource_id),
+            )
+        })
+    })
+}
+
+/// Whether a user's global grants cover everything `role_id` hands out, so
+/// nobody gives out more than they hold themselves
+pub async fn holds_role_grants(pool: &PgPool, user_id: Uuid, role_id: Uuid) -> Result<bool, Status> {
+    let held = effective_grants(pool, user_id, None).await?;
+
+    Ok(covers_all(&held, &role_grants(pool, role_id).await?))
+}
+
+/// Check if making `parent_id` the parent of `role_id` would close a loop,
+/// i.e. whether `role_id` is already among the ancestors of `parent_id`
+pub async fn creates_role_cycle<'e, E: PgExecutor<'e>>(
+    executor: E,
+    role_id: Uuid,
+    parent_id: Uuid,
+) -> Result<bool, Status> {
+    let creates_cycle = sqlx::query_scalar!(
         r#"
-        SELECT EXISTS (
-            SELECT 1 FROM user_roles ur
-            JOIN roles r ON ur.role_id = r.id
-            WHERE ur.user_id = $1 AND r.name = 'Admin'
+        WITH RECURSIVE ancestors (id) AS (
+            SELECT $2::uuid
+            UNION
+            SELECT r.parent_id
+            FROM roles r
+            JOIN ancestors a ON r.id = a.id
+            WHERE r.parent_id IS NOT NULL
         )
+        SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = $1)
         "#,
-        user_id
+        role_id,
+        parent_id
     )
-    .fetch_one(pool)
+    .fetch_one(executor)
     .await
     .map_err(|_| Status::InternalServerError)?;
 
-    Ok(is_admin.unwrap_or(false))
+    Ok(creates_cycle.unwrap_or(false))
 }
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rocket::serde::json::{json, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::authz::AuthzCheck;
+use crate::utils::api_error::ApiError;
+use crate::utils::auth::find_grant;
+use crate::utils::scope::{format_grant, Resource};
+
+/// Past this many entries, expired decisions are dropped on the next insert
+const CACHE_PRUNE_THRESHOLD: usize = 10_000;
+
+type CacheKey = (Uuid, String, Option<Uuid>, Option<Resource>);
+
+/// Short-lived cache of authorization decisions. Handlers that change roles,
+/// assignments or permissions drop the decisions they affect; assignments
+/// running out take up to the TTL to show up in answers, which is the price
+/// of not hitting the database for every check
+pub struct AuthzCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<CacheKey, (Instant, Value)>>,
+}
+
+impl AuthzCache {
+    /// TTL from `AUTHZ_CACHE_TTL_SECS` (default 30), `0` disables caching
+    pub fn from_env() -> Self {
+        let seconds = std::env::var("AUTHZ_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+
+        AuthzCache {
+            ttl: Duration::from_secs(seconds),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Drop the decisions about one user, after their roles change
+    pub fn forget_user(&self, user_id: Uuid) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|(cached_user_id, ..), _| *cached_user_id != user_id);
+        }
+    }
+
+    /// Drop every decision, after a change to a role or permission that can
+    /// reach any number of users
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Value> {
+        let entries = self.entries.lock().ok()?;
+
+        entries
+            .get(key)
+            .filter(|(expires_at, _)| *expires_at > Instant::now())
+            .map(|(_, decision)| decision.clone())
+    }
+
+    fn insert(&self, key: CacheKey, decision: Value) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() >= CACHE_PRUNE_THRESHOLD {
+                let now = Instant::now();
+                entries.retain(|_, (expires_at, _)| *expires_at > now);
+            }
+
+            entries.insert(key, (Instant::now() + self.ttl, decision));
+        }
+    }
+}
+
+/// Decide whether the user may perform the action, with the reason:
+/// `granted_by_role`, `unknown_user` or `no_matching_grant`
+pub async fn decide(pool: &PgPool, cache: &AuthzCache, check: &AuthzCheck) -> Result<Value, ApiError> {
+    let key = (check.user_id, check.action.clone(), check.organization_id, check.resource.clone());
+
+    if let Some(decision) = cache.get(&key) {
+        return Ok(decision);
+    }
+
+    let user_exists = sqlx::query_scalar!(
+        "SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)",
+        check.user_id
+    )
+    .fetch_one(pool)
+    .await?
+    .unwrap_or(false);
+
+    let grant = if user_exists {
+        find_grant(pool, check.user_id, check.organization_id, &check.action, check.resource.as_ref()).await?
+    } else {
+        None
+    };
+
+    let decision = match (&grant, user_exists) {
+        (Some(grant), _) => json!({
+            "user_id": check.user_id,
+            "action": check.action,
+            "organization_id": check.organization_id,
+            "resource": check.resource,
+            "decision": "allow",
+            "reason": "granted_by_role",
+            "role": {
+                "id": grant.role_id,
+                "name": grant.role_name,
+                "source": grant.source,
+                "inherited": grant.inherited
+            },
+            "grant": format_grant(&grant.resource_type, &grant.resource_id, &grant.permission)
+        }),
+        (None, false) => json!({
+            "user_id": check.user_id,
+            "action": check.action,
+            "organization_id": check.organization_id,
+            "resource": check.resource,
+            "decision": "deny",
+            "reason": "unknown_user"
+        }),
+        (None, true) => json!({
+            "user_id": check.user_id,
+            "action": check.action,
+            "organization_id": check.organization_id,
+            "resource": check.resource,
+            "decision": "deny",
+            "reason": "no_matching_grant"
+        }),
+    };
+
+    cache.insert(key, decision.clone());
+
+    Ok(decision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache() -> AuthzCache {
+        AuthzCache {
+            ttl: Duration::from_secs(30),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn key(user_id: Uuid, action: &str) -> CacheKey {
+        (user_id, action.to_string(), None, None)
+    }
+
+    #[test]
+    fn forgetting_a_user_keeps_other_users_decisions() {
+        let cache = cache();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        cache.insert(key(alice, "documents.read"), json!("allow"));
+        cache.insert(key(alice, "documents.edit"), json!("deny"));
+        cache.insert(key(bob, "documents.read"), json!("allow"));
+
+        cache.forget_user(alice);
+
+        assert!(cache.get(&key(alice, "documents.read")).is_none());
+        assert!(cache.get(&key(alice, "documents.edit")).is_none());
+        assert_eq!(cache.get(&key(bob, "documents.read")), Some(json!("allow")));
+
+        cache.clear();
+        assert!(cache.get(&key(bob, "documents.read")).is_none());
+    }
+}
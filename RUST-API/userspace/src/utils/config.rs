@@ -0,0 +1,7 @@
+/// Whether an on/off environment variable is set to `1`, `true` or `yes`
+/// (any case). Unset or anything else is off
+pub fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
@@ -3,21 +3,41 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use anyhow::Result;
 
+use crate::middleware::cors::ClientType;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
     pub exp: usize,
+    /// Id of the server-side session this token belongs to
+    pub jti: Uuid,
+    /// Kind of client the session was opened from, see [`ClientType::as_str`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
 }
 
-pub fn create_token(user_id: Uuid) -> Result<String> {
+/// How long an access token stays valid. Configurable through
+/// `ACCESS_TOKEN_TTL_MINUTES`, defaults to 15 minutes
+pub fn access_token_ttl() -> chrono::Duration {
+    let minutes = std::env::var("ACCESS_TOKEN_TTL_MINUTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(15);
+
+    chrono::Duration::minutes(minutes)
+}
+
+pub fn create_token(user_id: Uuid, session_id: Uuid, client_type: Option<ClientType>) -> Result<String> {
     let expiration = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::hours(24))
+        .checked_add_signed(access_token_ttl())
         .expect("valid timestamp")
         .timestamp() as usize;
 
     let claims = Claims {
         sub: user_id,
         exp: expiration,
+        jti: session_id,
+        client: client_type.map(|client_type| client_type.as_str().to_string()),
     };
 
     let token = encode(
@@ -31,4 +51,4 @@ pub fn create_token(user_id: Uuid) -> Result<String> {
     )?;
 
     Ok(token)
-}
\ No newline at end of file
+}
@@ -2,6 +2,12 @@ use argon2::{
     password_hash::{rand_core::OsRng, SaltString, Error},
     Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
 };
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// Hashed with the same parameters as real passwords, so checking it costs the same
+    static ref DUMMY_HASH: String = hash_password("not a real password").expect("hashing a fixed password");
+}
 
 pub fn hash_password(password: &str) -> Result<String, Error> {
     let salt = SaltString::generate(&mut OsRng);
@@ -16,4 +22,10 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, Error> {
     Ok(Argon2::default()
         .verify_password(password.as_bytes(), &parsed_hash)
         .is_ok())
+}
+
+/// Spend as long as `verify_password` would on a real account, for logins
+/// with an unknown email, so response times don't reveal which accounts exist
+pub fn verify_dummy_password(password: &str) {
+    let _ = verify_password(password, &DUMMY_HASH);
 }
\ No newline at end of file
@@ -0,0 +1,270 @@
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::models::invitation::CreateInvitation;
+use crate::utils::api_error::ApiError;
+use crate::utils::mailer::Email;
+use crate::utils::tokens::{generate_token, hash_token};
+
+/// Whether registration needs a valid invitation (`REGISTRATION_MODE=invite_only`)
+/// instead of being open to anyone (`open`, the default)
+pub fn invite_required() -> bool {
+    std::env::var("REGISTRATION_MODE")
+        .map(|mode| mode.eq_ignore_ascii_case("invite_only"))
+        .unwrap_or(false)
+}
+
+/// Expiry given to invitations that don't set one (`INVITATION_TTL_DAYS`,
+/// default 7). `0` leaves them open-ended
+pub fn invitation_ttl() -> Option<chrono::Duration> {
+    let days: i64 = std::env::var("INVITATION_TTL_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(7);
+
+    (days > 0).then(|| chrono::Duration::days(days))
+}
+
+/// A freshly stored invitation, with the raw code that is only known now
+pub struct CreatedInvitation {
+    pub id: Uuid,
+    pub code: String,
+    pub link: String,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// The link an invitee opens: organization invitations are accepted by a
+/// logged in user, global ones are used to register
+pub fn invitation_link(code: &str, organization_id: Option<Uuid>) -> String {
+    let base_url = std::env::var("APP_BASE_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+    let base_url = base_url.trim_end_matches('/');
+
+    match organization_id {
+        Some(_) => format!("{}/invitations/accept?token={}", base_url, code),
+        None => format!("{}/register?invite={}", base_url, code),
+    }
+}
+
+/// Store an invitation and the roles it grants. Only the hash of the code is kept
+pub async fn create_invitation(
+    pool: &PgPool,
+    organization_id: Option<Uuid>,
+    invitation: &CreateInvitation,
+    invited_by: Uuid,
+) -> Result<CreatedInvitation, ApiError> {
+    let code = generate_token();
+    let expires_at = invitation
+        .expires_at
+        .or_else(|| invitation_ttl().map(|ttl| chrono::Utc::now() + ttl));
+
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO invitations (organization_id, email, token_hash, invited_by, max_uses, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, expires_at, created_at
+        "#,
+        organization_id,
+        invitation.email,
+        hash_token(&code),
+        invited_by,
+        invitation.max_uses,
+        expires_at
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(err) if err.is_foreign_key_violation() => {
+            ApiError::not_found("ORGANIZATION_NOT_FOUND", "The organization doesn't exist.")
+        }
+        e => e.into(),
+    })?;
+
+    for role_id in &invitation.role_ids {
+        sqlx::query!(
+            r#"
+            INSERT INTO invitation_roles (invitation_id, role_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            result.id,
+            role_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(err) if err.is_foreign_key_violation() => {
+                ApiError::not_found("ROLE_NOT_FOUND", "One of the roles doesn't exist.")
+            }
+            e => e.into(),
+        })?;
+    }
+
+    tx.commit().await?;
+
+    Ok(CreatedInvitation {
+        id: result.id,
+        link: invitation_link(&code, organization_id),
+        code,
+        expires_at: result.expires_at,
+        created_at: result.created_at,
+    })
+}
+
+/// An invitation a user just redeemed
+pub struct RedeemedInvitation {
+    pub id: Uuid,
+    pub organization_id: Option<Uuid>,
+    pub role_ids: Vec<Uuid>,
+}
+
+/// Use up one redemption of the invitation with this code for the user and
+/// grant what it carries: membership and organization roles for organization
+/// invitations, global roles otherwise. Runs on the caller's transaction so
+/// nothing is kept if the rest of their work fails. With `organization_only`
+/// global invitations are refused, they can only be used to register.
+///
+/// Fails with `INVALID_INVITATION` for unknown, expired or used up codes,
+/// `INVITATION_EMAIL_MISMATCH` when the invitation is bound to another email
+/// and `INVITATION_ALREADY_REDEEMED` when the user already redeemed it
+pub async fn redeem_invitation(
+    conn: &mut PgConnection,
+    code: &str,
+    user_id: Uuid,
+    email: &str,
+    organization_only: bool,
+) -> Result<RedeemedInvitation, ApiError> {
+    // Counting the use in the same statement that checks it keeps
+    // concurrent redemptions from going over the limit
+    let invitation = sqlx::query!(
+        r#"
+        UPDATE invitations
+        SET use_count = use_count + 1
+        WHERE token_hash = $1
+        AND use_count < max_uses
+        AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+        AND (organization_id IS NOT NULL OR NOT $2)
+        RETURNING id, organization_id, email
+        "#,
+        hash_token(code),
+        organization_only
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| ApiError::bad_request("INVALID_INVITATION", "The invitation code is invalid, expired or used up."))?;
+
+    // A forwarded link doesn't let someone else in, in the invitee's place
+    if let Some(bound_email) = &invitation.email {
+        if !bound_email.eq_ignore_ascii_case(email) {
+            return Err(ApiError::forbidden("INVITATION_EMAIL_MISMATCH", "The invitation was sent to another email address."));
+        }
+    }
+
+    sqlx::query!(
+        "INSERT INTO invitation_redemptions (invitation_id, user_id) VALUES ($1, $2)",
+        invitation.id,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(err) if err.is_unique_violation() => {
+            ApiError::conflict("INVITATION_ALREADY_REDEEMED", "You already redeemed this invitation.")
+        }
+        e => e.into(),
+    })?;
+
+    let role_ids = sqlx::query_scalar!(
+        "SELECT role_id FROM invitation_roles WHERE invitation_id = $1",
+        invitation.id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    match invitation.organization_id {
+        Some(organization_id) => {
+            sqlx::query!(
+                r#"
+                INSERT INTO organization_members (organization_id, user_id)
+                VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+                "#,
+                organization_id,
+                user_id
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            for role_id in &role_ids {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO organization_member_roles (organization_id, user_id, role_id)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT DO NOTHING
+                    "#,
+                    organization_id,
+                    user_id,
+                    role_id
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        None => {
+            for role_id in &role_ids {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO user_roles (user_id, role_id)
+                    VALUES ($1, $2)
+                    ON CONFLICT DO NOTHING
+                    "#,
+                    user_id,
+                    role_id
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+    }
+
+    Ok(RedeemedInvitation {
+        id: invitation.id,
+        organization_id: invitation.organization_id,
+        role_ids,
+    })
+}
+
+/// The mail sent to someone invited, either to an organization or to register
+pub fn invitation_email(
+    email: &str,
+    organization_name: Option<&str>,
+    link: &str,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> Email {
+    let (subject, intro) = match organization_name {
+        Some(name) => (
+            format!("You've been invited to join {}", name),
+            format!("You've been invited to join {} on Userspace. Open the link below to accept:", name),
+        ),
+        None => (
+            "You've been invited to Userspace".to_string(),
+            "You've been invited to create an account on Userspace. Open the link below to sign up:".to_string(),
+        ),
+    };
+
+    let expiry = match expires_at {
+        Some(expires_at) => format!("The invitation expires on {}. ", expires_at.format("%Y-%m-%d %H:%M UTC")),
+        None => String::new(),
+    };
+
+    Email {
+        to: email.to_string(),
+        subject,
+        body: format!(
+            "{}\n\n{}\n\n{}If you weren't expecting it, you can ignore this message.",
+            intro, link, expiry
+        ),
+    }
+}
@@ -1,7 +1,9 @@
 use anyhow::{Context, Result};
 use chrono::Utc;
 use rocket::serde::json::{json, Value};
+use crate::middleware::request_context::RequestContext;
 use crate::models::log::{Log, CreateLog};
+use std::fmt;
 use uuid::Uuid;
 use validator::Validate;
 
@@ -13,14 +15,14 @@ pub enum LogAction {
     Custom(String),
 }
 
-impl ToString for LogAction {
-    fn to_string(&self) -> String {
+impl fmt::Display for LogAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            LogAction::Create => "create".to_string(),
-            LogAction::Update => "update".to_string(),
-            LogAction::Delete => "delete".to_string(),
-            LogAction::Read => "read".to_string(),
-            LogAction::Custom(s) => s.clone(),
+            LogAction::Create => write!(f, "create"),
+            LogAction::Update => write!(f, "update"),
+            LogAction::Delete => write!(f, "delete"),
+            LogAction::Read => write!(f, "read"),
+            LogAction::Custom(s) => write!(f, "{}", s),
         }
     }
 }
@@ -33,10 +35,21 @@ pub struct LogBuilder {
     previous_state: Option<Value>,
     new_state: Option<Value>,
     additional_details: Option<Value>,
+    context: Option<RequestContext>,
 }
 
 impl LogBuilder {
-    pub fn new(action: LogAction, resource_type: &str) -> Self {
+    /// An entry for a request, recording where it came from: IP, user agent,
+    /// client type, app version and request id
+    pub fn new(action: LogAction, resource_type: &str, context: &RequestContext) -> Self {
+        LogBuilder {
+            context: Some(context.clone()),
+            ..LogBuilder::system(action, resource_type)
+        }
+    }
+
+    /// An entry the server or the admin CLI writes on its own, outside of any request
+    pub fn system(action: LogAction, resource_type: &str) -> Self {
         LogBuilder {
             user_id: None,
             action,
@@ -45,6 +58,7 @@ impl LogBuilder {
             previous_state: None,
             new_state: None,
             additional_details: None,
+            context: None,
         }
     }
 
@@ -97,8 +111,9 @@ impl LogBuilder {
 
         CreateLog {
             user_id: self.user_id,
-            action: format!("{}_{}", self.resource_type, self.action.to_string()),
+            action: format!("{}_{}", self.resource_type, self.action),
             details,
+            context: self.context,
         }
     }
 }
@@ -118,16 +133,23 @@ pub async fn log_action(
     create_log.validate()
         .context("Failed to validate log entry")?;
 
+    let context = create_log.context.as_ref();
+
     let result = sqlx::query!(
         r#"
-        INSERT INTO logs (id, user_id, action, details, created_at)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO logs (id, user_id, action, details, created_at, ip_address, user_agent, client_type, app_version, request_id)
+        VALUES ($1, $2, $3, $4, $5, $6::text::inet, $7, $8, $9, $10)
         "#,
         log.id,
         log.user_id,
         log.action,
         log.details,
         log.created_at,
+        context.and_then(|context| context.ip_address).map(|ip| ip.to_string()),
+        context.and_then(|context| context.user_agent.clone()),
+        context.and_then(|context| context.client_type).map(|client_type| client_type.as_str()),
+        context.and_then(|context| context.app_version.clone()),
+        context.map(|context| context.request_id.clone()),
     )
     .execute(pool)
     .await
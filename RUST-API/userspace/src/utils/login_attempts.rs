@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rocket::fairing::AdHoc;
+use sqlx::PgPool;
+
+use crate::utils::api_error::ApiError;
+use crate::utils::mailer::Email;
+use crate::utils::config::env_flag;
+
+/// What failed logins are counted against. Accounts are keyed by email so
+/// unknown addresses are throttled like real ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttemptKey {
+    Account(String),
+    Ip(IpAddr),
+}
+
+impl AttemptKey {
+    pub fn account(email: &str) -> Self {
+        AttemptKey::Account(email.trim().to_lowercase())
+    }
+
+    /// The keys a password login is counted against
+    pub fn for_login(email: &str, ip: Option<IpAddr>) -> Vec<Self> {
+        let mut keys = vec![AttemptKey::account(email)];
+        keys.extend(ip.map(AttemptKey::Ip));
+        keys
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AttemptKey::Account(_) => "account",
+            AttemptKey::Ip(_) => "ip",
+        }
+    }
+}
+
+impl fmt::Display for AttemptKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttemptKey::Account(email) => write!(f, "account:{}", email),
+            AttemptKey::Ip(ip) => write!(f, "ip:{}", ip),
+        }
+    }
+}
+
+/// Failed logins counted under one key since its window started
+#[derive(Debug, Clone)]
+pub struct AttemptRecord {
+    pub failures: i32,
+    pub window_started_at: DateTime<Utc>,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl AttemptRecord {
+    /// A record stops counting once its window is over, or once its lockout
+    /// has run out
+    pub fn is_stale(&self, window: chrono::Duration, now: DateTime<Utc>) -> bool {
+        match self.locked_until {
+            Some(locked_until) => locked_until <= now,
+            None => self.window_started_at <= now - window,
+        }
+    }
+}
+
+/// Thresholds and timings, see `LockoutPolicy::from_env`
+#[derive(Debug, Clone)]
+pub struct LockoutPolicy {
+    pub account_threshold: i32,
+    pub ip_threshold: i32,
+    pub window: chrono::Duration,
+    pub lockout: chrono::Duration,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub notify: bool,
+}
+
+fn env_number(name: &str, default: i64) -> i64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+impl LockoutPolicy {
+    /// - `LOGIN_LOCKOUT_THRESHOLD`: failures per account before it's locked (default 5)
+    /// - `LOGIN_LOCKOUT_IP_THRESHOLD`: failures per client IP before it's locked (default 20)
+    /// - `LOGIN_LOCKOUT_WINDOW_MINUTES`: how long failures are counted (default 15)
+    /// - `LOGIN_LOCKOUT_MINUTES`: how long a lockout lasts (default 15)
+    /// - `LOGIN_DELAY_BASE_MS` / `LOGIN_DELAY_MAX_MS`: delay after the first
+    ///   failure, doubled with every further one up to the maximum (default 250 / 4000)
+    /// - `LOGIN_LOCKOUT_NOTIFY`: mail the owner when their account is locked
+    ///
+    /// A threshold of `0` turns that lockout off, the delays still apply
+    pub fn from_env() -> Self {
+        LockoutPolicy {
+            account_threshold: env_number("LOGIN_LOCKOUT_THRESHOLD", 5) as i32,
+            ip_threshold: env_number("LOGIN_LOCKOUT_IP_THRESHOLD", 20) as i32,
+            window: chrono::Duration::minutes(env_number("LOGIN_LOCKOUT_WINDOW_MINUTES", 15)),
+            lockout: chrono::Duration::minutes(env_number("LOGIN_LOCKOUT_MINUTES", 15)),
+            base_delay: Duration::from_millis(env_number("LOGIN_DELAY_BASE_MS", 250) as u64),
+            max_delay: Duration::from_millis(env_number("LOGIN_DELAY_MAX_MS", 4000) as u64),
+            notify: env_flag("LOGIN_LOCKOUT_NOTIFY"),
+        }
+    }
+
+    /// Failures after which `key` is locked, `None` when it never is
+    pub fn threshold(&self, key: &AttemptKey) -> Option<i32> {
+        let threshold = match key {
+            AttemptKey::Account(_) => self.account_threshold,
+            AttemptKey::Ip(_) => self.ip_threshold,
+        };
+        (threshold > 0).then_some(threshold)
+    }
+
+    /// How long the next attempt waits after `failures` recent failures
+    pub fn delay(&self, failures: i32) -> Duration {
+        if failures <= 0 {
+            return Duration::ZERO;
+        }
+
+        let factor = 1u32 << (failures - 1).min(16);
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Where the counters live. They have to be shared by every instance, so
+/// anything but Postgres is only good for a single instance or tests
+#[rocket::async_trait]
+pub trait AttemptStore: Send + Sync {
+    async fn get(&self, key: &AttemptKey) -> Result<Option<AttemptRecord>>;
+
+    /// Count one failure, starting a new window when the old one is stale,
+    /// and lock the key until `locked_until` once it reaches `threshold`.
+    /// Must be atomic, instances record failures concurrently
+    async fn record_failure(
+        &self,
+        key: &AttemptKey,
+        policy: &LockoutPolicy,
+        threshold: Option<i32>,
+        now: DateTime<Utc>,
+    ) -> Result<AttemptRecord>;
+
+    /// Forget the key, returning what was recorded
+    async fn clear(&self, key: &AttemptKey) -> Result<Option<AttemptRecord>>;
+
+    /// Delete every stale record
+    async fn prune(&self, window: chrono::Duration, now: DateTime<Utc>) -> Result<u64>;
+}
+
+/// Keeps the counters in the `login_attempts` table
+pub struct PgAttemptStore {
+    pool: PgPool,
+}
+
+impl PgAttemptStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgAttemptStore { pool }
+    }
+}
+
+#[rocket::async_trait]
+impl AttemptStore for PgAttemptStore {
+    async fn get(&self, key: &AttemptKey) -> Result<Option<AttemptRecord>> {
+        let record = sqlx::query_as!(
+            AttemptRecord,
+            r#"
+            SELECT failures, window_started_at, last_failure_at, locked_until
+            FROM login_attempts
+            WHERE key = $1
+            "#,
+            key.to_string()
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(record)
+    }
+
+    async fn record_failure(
+        &self,
+        key: &AttemptKey,
+        policy: &LockoutPolicy,
+        threshold: Option<i32>,
+        now: DateTime<Utc>,
+    ) -> Result<AttemptRecord> {
+        // The stale check matches `AttemptRecord::is_stale`
+        let record = sqlx::query_as!(
+            AttemptRecord,
+            r#"
+            INSERT INTO login_attempts AS a (key, failures, window_started_at, last_failure_at, locked_until)
+            VALUES ($1, 1, $2, $2, CASE WHEN $4::int <= 1 THEN $5::timestamptz END)
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN a.locked_until <= $2 OR (a.locked_until IS NULL AND a.window_started_at <= $3) THEN 1
+                    ELSE a.failures + 1
+                END,
+                window_started_at = CASE
+                    WHEN a.locked_until <= $2 OR (a.locked_until IS NULL AND a.window_started_at <= $3) THEN $2
+                    ELSE a.window_started_at
+                END,
+                last_failure_at = $2,
+                locked_until = CASE
+                    WHEN a.locked_until <= $2 OR (a.locked_until IS NULL AND a.window_started_at <= $3) THEN
+                        CASE WHEN $4 <= 1 THEN $5 END
+                    WHEN a.locked_until IS NULL AND a.failures + 1 >= $4 THEN $5
+                    ELSE a.locked_until
+                END
+            RETURNING failures, window_started_at, last_failure_at, locked_until
+            "#,
+            key.to_string(),
+            now,
+            now - policy.window,
+            threshold,
+            now + policy.lockout
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(record)
+    }
+
+    async fn clear(&self, key: &AttemptKey) -> Result<Option<AttemptRecord>> {
+        let record = sqlx::query_as!(
+            AttemptRecord,
+            r#"
+            DELETE FROM login_attempts
+            WHERE key = $1
+            RETURNING failures, window_started_at, last_failure_at, locked_until
+            "#,
+            key.to_string()
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(record)
+    }
+
+    async fn prune(&self, window: chrono::Duration, now: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM login_attempts
+            WHERE locked_until <= $1 OR (locked_until IS NULL AND window_started_at <= $2)
+            "#,
+            now,
+            now - window
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Keeps the counters in this process, for a single instance or tests
+#[derive(Default)]
+pub struct MemoryAttemptStore {
+    records: Mutex<HashMap<String, AttemptRecord>>,
+}
+
+#[rocket::async_trait]
+impl AttemptStore for MemoryAttemptStore {
+    async fn get(&self, key: &AttemptKey) -> Result<Option<AttemptRecord>> {
+        let records = self.records.lock().expect("login attempts poisoned");
+        Ok(records.get(&key.to_string()).cloned())
+    }
+
+    async fn record_failure(
+        &self,
+        key: &AttemptKey,
+        policy: &LockoutPolicy,
+        threshold: Option<i32>,
+        now: DateTime<Utc>,
+    ) -> Result<AttemptRecord> {
+        let mut records = self.records.lock().expect("login attempts poisoned");
+        let record = records
+            .entry(key.to_string())
+            .and_modify(|record| {
+                if record.is_stale(policy.window, now) {
+                    record.failures = 0;
+                    record.window_started_at = now;
+                    record.locked_until = None;
+                }
+            })
+            .or_insert(AttemptRecord {
+                failures: 0,
+                window_started_at: now,
+                last_failure_at: now,
+                locked_until: None,
+            });
+
+        record.failures += 1;
+        record.last_failure_at = now;
+        if record.locked_until.is_none() && threshold.is_some_and(|threshold| record.failures >= threshold) {
+            record.locked_until = Some(now + policy.lockout);
+        }
+
+        Ok(record.clone())
+    }
+
+    async fn clear(&self, key: &AttemptKey) -> Result<Option<AttemptRecord>> {
+        let mut records = self.records.lock().expect("login attempts poisoned");
+        Ok(records.remove(&key.to_string()))
+    }
+
+    async fn prune(&self, window: chrono::Duration, now: DateTime<Utc>) -> Result<u64> {
+        let mut records = self.records.lock().expect("login attempts poisoned");
+        let before = records.len();
+        records.retain(|_, record| !record.is_stale(window, now));
+        Ok((before - records.len()) as u64)
+    }
+}
+
+/// A key that the failure just recorded locked
+#[derive(Debug, Clone)]
+pub struct Lockout {
+    pub key: AttemptKey,
+    pub failures: i32,
+    pub locked_until: DateTime<Utc>,
+}
+
+/// Managed state guarding password logins against guessing
+#[derive(Clone)]
+pub struct LoginThrottle {
+    store: Arc<dyn AttemptStore>,
+    policy: LockoutPolicy,
+}
+
+impl LoginThrottle {
+    pub fn new(store: impl AttemptStore + 'static, policy: LockoutPolicy) -> Self {
+        LoginThrottle { store: Arc::new(store), policy }
+    }
+
+    /// Pick the store from `LOGIN_ATTEMPT_STORE` (postgres or memory),
+    /// defaulting to postgres
+    pub fn from_env(pool: &PgPool) -> Result<Self> {
+        let policy = LockoutPolicy::from_env();
+
+        let throttle = match std::env::var("LOGIN_ATTEMPT_STORE").unwrap_or_default().to_lowercase().as_str() {
+            "" | "postgres" => LoginThrottle::new(PgAttemptStore::new(pool.clone()), policy),
+            "memory" => LoginThrottle::new(MemoryAttemptStore::default(), policy),
+            other => bail!("Unknown LOGIN_ATTEMPT_STORE '{}'", other),
+        };
+
+        Ok(throttle)
+    }
+
+    pub fn policy(&self) -> &LockoutPolicy {
+        &self.policy
+    }
+
+    /// Refuse the attempt while any of the keys is locked, otherwise wait
+    /// out the delay earned by their recent failures
+    pub async fn admit(&self, keys: &[AttemptKey]) -> Result<(), ApiError> {
+        let now = Utc::now();
+        let mut failures = 0;
+
+        for key in keys {
+            let record = match self.store.get(key).await? {
+                Some(record) if !record.is_stale(self.policy.window, now) => record,
+                _ => continue,
+            };
+
+            if let Some(locked_until) = record.locked_until {
+                let retry_after = (locked_until - now).num_seconds().max(1) as u64;
+                return Err(match key {
+                    AttemptKey::Account(_) => ApiError::too_many_requests(
+                        "ACCOUNT_LOCKED",
+                        "Too many failed logins for this account. Try again later.",
+                        retry_after,
+                    ),
+                    AttemptKey::Ip(_) => ApiError::too_many_requests(
+                        "TOO_MANY_LOGIN_ATTEMPTS",
+                        "Too many failed logins from this address. Try again later.",
+                        retry_after,
+                    ),
+                });
+            }
+
+            failures = failures.max(record.failures);
+        }
+
+        let delay = self.policy.delay(failures);
+        if !delay.is_zero() {
+            rocket::tokio::time::sleep(delay).await;
+        }
+
+        Ok(())
+    }
+
+    /// Count a failed login against every key, returning the keys it locked
+    pub async fn record_failure(&self, keys: &[AttemptKey]) -> Result<Vec<Lockout>> {
+        let now = Utc::now();
+        let mut lockouts = Vec::new();
+
+        for key in keys {
+            let threshold = self.policy.threshold(key);
+            let record = self.store.record_failure(key, &self.policy, threshold, now).await?;
+
+            // Only the failure that reached the threshold reports the lockout
+            if let Some(locked_until) = record.locked_until.filter(|_| Some(record.failures) == threshold) {
+                lockouts.push(Lockout { key: key.clone(), failures: record.failures, locked_until });
+            }
+        }
+
+        Ok(lockouts)
+    }
+
+    /// Reset the counter of `key`, returning it if it was still counting
+    pub async fn clear(&self, key: &AttemptKey) -> Result<Option<AttemptRecord>> {
+        let now = Utc::now();
+        let record = self.store.clear(key).await?;
+        Ok(record.filter(|record| !record.is_stale(self.policy.window, now)))
+    }
+
+    pub async fn prune(&self) -> Result<u64> {
+        self.store.prune(self.policy.window, Utc::now()).await
+    }
+}
+
+/// The mail sent to an account owner when their account gets locked
+pub fn account_locked_email(email: &str, locked_until: DateTime<Utc>) -> Email {
+    Email {
+        to: email.to_string(),
+        subject: "Your account has been locked".to_string(),
+        body: format!(
+            "There were too many failed attempts to log in to your account, so logins are paused until {} UTC.\n\n\
+             If that was you, wait until then or reset your password. If it wasn't, someone may be trying to \
+             guess your password; consider changing it and enabling two-factor authentication.",
+            locked_until.format("%Y-%m-%d %H:%M")
+        ),
+    }
+}
+
+/// Delete stale counters in the background, once per window
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Login Attempt Pruner", |rocket| Box::pin(async move {
+        let throttle = match rocket.state::<LoginThrottle>() {
+            Some(throttle) => throttle.clone(),
+            None => return,
+        };
+
+        let period = throttle.policy().window.to_std().unwrap_or_default().max(Duration::from_secs(60));
+
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(period);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = throttle.prune().await.context("Failed to prune login attempts") {
+                    eprintln!("{:#}", e);
+                }
+            }
+        });
+    }))
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rocket::serde::json::json;
+use serde::Serialize;
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::config::env_flag;
+
+/// The migrations in `migrations/`, embedded at compile time so a deploy
+/// doesn't need `sqlx-cli`
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Started but didn't finish, the database needs fixing by hand
+    Failed,
+    /// Applied, but the file has been edited since
+    Modified,
+    /// Applied, but this build doesn't know it
+    Missing,
+}
+
+impl fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::Failed => "failed",
+            MigrationState::Modified => "modified",
+            MigrationState::Missing => "missing",
+        };
+        f.write_str(label)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+    pub reversible: bool,
+    pub applied_at: Option<DateTime<Utc>>,
+    pub execution_ms: Option<i64>,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:<40} {}", self.version, self.description, self.state)?;
+        if let Some(applied_at) = self.applied_at {
+            write!(f, " {}", applied_at.to_rfc3339())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct AppliedRow {
+    version: i64,
+    description: String,
+    success: bool,
+    checksum: Vec<u8>,
+    installed_on: DateTime<Utc>,
+    execution_time: i64,
+}
+
+/// Every known migration, oldest first, next to what the database recorded
+/// for it. Migrations the database applied but this build doesn't ship are
+/// listed too
+pub async fn status(pool: &PgPool) -> Result<Vec<MigrationStatus>> {
+    // The bookkeeping table doesn't exist before the first run
+    let table: Option<String> = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations')::text")
+        .fetch_one(pool)
+        .await?;
+
+    let mut applied: HashMap<i64, AppliedRow> = HashMap::new();
+    if table.is_some() {
+        let rows: Vec<AppliedRow> = sqlx::query_as(
+            r#"
+            SELECT version, description, success, checksum, installed_on, execution_time
+            FROM _sqlx_migrations
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+        applied.extend(rows.into_iter().map(|row| (row.version, row)));
+    }
+
+    let reversible: Vec<i64> = MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .collect();
+
+    let mut statuses: Vec<MigrationStatus> = MIGRATOR
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .map(|migration| {
+            let row = applied.remove(&migration.version);
+            let state = match &row {
+                None => MigrationState::Pending,
+                Some(row) if !row.success => MigrationState::Failed,
+                Some(row) if row.checksum != *migration.checksum => MigrationState::Modified,
+                Some(_) => MigrationState::Applied,
+            };
+
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+                reversible: reversible.contains(&migration.version),
+                applied_at: row.as_ref().map(|row| row.installed_on),
+                execution_ms: row.as_ref().map(|row| row.execution_time / 1_000_000),
+            }
+        })
+        .collect();
+
+    statuses.extend(applied.into_values().map(|row| MigrationStatus {
+        version: row.version,
+        description: row.description,
+        state: if row.success { MigrationState::Missing } else { MigrationState::Failed },
+        reversible: false,
+        applied_at: Some(row.installed_on),
+        execution_ms: Some(row.execution_time / 1_000_000),
+    }));
+
+    statuses.sort_by_key(|status| status.version);
+
+    Ok(statuses)
+}
+
+/// Apply every pending migration and return the ones that were applied
+pub async fn run(pool: &PgPool) -> Result<Vec<MigrationStatus>> {
+    let pending: Vec<MigrationStatus> = status(pool)
+        .await?
+        .into_iter()
+        .filter(|status| status.state == MigrationState::Pending)
+        .collect();
+
+    MIGRATOR.run(pool).await.context("Failed to run migrations")?;
+
+    Ok(pending)
+}
+
+/// Revert applied migrations newer than `target`, newest first, or only the
+/// latest one without a target. Returns the ones that were reverted
+pub async fn revert(pool: &PgPool, target: Option<i64>) -> Result<Vec<MigrationStatus>> {
+    let mut applied: Vec<MigrationStatus> = status(pool)
+        .await?
+        .into_iter()
+        .filter(|status| status.state != MigrationState::Pending)
+        .collect();
+
+    let target = match target {
+        Some(target) => target,
+        None => match applied.iter().rev().nth(1) {
+            Some(previous) => previous.version,
+            None => 0,
+        },
+    };
+
+    applied.retain(|status| status.version > target);
+    applied.reverse();
+
+    if let Some(status) = applied.iter().find(|status| !status.reversible) {
+        bail!("Migration {} {} can't be reverted", status.version, status.description);
+    }
+
+    MIGRATOR.undo(pool, target).await.context("Failed to revert migrations")?;
+
+    Ok(applied)
+}
+
+/// Apply pending migrations on startup when `RUN_MIGRATIONS` is set.
+/// Instances starting together wait on the same lock, so only one of them
+/// applies anything
+pub async fn run_from_env(pool: &PgPool) -> Result<()> {
+    if !env_flag("RUN_MIGRATIONS") {
+        return Ok(());
+    }
+
+    let applied = run(pool).await?;
+    if applied.is_empty() {
+        return Ok(());
+    }
+
+    for migration in &applied {
+        println!("Applied migration {} {}", migration.version, migration.description);
+    }
+
+    let versions: Vec<String> = applied
+        .iter()
+        .map(|migration| format!("{} {}", migration.version, migration.description))
+        .collect();
+
+    let log = LogBuilder::system(LogAction::Custom("migrated".to_string()), "database")
+        .with_additional_details(&json!({
+            "applied": versions,
+            "performed_by": "system",
+            "via": "startup"
+        }))?
+        .build();
+
+    let _ = log_action(pool, &log).await;
+
+    Ok(())
+}
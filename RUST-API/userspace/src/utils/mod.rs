@@ -1,4 +1,20 @@
 pub mod hashing;
 pub mod create_jwt;
 pub mod auth;
-pub mod logger;
\ No newline at end of file
+pub mod logger;
+pub mod session;
+pub mod tokens;
+pub mod mailer;
+pub mod verification;
+pub mod password_reset;
+pub mod totp;
+pub mod webauthn;
+pub mod role_sweeper;
+pub mod invitations;
+pub mod authz;
+pub mod scope;
+pub mod policy;
+pub mod migrations;
+pub mod api_error;
+pub mod config;
+pub mod login_attempts;
\ No newline at end of file
@@ -0,0 +1,53 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::mailer::Email;
+use crate::utils::tokens::{generate_token, hash_token};
+
+/// How long a reset link stays valid (`PASSWORD_RESET_TTL_MINUTES`, default 60)
+fn reset_ttl() -> chrono::Duration {
+    let minutes = std::env::var("PASSWORD_RESET_TTL_MINUTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60);
+
+    chrono::Duration::minutes(minutes)
+}
+
+/// Store a new single-use reset token for the user, returning the raw token.
+/// Only its hash is kept in the database
+pub async fn create_reset_token(pool: &PgPool, user_id: Uuid) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        user_id,
+        hash_token(&token),
+        chrono::Utc::now() + reset_ttl()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// The mail sent to a user who asked to reset their password
+pub fn password_reset_email(email: &str, token: &str) -> Email {
+    let base_url = std::env::var("APP_BASE_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+
+    Email {
+        to: email.to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Someone asked to reset the password of your account. Open the link below to choose a new one:\n\n\
+             {}/reset-password?token={}\n\n\
+             The link expires in {} minutes. If you didn't ask for this, you can ignore this message.",
+            base_url.trim_end_matches('/'),
+            token,
+            reset_ttl().num_minutes()
+        ),
+    }
+}
@@ -0,0 +1,1012 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use rocket::serde::json::json;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::models::role_permissions::{validate_resource_id, validate_resource_type};
+use crate::utils::config::env_flag;
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+use crate::utils::scope::{format_grant, WILDCARD};
+
+/// The roles, permissions and grants the database should hold, kept in a
+/// TOML file under version control:
+///
+/// ```toml
+/// [permissions]
+/// "posts.delete" = "Delete any post"
+///
+/// [roles.Moderator]
+/// description = "Keeps the forums clean"
+/// parent = "Member"
+/// organization_assignable = true
+/// permissions = ["posts.delete", "board:general:posts.pin"]
+///
+/// [users]
+/// "admin@example.com" = ["Admin"]
+/// ```
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Permission names with their descriptions, empty for none
+    #[serde(default)]
+    pub permissions: BTreeMap<String, String>,
+    #[serde(default)]
+    pub roles: BTreeMap<String, PolicyRole>,
+    /// Roles given to existing users, by email. Only ever added, never pruned
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub users: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyRole {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Role to inherit permissions from, also declared in the policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// Whether organization admins may give the role to their members
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub organization_assignable: bool,
+    /// Permission names, or `resource_type:resource_id:permission` for scoped grants
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// A permission on a role: (role, permission, resource type, resource id)
+type GrantKey = (String, String, String, String);
+
+/// Split a policy grant into permission, resource type and resource id
+fn parse_grant(grant: &str) -> Result<(String, String, String)> {
+    let parts: Vec<&str> = grant.split(':').collect();
+
+    match parts.as_slice() {
+        [permission] => Ok((permission.to_string(), WILDCARD.to_string(), WILDCARD.to_string())),
+        [resource_type, resource_id, permission] => Ok((
+            permission.to_string(),
+            resource_type.to_string(),
+            resource_id.to_string(),
+        )),
+        _ => bail!("'{}' is neither a permission nor resource_type:resource_id:permission", grant),
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read policy file {}", path.display()))?;
+        let policy: Policy = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse policy file {}", path.display()))?;
+
+        policy.check()?;
+
+        Ok(policy)
+    }
+
+    /// Reject policies that reference undeclared roles or permissions, or use
+    /// names and scopes the API wouldn't accept
+    fn check(&self) -> Result<()> {
+        for name in self.permissions.keys() {
+            if !(3..=50).contains(&name.len()) {
+                bail!("Permission '{}' must be 3-50 characters", name);
+            }
+        }
+
+        for (name, role) in &self.roles {
+            if !(1..=50).contains(&name.len()) {
+                bail!("Role '{}' must be 1-50 characters", name);
+            }
+
+            if let Some(parent) = &role.parent {
+                if !self.roles.contains_key(parent) {
+                    bail!("Role '{}' inherits from undeclared role '{}'", name, parent);
+                }
+            }
+
+            for grant in &role.permissions {
+                let (permission, resource_type, resource_id) = parse_grant(grant)?;
+                if !self.permissions.contains_key(&permission) {
+                    bail!("Role '{}' is granted undeclared permission '{}'", name, permission);
+                }
+                if validate_resource_type(&resource_type).is_err() || validate_resource_id(&resource_id).is_err() {
+                    bail!("Role '{}' has a grant with an invalid scope: '{}'", name, grant);
+                }
+            }
+        }
+
+        for (email, roles) in &self.users {
+            for role in roles {
+                if !self.roles.contains_key(role) {
+                    bail!("User '{}' is given undeclared role '{}'", email, role);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn grants(&self) -> BTreeSet<GrantKey> {
+        let mut grants = BTreeSet::new();
+
+        for (name, role) in &self.roles {
+            for grant in &role.permissions {
+                if let Ok((permission, resource_type, resource_id)) = parse_grant(grant) {
+                    grants.insert((name.clone(), permission, resource_type, resource_id));
+                }
+            }
+        }
+
+        grants
+    }
+}
+
+/// One difference between the policy and the database
+#[derive(Debug)]
+pub enum Change {
+    CreatePermission { name: String, description: Option<String> },
+    DescribePermission { name: String, description: Option<String> },
+    CreateRole { name: String, description: Option<String> },
+    DescribeRole { name: String, description: Option<String> },
+    SetParent { role: String, parent: Option<String> },
+    SetOrganizationAssignable { role: String, assignable: bool },
+    Grant(GrantKey),
+    AssignRole { email: String, role: String },
+    Revoke(GrantKey),
+    RemoveRole { name: String },
+    RemovePermission { name: String },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::CreatePermission { name, .. } => write!(f, "+ permission {}", name),
+            Change::DescribePermission { name, .. } => write!(f, "~ permission {} description", name),
+            Change::CreateRole { name, .. } => write!(f, "+ role {}", name),
+            Change::DescribeRole { name, .. } => write!(f, "~ role {} description", name),
+            Change::SetParent { role, parent: Some(parent) } => write!(f, "~ role {} inherits from {}", role, parent),
+            Change::SetParent { role, parent: None } => write!(f, "~ role {} inherits from nothing", role),
+            Change::SetOrganizationAssignable { role, assignable: true } => {
+                write!(f, "~ role {} assignable in organizations", role)
+            }
+            Change::SetOrganizationAssignable { role, assignable: false } => {
+                write!(f, "~ role {} not assignable in organizations", role)
+            }
+            Change::Grant((role, permission, resource_type, resource_id)) => {
+                write!(f, "+ grant {} to {}", format_grant(resource_type, resource_id, permission), role)
+            }
+            Change::AssignRole { email, role } => write!(f, "+ role {} for {}", role, email),
+            Change::Revoke((role, permission, resource_type, resource_id)) => {
+                write!(f, "- grant {} from {}", format_grant(resource_type, resource_id, permission), role)
+            }
+            Change::RemoveRole { name } => write!(f, "- role {}", name),
+            Change::RemovePermission { name } => write!(f, "- permission {}", name),
+        }
+    }
+}
+
+/// What reconciling a policy does: `changes` bring the database in line,
+/// `extras` are in the database but not the policy and only go when pruning
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    pub extras: Vec<Change>,
+    /// Users named in the policy that don't exist (yet)
+    pub warnings: Vec<String>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.extras.is_empty()
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        for extra in &self.extras {
+            writeln!(f, "{} (not in policy)", extra)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "! {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// The policy the database currently holds, so it can be put under version
+/// control. Users are left out, their roles are data rather than policy
+pub async fn export(pool: &PgPool) -> Result<Policy> {
+    let mut policy = Policy::default();
+
+    for permission in sqlx::query!("SELECT name, description FROM permissions ORDER BY name")
+        .fetch_all(pool)
+        .await?
+    {
+        policy.permissions.insert(permission.name, permission.description.unwrap_or_default());
+    }
+
+    for role in sqlx::query!(
+        r#"
+        SELECT r.name, r.description, p.name AS "parent?", r.organization_assignable
+        FROM roles r
+        LEFT JOIN roles p ON r.parent_id = p.id
+        ORDER BY r.name
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        policy.roles.insert(role.name, PolicyRole {
+            description: role.description,
+            parent: role.parent,
+            organization_assignable: role.organization_assignable,
+            permissions: Vec::new(),
+        });
+    }
+
+    for grant in sqlx::query!(
+        r#"
+        SELECT r.name AS role, p.name AS permission, rp.resource_type, rp.resource_id
+        FROM role_permissions rp
+        JOIN roles r ON rp.role_id = r.id
+        JOIN permissions p ON rp.permission_id = p.id
+        ORDER BY r.name, p.name, rp.resource_type, rp.resource_id
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        let written = if grant.resource_type == WILDCARD && grant.resource_id == WILDCARD {
+            grant.permission
+        } else {
+            format_grant(&grant.resource_type, &grant.resource_id, &grant.permission)
+        };
+
+        if let Some(role) = policy.roles.get_mut(&grant.role) {
+            role.permissions.push(written);
+        }
+    }
+
+    Ok(policy)
+}
+
+/// A role as the database holds it
+#[derive(Debug, Clone, Default)]
+struct CurrentRole {
+    description: Option<String>,
+    parent: Option<String>,
+    organization_assignable: bool,
+}
+
+/// What the database holds, as far as a policy is concerned
+#[derive(Debug, Default)]
+struct Current {
+    permissions: HashMap<String, Option<String>>,
+    roles: HashMap<String, CurrentRole>,
+    grants: BTreeSet<GrantKey>,
+    /// Roles the users named in the policy hold right now, by lowercased
+    /// email. Users that don't exist are missing
+    assignments: HashMap<String, Vec<String>>,
+}
+
+impl Current {
+    async fn load(conn: &mut PgConnection, policy: &Policy) -> Result<Self> {
+        let permissions = sqlx::query!("SELECT name, description FROM permissions")
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(|permission| (permission.name, permission.description))
+            .collect();
+
+        let roles = sqlx::query!(
+            r#"
+            SELECT r.name, r.description, p.name AS "parent?", r.organization_assignable
+            FROM roles r
+            LEFT JOIN roles p ON r.parent_id = p.id
+            "#
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|role| (role.name, CurrentRole {
+            description: role.description,
+            parent: role.parent,
+            organization_assignable: role.organization_assignable,
+        }))
+        .collect();
+
+        let grants = sqlx::query!(
+            r#"
+            SELECT r.name AS role, p.name AS permission, rp.resource_type, rp.resource_id
+            FROM role_permissions rp
+            JOIN roles r ON rp.role_id = r.id
+            JOIN permissions p ON rp.permission_id = p.id
+            "#
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|grant| (grant.role, grant.permission, grant.resource_type, grant.resource_id))
+        .collect();
+
+        // Expired and not yet started assignments don't count as held
+        let emails: Vec<String> = policy.users.keys().map(|email| email.to_lowercase()).collect();
+        let mut assignments: HashMap<String, Vec<String>> = HashMap::new();
+        for assignment in sqlx::query!(
+            r#"
+            SELECT LOWER(u.email) AS "email!", r.name AS "role?"
+            FROM users u
+            LEFT JOIN user_roles ur ON u.id = ur.user_id
+                AND (ur.starts_at IS NULL OR ur.starts_at <= CURRENT_TIMESTAMP)
+                AND (ur.expires_at IS NULL OR ur.expires_at > CURRENT_TIMESTAMP)
+            LEFT JOIN roles r ON ur.role_id = r.id
+            WHERE LOWER(u.email) = ANY($1)
+            "#,
+            &emails
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        {
+            let held = assignments.entry(assignment.email).or_default();
+            held.extend(assignment.role);
+        }
+
+        Ok(Current { permissions, roles, grants, assignments })
+    }
+}
+
+/// Compare the policy with what the database holds. Take the lock with
+/// [`lock`] first when the plan is going to be applied
+pub async fn plan(conn: &mut PgConnection, policy: &Policy) -> Result<Plan> {
+    let current = Current::load(conn, policy).await?;
+    diff(policy, &current)
+}
+
+/// The changes that bring `current` in line with the policy
+fn diff(policy: &Policy, current: &Current) -> Result<Plan> {
+    let mut plan = Plan::default();
+
+    for (name, description) in &policy.permissions {
+        let description = Some(description.clone()).filter(|description| !description.is_empty());
+
+        match current.permissions.get(name) {
+            None => plan.changes.push(Change::CreatePermission {
+                name: name.clone(),
+                description,
+            }),
+            Some(existing) if *existing != description => {
+                plan.changes.push(Change::DescribePermission {
+                    name: name.clone(),
+                    description,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (name, role) in &policy.roles {
+        match current.roles.get(name) {
+            None => plan.changes.push(Change::CreateRole {
+                name: name.clone(),
+                description: role.description.clone(),
+            }),
+            Some(existing) if existing.description != role.description => {
+                plan.changes.push(Change::DescribeRole {
+                    name: name.clone(),
+                    description: role.description.clone(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (name, role) in &policy.roles {
+        let assignable = current.roles.get(name).is_some_and(|existing| existing.organization_assignable);
+        if assignable != role.organization_assignable {
+            plan.changes.push(Change::SetOrganizationAssignable {
+                role: name.clone(),
+                assignable: role.organization_assignable,
+            });
+        }
+    }
+
+    // Parents are set once every role exists
+    for (name, role) in &policy.roles {
+        let current_parent = current.roles.get(name).and_then(|existing| existing.parent.clone());
+        if current_parent != role.parent {
+            plan.changes.push(Change::SetParent {
+                role: name.clone(),
+                parent: role.parent.clone(),
+            });
+        }
+    }
+
+    let wanted = policy.grants();
+    for grant in wanted.difference(&current.grants) {
+        plan.changes.push(Change::Grant(grant.clone()));
+    }
+
+    for (email, wanted_roles) in &policy.users {
+        let held = match current.assignments.get(&email.to_lowercase()) {
+            Some(held) => held,
+            None => {
+                plan.warnings.push(format!("no user with email {}, skipping their roles", email));
+                continue;
+            }
+        };
+
+        for role in wanted_roles {
+            if !held.contains(role) {
+                plan.changes.push(Change::AssignRole {
+                    email: email.clone(),
+                    role: role.clone(),
+                });
+            }
+        }
+    }
+
+    // Grants of roles that are removed entirely go with the role
+    for grant in current.grants.difference(&wanted) {
+        if policy.roles.contains_key(&grant.0) {
+            plan.extras.push(Change::Revoke(grant.clone()));
+        }
+    }
+
+    let mut extra_roles: Vec<&String> = current.roles.keys().filter(|name| !policy.roles.contains_key(*name)).collect();
+    extra_roles.sort();
+    for name in extra_roles {
+        plan.extras.push(Change::RemoveRole { name: name.clone() });
+    }
+
+    let mut extra_permissions: Vec<&String> = current
+        .permissions
+        .keys()
+        .filter(|name| !policy.permissions.contains_key(*name))
+        .collect();
+    extra_permissions.sort();
+    for name in extra_permissions {
+        plan.extras.push(Change::RemovePermission { name: name.clone() });
+    }
+
+    // Roles outside the policy keep their parent unless pruned, so a policy
+    // parent could still close a loop through one of them
+    let mut parents: HashMap<&str, &str> = current
+        .roles
+        .iter()
+        .filter_map(|(name, existing)| existing.parent.as_deref().map(|parent| (name.as_str(), parent)))
+        .collect();
+    for (name, role) in &policy.roles {
+        match &role.parent {
+            Some(parent) => parents.insert(name, parent),
+            None => parents.remove(name.as_str()),
+        };
+    }
+
+    for name in policy.roles.keys() {
+        let mut current = name.as_str();
+        let mut steps = 0;
+
+        while let Some(parent) = parents.get(current) {
+            if *parent == name || steps > parents.len() {
+                bail!("Role '{}' would inherit from itself", name);
+            }
+            current = parent;
+            steps += 1;
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Take the same lock as parent changes through the API for the rest of the
+/// transaction, so a plan made under it can't be overtaken by another
+/// instance syncing the same policy
+pub async fn lock(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query!("LOCK TABLE roles IN SHARE ROW EXCLUSIVE MODE")
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Apply a plan, removing the extras too when `prune` is set. Run it in the
+/// transaction the plan was made in, after [`lock`]
+pub async fn apply(conn: &mut PgConnection, plan: &Plan, prune: bool) -> Result<()> {
+    let extras = if prune { plan.extras.as_slice() } else { &[] };
+
+    for change in plan.changes.iter().chain(extras) {
+        match change {
+            Change::CreatePermission { name, description } => {
+                sqlx::query!(
+                    "INSERT INTO permissions (name, description) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING",
+                    name,
+                    description.as_deref()
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            Change::DescribePermission { name, description } => {
+                sqlx::query!(
+                    "UPDATE permissions SET description = $2, updated_at = CURRENT_TIMESTAMP WHERE name = $1",
+                    name,
+                    description.as_deref()
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            Change::CreateRole { name, description } => {
+                sqlx::query!(
+                    "INSERT INTO roles (name, description) VALUES ($1, $2) ON CONFLICT (name) DO NOTHING",
+                    name,
+                    description.as_deref()
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            Change::DescribeRole { name, description } => {
+                sqlx::query!(
+                    "UPDATE roles SET description = $2, updated_at = CURRENT_TIMESTAMP WHERE name = $1",
+                    name,
+                    description.as_deref()
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            Change::SetParent { role, parent } => {
+                sqlx::query!(
+                    r#"
+                    UPDATE roles
+                    SET parent_id = (SELECT id FROM roles WHERE name = $2)
+                    WHERE name = $1
+                    "#,
+                    role,
+                    parent.as_deref()
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            Change::SetOrganizationAssignable { role, assignable } => {
+                sqlx::query!(
+                    "UPDATE roles SET organization_assignable = $2, updated_at = CURRENT_TIMESTAMP WHERE name = $1",
+                    role,
+                    assignable
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            Change::Grant((role, permission, resource_type, resource_id)) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO role_permissions (role_id, permission_id, resource_type, resource_id)
+                    SELECT r.id, p.id, $3, $4
+                    FROM roles r, permissions p
+                    WHERE r.name = $1 AND p.name = $2
+                    ON CONFLICT DO NOTHING
+                    "#,
+                    role,
+                    permission,
+                    resource_type,
+                    resource_id
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            // Only planned when the user doesn't hold the role right now, so
+            // an expired or not yet started assignment becomes permanent
+            Change::AssignRole { email, role } => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO user_roles (user_id, role_id)
+                    SELECT u.id, r.id
+                    FROM users u, roles r
+                    WHERE LOWER(u.email) = LOWER($1) AND r.name = $2
+                    ON CONFLICT (user_id, role_id) DO UPDATE
+                    SET starts_at = NULL, expires_at = NULL
+                    "#,
+                    email,
+                    role
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            Change::Revoke((role, permission, resource_type, resource_id)) => {
+                sqlx::query!(
+                    r#"
+                    DELETE FROM role_permissions rp
+                    USING roles r, permissions p
+                    WHERE rp.role_id = r.id AND rp.permission_id = p.id
+                    AND r.name = $1 AND p.name = $2 AND rp.resource_type = $3 AND rp.resource_id = $4
+                    "#,
+                    role,
+                    permission,
+                    resource_type,
+                    resource_id
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            // Grants, assignments and invitations of the role cascade with it
+            Change::RemoveRole { name } => {
+                sqlx::query!("DELETE FROM roles WHERE name = $1", name)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            Change::RemovePermission { name } => {
+                sqlx::query!("DELETE FROM permissions WHERE name = $1", name)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reconcile the database with the policy file at `path`. Prints the plan,
+/// applies it unless `dry_run` and records what was done in the audit log
+pub async fn sync(pool: &PgPool, path: &Path, prune: bool, dry_run: bool) -> Result<Plan> {
+    let policy = Policy::load(path)?;
+
+    // Plan under the lock, so instances starting together apply one after
+    // the other and the later ones find nothing left to do
+    let mut tx = pool.begin().await?;
+    if !dry_run {
+        lock(&mut tx).await?;
+    }
+
+    let plan = plan(&mut tx, &policy).await?;
+
+    if plan.is_empty() && plan.warnings.is_empty() {
+        println!("RBAC policy {} is in sync", path.display());
+    } else {
+        print!("RBAC policy {}:\n{}", path.display(), plan);
+    }
+
+    if dry_run {
+        println!("Dry run, nothing applied");
+        return Ok(plan);
+    }
+
+    if plan.changes.is_empty() && (!prune || plan.extras.is_empty()) {
+        return Ok(plan);
+    }
+
+    apply(&mut tx, &plan, prune).await?;
+    tx.commit().await?;
+
+    let mut applied: Vec<String> = plan.changes.iter().map(|change| change.to_string()).collect();
+    if prune {
+        applied.extend(plan.extras.iter().map(|extra| extra.to_string()));
+    }
+
+    let log = LogBuilder::system(LogAction::Custom("synced".to_string()), "rbac_policy")
+        .with_resource_id(path.display().to_string())
+        .with_additional_details(&json!({
+            "changes": applied,
+            "pruned": prune,
+            "performed_by": "system",
+        }))?
+        .build();
+
+    let _ = log_action(pool, &log).await;
+
+    Ok(plan)
+}
+
+/// Sync the policy file named by `RBAC_POLICY_FILE`, if any. Extras are only
+/// reported unless `RBAC_POLICY_PRUNE` is set, and `RBAC_POLICY_DRY_RUN` only
+/// prints the plan
+pub async fn sync_from_env(pool: &PgPool) -> Result<()> {
+    let path = match std::env::var("RBAC_POLICY_FILE") {
+        Ok(path) if !path.is_empty() => path,
+        _ => return Ok(()),
+    };
+
+    sync(pool, Path::new(&path), env_flag("RBAC_POLICY_PRUNE"), env_flag("RBAC_POLICY_DRY_RUN")).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(toml: &str) -> Policy {
+        let policy: Policy = toml::from_str(toml).unwrap();
+        policy.check().unwrap();
+        policy
+    }
+
+    const POLICY: &str = r#"
+        [permissions]
+        "posts.read" = ""
+        "posts.delete" = "Delete any post"
+
+        [roles.Member]
+        permissions = ["posts.read"]
+
+        [roles.Moderator]
+        description = "Keeps the forums clean"
+        parent = "Member"
+        permissions = ["posts.delete", "board:general:posts.delete"]
+
+        [users]
+        "Mod@Example.com" = ["Moderator"]
+    "#;
+
+    /// The database after `POLICY` was applied
+    fn in_sync() -> Current {
+        let role = |description: Option<&str>, parent: Option<&str>| CurrentRole {
+            description: description.map(str::to_string),
+            parent: parent.map(str::to_string),
+            organization_assignable: false,
+        };
+        let grant = |role: &str, permission: &str, resource_type: &str, resource_id: &str| {
+            (role.to_string(), permission.to_string(), resource_type.to_string(), resource_id.to_string())
+        };
+
+        Current {
+            permissions: HashMap::from([
+                ("posts.read".to_string(), None),
+                ("posts.delete".to_string(), Some("Delete any post".to_string())),
+            ]),
+            roles: HashMap::from([
+                ("Member".to_string(), role(None, None)),
+                ("Moderator".to_string(), role(Some("Keeps the forums clean"), Some("Member"))),
+            ]),
+            grants: BTreeSet::from([
+                grant("Member", "posts.read", "*", "*"),
+                grant("Moderator", "posts.delete", "*", "*"),
+                grant("Moderator", "posts.delete", "board", "general"),
+            ]),
+            assignments: HashMap::from([("mod@example.com".to_string(), vec!["Moderator".to_string()])]),
+        }
+    }
+
+    fn changes(plan: &Plan) -> Vec<String> {
+        plan.changes.iter().map(ToString::to_string).collect()
+    }
+
+    fn extras(plan: &Plan) -> Vec<String> {
+        plan.extras.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn empty_database_gets_everything() {
+        let current = Current {
+            assignments: HashMap::from([("mod@example.com".to_string(), Vec::new())]),
+            ..Current::default()
+        };
+        let plan = diff(&policy(POLICY), &current).unwrap();
+
+        assert_eq!(changes(&plan), [
+            "+ permission posts.delete",
+            "+ permission posts.read",
+            "+ role Member",
+            "+ role Moderator",
+            "~ role Moderator inherits from Member",
+            "+ grant *:*:posts.read to Member",
+            "+ grant *:*:posts.delete to Moderator",
+            "+ grant board:general:posts.delete to Moderator",
+            "+ role Moderator for Mod@Example.com",
+        ]);
+        assert!(plan.extras.is_empty());
+        assert!(plan.warnings.is_empty());
+    }
+
+    #[test]
+    fn database_in_sync_needs_nothing() {
+        let plan = diff(&policy(POLICY), &in_sync()).unwrap();
+
+        assert!(plan.is_empty(), "{}", plan);
+        assert!(plan.warnings.is_empty());
+    }
+
+    #[test]
+    fn changed_descriptions_and_parents_are_updated() {
+        let mut current = in_sync();
+        current.permissions.insert("posts.read".to_string(), Some("Read posts".to_string()));
+        let moderator = current.roles.get_mut("Moderator").unwrap();
+        moderator.description = None;
+        moderator.parent = None;
+        moderator.organization_assignable = true;
+
+        let plan = diff(&policy(POLICY), &current).unwrap();
+
+        assert_eq!(changes(&plan), [
+            "~ permission posts.read description",
+            "~ role Moderator description",
+            "~ role Moderator not assignable in organizations",
+            "~ role Moderator inherits from Member",
+        ]);
+    }
+
+    #[test]
+    fn unknown_users_are_warned_about_and_missing_roles_assigned() {
+        let mut current = in_sync();
+        current.assignments.insert("mod@example.com".to_string(), Vec::new());
+
+        let mut policy = policy(POLICY);
+        policy.users.insert("ghost@example.com".to_string(), vec!["Member".to_string()]);
+
+        let plan = diff(&policy, &current).unwrap();
+
+        assert_eq!(changes(&plan), ["+ role Moderator for Mod@Example.com"]);
+        assert_eq!(plan.warnings, ["no user with email ghost@example.com, skipping their roles"]);
+    }
+
+    #[test]
+    fn anything_outside_the_policy_is_only_an_extra() {
+        let mut current = in_sync();
+        current.permissions.insert("posts.pin".to_string(), None);
+        current.roles.insert("Legacy".to_string(), CurrentRole::default());
+        current.grants.insert(("Member".to_string(), "posts.pin".to_string(), "*".to_string(), "*".to_string()));
+        current.grants.insert(("Legacy".to_string(), "posts.read".to_string(), "*".to_string(), "*".to_string()));
+
+        let plan = diff(&policy(POLICY), &current).unwrap();
+
+        assert!(plan.changes.is_empty());
+        // The grant of a removed role goes with the role, it isn't listed
+        assert_eq!(extras(&plan), [
+            "- grant *:*:posts.pin from Member",
+            "- role Legacy",
+            "- permission posts.pin",
+        ]);
+    }
+
+    #[test]
+    fn parents_that_loop_are_refused() {
+        let looping = policy(r#"
+            [roles.A]
+            parent = "B"
+
+            [roles.B]
+            parent = "A"
+        "#);
+
+        let error = diff(&looping, &Current::default()).unwrap_err();
+        assert!(error.to_string().contains("would inherit from itself"), "{}", error);
+    }
+
+    #[test]
+    fn parent_chains_without_a_loop_are_fine() {
+        let chain = policy(r#"
+            [roles.A]
+            parent = "B"
+
+            [roles.B]
+            parent = "C"
+
+            [roles.C]
+        "#);
+
+        // The database has the loop the other way round, the policy replaces it
+        let mut current = Current::default();
+        current.roles.insert("C".to_string(), CurrentRole {
+            parent: Some("A".to_string()),
+            ..CurrentRole::default()
+        });
+
+        let plan = diff(&chain, &current).unwrap();
+        assert!(changes(&plan).contains(&"~ role C inherits from nothing".to_string()));
+    }
+
+    #[test]
+    fn grants_are_a_permission_or_a_scoped_permission() {
+        let grant = |permission: &str, resource_type: &str, resource_id: &str| {
+            (permission.to_string(), resource_type.to_string(), resource_id.to_string())
+        };
+
+        assert_eq!(parse_grant("posts.read").unwrap(), grant("posts.read", "*", "*"));
+        assert_eq!(parse_grant("board:general:posts.delete").unwrap(), grant("posts.delete", "board", "general"));
+        assert_eq!(parse_grant("board:*:posts.*").unwrap(), grant("posts.*", "board", "*"));
+
+        assert!(parse_grant("board:posts.read").is_err());
+        assert!(parse_grant("a:b:c:posts.read").is_err());
+    }
+
+    /// The error `check` gives for `toml`, which must fail it
+    fn refused(toml: &str) -> String {
+        let policy: Policy = toml::from_str(toml).unwrap();
+        policy.check().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn policies_referencing_undeclared_names_are_refused() {
+        assert!(refused(r#"
+            [roles.A]
+            parent = "Nobody"
+        "#).contains("undeclared role 'Nobody'"));
+
+        assert!(refused(r#"
+            [roles.A]
+            permissions = ["posts.read"]
+        "#).contains("undeclared permission 'posts.read'"));
+
+        assert!(refused(r#"
+            [roles.A]
+
+            [users]
+            "someone@example.com" = ["B"]
+        "#).contains("undeclared role 'B'"));
+    }
+
+    #[test]
+    fn policies_with_invalid_names_or_scopes_are_refused() {
+        assert!(refused(r#"
+            [permissions]
+            "ab" = ""
+        "#).contains("must be 3-50 characters"));
+
+        assert!(refused(&format!("[roles.{}]", "A".repeat(51))).contains("must be 1-50 characters"));
+
+        assert!(refused(r#"
+            [permissions]
+            "posts.read" = ""
+
+            [roles.A]
+            permissions = ["bo ard:general:posts.read"]
+        "#).contains("invalid scope"));
+
+        assert!(refused(r#"
+            [permissions]
+            "posts.read" = ""
+
+            [roles.A]
+            permissions = ["board:gen*ral:posts.read"]
+        "#).contains("invalid scope"));
+
+        assert!(refused(r#"
+            [permissions]
+            "posts.read" = ""
+
+            [roles.A]
+            permissions = ["posts.read:x"]
+        "#).contains("neither a permission"));
+    }
+
+    #[sqlx::test]
+    async fn applied_plan_leaves_nothing_to_do(pool: PgPool) {
+        let policy = policy(POLICY);
+        let mut conn = pool.acquire().await.unwrap();
+
+        let first = plan(&mut conn, &policy).await.unwrap();
+        assert!(!first.changes.is_empty());
+        apply(&mut conn, &first, false).await.unwrap();
+
+        let second = plan(&mut conn, &policy).await.unwrap();
+        assert!(second.changes.is_empty(), "{}", second);
+        // The seeded roles and permissions are still there, only reported
+        assert!(extras(&second).contains(&"- role Admin".to_string()));
+
+        apply(&mut conn, &second, true).await.unwrap();
+        let pruned = plan(&mut conn, &policy).await.unwrap();
+        assert!(pruned.is_empty(), "{}", pruned);
+    }
+
+    #[sqlx::test]
+    async fn concurrent_syncs_apply_once(pool: PgPool) {
+        let path = std::env::temp_dir().join(format!("policy-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, POLICY).unwrap();
+
+        let (first, second) = tokio::join!(
+            sync(&pool, &path, false, false),
+            sync(&pool, &path, false, false),
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // Whichever went second planned after the first committed
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert!(first.changes.is_empty() != second.changes.is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use rocket::fairing::AdHoc;
+use rocket::serde::json::json;
+use sqlx::PgPool;
+
+use crate::utils::logger::{log_action, LogAction, LogBuilder};
+
+/// How often expired role assignments are removed, from `ROLE_SWEEP_INTERVAL_SECS`
+/// (default 60, must be at least 1)
+fn sweep_interval() -> Result<std::time::Duration> {
+    let seconds = match std::env::var("ROLE_SWEEP_INTERVAL_SECS") {
+        Ok(value) => value
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|seconds| *seconds > 0)
+            .with_context(|| format!("ROLE_SWEEP_INTERVAL_SECS must be a whole number of seconds above 0, got '{}'", value))?,
+        Err(_) => 60,
+    };
+
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// Delete every expired role assignment, writing an audit entry for each.
+/// Permission checks already ignore expired grants, this keeps the table honest
+pub async fn sweep_expired_roles(pool: &PgPool) -> Result<u64> {
+    let expired = sqlx::query!(
+        r#"
+        DELETE FROM user_roles ur
+        USING roles r
+        WHERE ur.role_id = r.id AND ur.expires_at <= CURRENT_TIMESTAMP
+        RETURNING ur.user_id, ur.role_id, r.name, ur.starts_at, ur.expires_at, ur.created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for assignment in &expired {
+        let log = LogBuilder::system(LogAction::Delete, "user_role")
+            .with_resource_id(format!("{}:{}", assignment.user_id, assignment.role_id))
+            .with_previous_state(&json!({
+                "user_id": assignment.user_id,
+                "role_id": assignment.role_id,
+                "role_name": assignment.name,
+                "starts_at": assignment.starts_at,
+                "expires_at": assignment.expires_at,
+                "created_at": assignment.created_at
+            }))?
+            .with_additional_details(&json!({
+                "reason": "expired",
+                "removed_by": "system",
+                "deletion_timestamp": chrono::Utc::now().to_rfc3339(),
+            }))?
+            .build();
+
+        let _ = log_action(pool, &log).await;
+    }
+
+    Ok(expired.len() as u64)
+}
+
+/// Run `sweep_expired_roles` in the background for as long as the server is up.
+/// Fails on an invalid `ROLE_SWEEP_INTERVAL_SECS`
+pub fn fairing() -> Result<AdHoc> {
+    let period = sweep_interval()?;
+
+    Ok(AdHoc::on_liftoff("Expired Role Sweeper", move |rocket| Box::pin(async move {
+        let pool = match rocket.state::<PgPool>() {
+            Some(pool) => pool.clone(),
+            None => return,
+        };
+
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(period);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = sweep_expired_roles(&pool).await {
+                    eprintln!("Failed to sweep expired role assignments: {}", e);
+                }
+            }
+        });
+    })))
+}
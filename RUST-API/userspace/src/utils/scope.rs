@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+
+/// Matches any resource type, id or action
+pub const WILDCARD: &str = "*";
+
+/// The resource an action is performed on, e.g. `document` / `123`.
+/// Ids are paths, so `team-a/project-1` lives under `team-a`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Resource {
+    pub resource_type: String,
+    pub resource_id: String,
+}
+
+/// Whether a granted permission name covers an action: the same name, `*`,
+/// or a `prefix.*` whose prefix the action falls under (`documents.*` covers
+/// `documents.edit` and `documents.comments.delete`)
+pub fn action_matches(granted: &str, action: &str) -> bool {
+    if granted == WILDCARD || granted == action {
+        return true;
+    }
+
+    match granted.strip_suffix(".*") {
+        Some(prefix) => is_under(action, prefix, '.'),
+        None => false,
+    }
+}
+
+/// Whether a granted resource id covers a requested one. `*` covers every id,
+/// `a/*` covers everything below `a`, and `a` covers itself and everything below it
+pub fn resource_id_matches(granted: &str, requested: &str) -> bool {
+    if granted == WILDCARD || granted == requested {
+        return true;
+    }
+
+    match granted.strip_suffix("/*") {
+        Some(parent) => is_under(requested, parent, '/'),
+        None => is_under(requested, granted, '/'),
+    }
+}
+
+/// Whether a grant scoped to `resource_type`/`resource_id` covers the
+/// requested resource. Checks without a resource are only covered by
+/// unscoped (`*`/`*`) grants
+pub fn scope_matches(resource_type: &str, resource_id: &str, requested: Option<&Resource>) -> bool {
+    match requested {
+        None => resource_type == WILDCARD && resource_id == WILDCARD,
+        Some(resource) => {
+            (resource_type == WILDCARD || resource_type == resource.resource_type)
+                && resource_id_matches(resource_id, &resource.resource_id)
+        }
+    }
+}
+
+/// Whether holding the first grant means holding the second one too, i.e.
+/// its action covers the other's and its scope is at least as wide
+pub fn grant_covers(
+    (granted_permission, granted_type, granted_id): (&str, &str, &str),
+    (permission, resource_type, resource_id): (&str, &str, &str),
+) -> bool {
+    action_matches(granted_permission, permission)
+        && (granted_type == WILDCARD || granted_type == resource_type)
+        && resource_id_matches(granted_id, resource_id)
+}
+
+/// Render a scoped grant the way people write them, e.g. `document:123:edit`
+pub fn format_grant(resource_type: &str, resource_id: &str, permission: &str) -> String {
+    format!("{}:{}:{}", resource_type, resource_id, permission)
+}
+
+fn is_under(value: &str, parent: &str, separator: char) -> bool {
+    value.len() > parent.len()
+        && value.starts_with(parent)
+        && value[parent.len()..].starts_with(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(resource_type: &str, resource_id: &str) -> Resource {
+        Resource {
+            resource_type: resource_type.to_string(),
+            resource_id: resource_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn actions_match_by_name_or_prefix() {
+        assert!(action_matches("*", "documents.edit"));
+        assert!(action_matches("documents.edit", "documents.edit"));
+        assert!(action_matches("documents.*", "documents.edit"));
+        assert!(action_matches("documents.*", "documents.comments.delete"));
+
+        assert!(!action_matches("documents.edit", "documents.delete"));
+        assert!(!action_matches("documents.*", "documents"));
+        assert!(!action_matches("documents.*", "documentsx.edit"));
+        assert!(!action_matches("documents", "documents.edit"));
+    }
+
+    #[test]
+    fn resource_ids_cover_themselves_and_their_children() {
+        assert!(resource_id_matches("*", "team-a/project-1"));
+        assert!(resource_id_matches("team-a", "team-a"));
+        assert!(resource_id_matches("team-a", "team-a/project-1"));
+        assert!(resource_id_matches("team-a/*", "team-a/project-1/doc"));
+
+        assert!(!resource_id_matches("team-a/*", "team-a"));
+        assert!(!resource_id_matches("team-a", "team-ab"));
+        assert!(!resource_id_matches("team-a/project-1", "team-a"));
+    }
+
+    #[test]
+    fn checks_without_a_resource_need_an_unscoped_grant() {
+        assert!(scope_matches("*", "*", None));
+        assert!(!scope_matches("document", "*", None));
+        assert!(!scope_matches("*", "123", None));
+
+        let document = resource("document", "123");
+        assert!(scope_matches("*", "*", Some(&document)));
+        assert!(scope_matches("document", "123", Some(&document)));
+        assert!(scope_matches("document", "*", Some(&document)));
+        assert!(!scope_matches("folder", "*", Some(&document)));
+        assert!(!scope_matches("document", "124", Some(&document)));
+    }
+
+    #[test]
+    fn grants_cover_narrower_grants_only() {
+        assert!(grant_covers(("*", "*", "*"), ("documents.edit", "document", "123")));
+        assert!(grant_covers(("documents.*", "document", "*"), ("documents.edit", "document", "123")));
+        assert!(grant_covers(("documents.edit", "*", "team-a"), ("documents.edit", "folder", "team-a/x")));
+        assert!(grant_covers(("documents.edit", "document", "123"), ("documents.edit", "document", "123")));
+
+        // Wider in any way is not covered
+        assert!(!grant_covers(("documents.edit", "document", "123"), ("documents.edit", "document", "*")));
+        assert!(!grant_covers(("documents.edit", "document", "*"), ("documents.edit", "*", "*")));
+        assert!(!grant_covers(("documents.edit", "*", "*"), ("documents.*", "*", "*")));
+        assert!(!grant_covers(("documents.*", "*", "*"), ("*", "*", "*")));
+    }
+
+    #[test]
+    fn grants_are_formatted_type_id_permission() {
+        assert_eq!(format_grant("document", "123", "documents.edit"), "document:123:documents.edit");
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::{DateTime, Utc};
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::serde::json::{json, Value};
+use rocket::time::Duration;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::middleware::cors::ClientType;
+use crate::utils::create_jwt::{access_token_ttl, create_token};
+use crate::utils::tokens::{generate_token, hash_token};
+
+/// How long a session, and so its refresh token family, stays valid.
+/// Configurable through `REFRESH_TOKEN_TTL_DAYS`, defaults to 30 days
+pub fn session_ttl() -> chrono::Duration {
+    let days = std::env::var("REFRESH_TOKEN_TTL_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30);
+
+    chrono::Duration::days(days)
+}
+
+/// Tokens handed to a client when a session is opened or refreshed
+pub struct SessionTokens {
+    pub session_id: Uuid,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+pub enum RefreshOutcome {
+    /// The refresh token was valid and has been replaced by a new one
+    Rotated { user_id: Uuid, tokens: SessionTokens },
+    /// An already used refresh token was presented, the whole family is revoked
+    Reused { user_id: Uuid, session_id: Uuid },
+    /// Unknown, expired or revoked refresh token
+    Invalid,
+}
+
+impl SessionTokens {
+    /// Response body for clients that receive their tokens in the body
+    /// instead of cookies
+    pub fn to_response(&self, message: &str) -> Value {
+        json!({
+            "message": message,
+            "access_token": self.access_token,
+            "refresh_token": self.refresh_token,
+            "token_type": "Bearer",
+            "expires_in": access_token_ttl().num_seconds()
+        })
+    }
+}
+
+/// Create a new session for a user, returning its id and expiry
+pub async fn create_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    client_type: Option<ClientType>,
+) -> Result<(Uuid, DateTime<Utc>), sqlx::Error> {
+    let expires_at = Utc::now() + session_ttl();
+
+    let session = sqlx::query!(
+        r#"
+        INSERT INTO sessions (user_id, expires_at, client_type)
+        VALUES ($1, $2, $3)
+        RETURNING id
+        "#,
+        user_id,
+        expires_at,
+        client_type.map(|client_type| client_type.as_str())
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((session.id, expires_at))
+}
+
+/// Open a session for a user and issue its first access and refresh tokens.
+/// The client type sticks to the session and its tokens, except `Service`:
+/// a backend only gets that budget while it presents its key
+pub async fn start_session(pool: &PgPool, user_id: Uuid, client_type: Option<ClientType>) -> anyhow::Result<SessionTokens> {
+    let client_type = client_type.filter(|client_type| *client_type != ClientType::Service);
+    let (session_id, expires_at) = create_session(pool, user_id, client_type).await?;
+    let refresh_token = generate_token();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (session_id, token_hash, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        session_id,
+        hash_token(&refresh_token),
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(SessionTokens {
+        session_id,
+        access_token: create_token(user_id, session_id, client_type)?,
+        refresh_token,
+    })
+}
+
+/// Exchange a refresh token for a new token pair. Each refresh token can only
+/// be used once; presenting it again revokes the session it belongs to
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    presented_token: &str,
+) -> anyhow::Result<RefreshOutcome> {
+    let mut tx = pool.begin().await?;
+
+    // Lock the token row so concurrent uses of the same token are serialized
+    let existing = sqlx::query!(
+        r#"
+        SELECT rt.id, rt.session_id, rt.expires_at, rt.used_at,
+               s.user_id, s.client_type, s.revoked_at, s.expires_at AS session_expires_at
+        FROM refresh_tokens rt
+        JOIN sessions s ON rt.session_id = s.id
+        WHERE rt.token_hash = $1
+        FOR UPDATE OF rt
+        "#,
+        hash_token(presented_token)
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let existing = match existing {
+        Some(existing) => existing,
+        None => return Ok(RefreshOutcome::Invalid),
+    };
+
+    if existing.used_at.is_some() {
+        sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND revoked_at IS NULL
+            "#,
+            existing.session_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        return Ok(RefreshOutcome::Reused {
+            user_id: existing.user_id,
+            session_id: existing.session_id,
+        });
+    }
+
+    let now = Utc::now();
+    if existing.revoked_at.is_some()
+        || existing.expires_at <= now
+        || existing.session_expires_at <= now
+    {
+        return Ok(RefreshOutcome::Invalid);
+    }
+
+    sqlx::query!(
+        "UPDATE refresh_tokens SET used_at = CURRENT_TIMESTAMP WHERE id = $1",
+        existing.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let refresh_token = generate_token();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (session_id, token_hash, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        existing.session_id,
+        hash_token(&refresh_token),
+        existing.session_expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(RefreshOutcome::Rotated {
+        user_id: existing.user_id,
+        tokens: SessionTokens {
+            session_id: existing.session_id,
+            access_token: create_token(
+                existing.user_id,
+                existing.session_id,
+                existing.client_type.as_deref().map(ClientType::from),
+            )?,
+            refresh_token,
+        },
+    })
+}
+
+/// Check that a session exists for the user and is neither revoked nor expired
+pub async fn is_session_active(
+    pool: &PgPool,
+    session_id: Uuid,
+    user_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let active = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM sessions
+            WHERE id = $1
+            AND user_id = $2
+            AND revoked_at IS NULL
+            AND expires_at > CURRENT_TIMESTAMP
+        )
+        "#,
+        session_id,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(active.unwrap_or(false))
+}
+
+/// Revoke a single session, and with it its refresh token family
+pub async fn revoke_session(pool: &PgPool, session_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE sessions
+        SET revoked_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND revoked_at IS NULL
+        "#,
+        session_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Revoke every live session of a user, optionally keeping one (usually the
+/// caller's own). Returns the number of sessions revoked
+pub async fn revoke_user_sessions<'e, E: sqlx::PgExecutor<'e>>(
+    executor: E,
+    user_id: Uuid,
+    keep_session_id: Option<Uuid>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE sessions
+        SET revoked_at = CURRENT_TIMESTAMP
+        WHERE user_id = $1
+        AND revoked_at IS NULL
+        AND ($2::uuid IS NULL OR id <> $2)
+        "#,
+        user_id,
+        keep_session_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Store the access and refresh tokens in secure, HTTP-only private cookies
+pub fn set_session_cookies(cookies: &CookieJar<'_>, tokens: &SessionTokens) {
+    let mut auth_cookie = Cookie::new("auth_token", tokens.access_token.clone());
+    auth_cookie.set_path("/");
+    auth_cookie.set_secure(true);
+    auth_cookie.set_http_only(true);
+    auth_cookie.set_same_site(SameSite::Strict);
+    auth_cookie.set_max_age(Duration::seconds(access_token_ttl().num_seconds()));
+    cookies.add_private(auth_cookie);
+
+    // The refresh token is only ever needed by the auth routes
+    let mut refresh_cookie = Cookie::new("refresh_token", tokens.refresh_token.clone());
+    refresh_cookie.set_path("/api/auth");
+    refresh_cookie.set_secure(true);
+    refresh_cookie.set_http_only(true);
+    refresh_cookie.set_same_site(SameSite::Strict);
+    refresh_cookie.set_max_age(Duration::seconds(session_ttl().num_seconds()));
+    cookies.add_private(refresh_cookie);
+}
+
+/// Remove both session cookies
+pub fn clear_session_cookies(cookies: &CookieJar<'_>) {
+    cookies.remove_private(Cookie::from("auth_token"));
+    cookies.remove_private(Cookie::build("refresh_token").path("/api/auth"));
+}
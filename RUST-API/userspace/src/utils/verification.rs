@@ -0,0 +1,92 @@
+use anyhow::Result;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::utils::mailer::Email;
+
+const EMAIL_VERIFICATION_PURPOSE: &str = "email_verification";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerificationClaims {
+    pub sub: Uuid,
+    pub exp: usize,
+    /// The address being verified, so the token dies if the email changes
+    pub email: String,
+    pub purpose: String,
+}
+
+/// Whether login is refused until the email is verified (`REQUIRE_EMAIL_VERIFICATION`)
+pub fn email_verification_required() -> bool {
+    std::env::var("REQUIRE_EMAIL_VERIFICATION")
+        .map(|value| value.eq_ignore_ascii_case("true") || value == "1")
+        .unwrap_or(false)
+}
+
+/// How long a verification link stays valid (`EMAIL_VERIFICATION_TTL_HOURS`, default 24)
+fn verification_ttl() -> chrono::Duration {
+    let hours = std::env::var("EMAIL_VERIFICATION_TTL_HOURS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(24);
+
+    chrono::Duration::hours(hours)
+}
+
+fn jwt_secret() -> String {
+    std::env::var("JWT_SECRET").expect("JWT_SECRET must be set")
+}
+
+/// Create a signed, expiring token proving ownership of `email`
+pub fn create_verification_token(user_id: Uuid, email: &str) -> Result<String> {
+    let expiration = chrono::Utc::now()
+        .checked_add_signed(verification_ttl())
+        .expect("valid timestamp")
+        .timestamp() as usize;
+
+    let claims = VerificationClaims {
+        sub: user_id,
+        exp: expiration,
+        email: email.to_string(),
+        purpose: EMAIL_VERIFICATION_PURPOSE.to_string(),
+    };
+
+    Ok(encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(jwt_secret().as_bytes()),
+    )?)
+}
+
+/// Check the signature, expiry and purpose of a verification token
+pub fn decode_verification_token(token: &str) -> Result<VerificationClaims> {
+    let token_data = decode::<VerificationClaims>(
+        token,
+        &DecodingKey::from_secret(jwt_secret().as_bytes()),
+        &Validation::default(),
+    )?;
+
+    if token_data.claims.purpose != EMAIL_VERIFICATION_PURPOSE {
+        anyhow::bail!("Token is not an email verification token");
+    }
+
+    Ok(token_data.claims)
+}
+
+/// The mail sent to a user to verify their address
+pub fn verification_email(user_id: Uuid, email: &str) -> Result<Email> {
+    let token = create_verification_token(user_id, email)?;
+    let base_url = std::env::var("APP_BASE_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+
+    Ok(Email {
+        to: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Please confirm your email address by opening the link below:\n\n\
+             {}/verify-email?token={}\n\n\
+             If you didn't create an account, you can ignore this message.",
+            base_url.trim_end_matches('/'),
+            token
+        ),
+    })
+}
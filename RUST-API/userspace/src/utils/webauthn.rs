@@ -0,0 +1,248 @@
+use anyhow::{Context, Result};
+use rand::Rng;
+use rocket::serde::json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+use webauthn_rs::fake::{FakePasskeyDistribution, WebauthnFakeCredentialGenerator};
+use webauthn_rs::prelude::{
+    Base64UrlSafeData, RequestChallengeResponse, Url, Webauthn, WebauthnBuilder, WebauthnError,
+};
+use webauthn_rs::DEFAULT_AUTHENTICATOR_TIMEOUT;
+use webauthn_rs_proto::{AllowCredentials, PublicKeyCredentialRequestOptions, UserVerificationPolicy};
+
+/// How long a started ceremony can be finished
+const CEREMONY_TTL_MINUTES: i64 = 5;
+/// Unfinished ceremonies kept per user and kind, older ones are dropped
+const MAX_PENDING_CEREMONIES: i64 = 5;
+
+/// Build the relying party from `WEBAUTHN_RP_ID` and `WEBAUTHN_RP_ORIGIN`,
+/// which must match the web client's origin
+pub fn build_webauthn() -> Result<Webauthn> {
+    let rp_id = rp_id();
+    let rp_origin = std::env::var("WEBAUTHN_RP_ORIGIN").unwrap_or_else(|_| "http://localhost:3000".to_string());
+    let rp_name = std::env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "Userspace".to_string());
+
+    let rp_origin = Url::parse(&rp_origin).context("WEBAUTHN_RP_ORIGIN must be a valid URL")?;
+
+    WebauthnBuilder::new(&rp_id, &rp_origin)
+        .context("WEBAUTHN_RP_ORIGIN must be on the WEBAUTHN_RP_ID domain")?
+        .rp_name(&rp_name)
+        .build()
+        .context("Failed to build the WebAuthn relying party")
+}
+
+fn rp_id() -> String {
+    std::env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string())
+}
+
+/// Login options for an email without passkeys, shaped like the real ones.
+/// The credential ids are derived from the email and `JWT_SECRET`, so the
+/// same email always gets the same ids and unknown accounts can't be told
+/// apart from accounts with passkeys
+pub fn decoy_login_options(email: &str) -> Result<RequestChallengeResponse, WebauthnError> {
+    let key = Sha256::new()
+        .chain_update(b"webauthn-decoy:")
+        .chain_update(std::env::var("JWT_SECRET").expect("JWT_SECRET must be set"))
+        .finalize();
+    let generator = WebauthnFakeCredentialGenerator::<FakePasskeyDistribution>::new(&key)?;
+
+    let allow_credentials = generator
+        .generate(email.as_bytes())?
+        .into_iter()
+        .map(|id| AllowCredentials {
+            type_: "public-key".to_string(),
+            id: id.into(),
+            transports: None,
+        })
+        .collect();
+
+    // Same settings as `start_passkey_authentication` with the relying party from `build_webauthn`
+    Ok(RequestChallengeResponse {
+        public_key: PublicKeyCredentialRequestOptions {
+            challenge: Base64UrlSafeData::from(rand::thread_rng().gen::<[u8; 32]>().to_vec()),
+            timeout: Some(DEFAULT_AUTHENTICATOR_TIMEOUT.as_millis() as u32),
+            rp_id: rp_id(),
+            allow_credentials,
+            user_verification: UserVerificationPolicy::Required,
+            hints: None,
+            extensions: None,
+        },
+        mediation: None,
+    })
+}
+
+/// Persist the server half of a ceremony until the client finishes it.
+/// Expired ceremonies are cleared out on the way, and only the newest few
+/// per user and kind are kept, so starting logins over and over can't grow
+/// the table
+pub async fn store_ceremony(
+    pool: &PgPool,
+    user_id: Uuid,
+    kind: &str,
+    state: Value,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!("DELETE FROM webauthn_ceremonies WHERE expires_at <= CURRENT_TIMESTAMP")
+        .execute(pool)
+        .await?;
+
+    let ceremony = sqlx::query!(
+        r#"
+        INSERT INTO webauthn_ceremonies (user_id, kind, state, expires_at)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+        user_id,
+        kind,
+        state,
+        chrono::Utc::now() + chrono::Duration::minutes(CEREMONY_TTL_MINUTES)
+    )
+    .fetch_one(pool)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM webauthn_ceremonies
+        WHERE user_id = $1 AND kind = $2
+        AND id NOT IN (
+            SELECT id FROM webauthn_ceremonies
+            WHERE user_id = $1 AND kind = $2
+            ORDER BY created_at DESC, id
+            LIMIT $3
+        )
+        "#,
+        user_id,
+        kind,
+        MAX_PENDING_CEREMONIES
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(ceremony.id)
+}
+
+/// Take a ceremony out of storage; each one can only be finished once
+pub async fn take_ceremony(
+    pool: &PgPool,
+    ceremony_id: Uuid,
+    kind: &str,
+) -> Result<Option<(Uuid, Value)>, sqlx::Error> {
+    let ceremony = sqlx::query!(
+        r#"
+        DELETE FROM webauthn_ceremonies
+        WHERE id = $1 AND kind = $2 AND expires_at > CURRENT_TIMESTAMP
+        RETURNING user_id, state
+        "#,
+        ceremony_id,
+        kind
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(ceremony.map(|ceremony| (ceremony.user_id, ceremony.state)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webauthn_authenticator_rs::softpasskey::SoftPasskey;
+    use webauthn_authenticator_rs::WebauthnAuthenticator;
+    use webauthn_rs::prelude::{Passkey, PasskeyAuthentication, PasskeyRegistration};
+
+    fn origin() -> Url {
+        Url::parse("http://localhost:3000").unwrap()
+    }
+
+    /// The ceremony state goes through JSON like it does through `webauthn_ceremonies`
+    fn stored<T: serde::Serialize + serde::de::DeserializeOwned>(state: &T) -> T {
+        rocket::serde::json::from_value(rocket::serde::json::to_value(state).unwrap()).unwrap()
+    }
+
+    fn register(webauthn: &Webauthn, authenticator: &mut WebauthnAuthenticator<SoftPasskey>) -> Passkey {
+        let (options, state) = webauthn
+            .start_passkey_registration(Uuid::new_v4(), "someone@example.com", "someone", None)
+            .unwrap();
+        let state: PasskeyRegistration = stored(&state);
+
+        let credential = authenticator.do_registration(origin(), options).unwrap();
+        let passkey = webauthn.finish_passkey_registration(&credential, &state).unwrap();
+
+        // Passkeys are stored as JSON too
+        stored(&passkey)
+    }
+
+    /// What a client sees, minus the challenge that's random for real logins too
+    fn visible_shape(options: &RequestChallengeResponse) -> Value {
+        let mut options = rocket::serde::json::to_value(options).unwrap();
+        options["publicKey"]["challenge"] = Value::Null;
+        options["publicKey"]["allowCredentials"] = Value::Null;
+        options
+    }
+
+    fn ids(options: &RequestChallengeResponse) -> Vec<Vec<u8>> {
+        options.public_key.allow_credentials.iter().map(|c| c.id.to_vec()).collect()
+    }
+
+    #[test]
+    fn decoy_login_options_look_real_and_are_stable() {
+        std::env::set_var("JWT_SECRET", "test-secret");
+        let webauthn = build_webauthn().unwrap();
+        let passkey = register(&webauthn, &mut WebauthnAuthenticator::new(SoftPasskey::new(true)));
+        let (real, _) = webauthn.start_passkey_authentication(&[passkey]).unwrap();
+
+        let decoy = decoy_login_options("nobody@example.com").unwrap();
+        let again = decoy_login_options("nobody@example.com").unwrap();
+        let other = decoy_login_options("somebody@example.com").unwrap();
+
+        assert_eq!(visible_shape(&decoy), visible_shape(&real));
+        assert!(!ids(&decoy).is_empty());
+        assert_eq!(ids(&decoy), ids(&again));
+        assert_ne!(ids(&decoy), ids(&other));
+        assert_ne!(decoy.public_key.challenge, again.public_key.challenge);
+    }
+
+    #[test]
+    fn passkey_registers_and_logs_in() {
+        let webauthn = build_webauthn().unwrap();
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        let mut passkey = register(&webauthn, &mut authenticator);
+
+        let (options, state) = webauthn.start_passkey_authentication(std::slice::from_ref(&passkey)).unwrap();
+        let state: PasskeyAuthentication = stored(&state);
+
+        let credential = authenticator.do_authentication(origin(), options).unwrap();
+        let result = webauthn.finish_passkey_authentication(&credential, &state).unwrap();
+
+        assert_eq!(result.cred_id(), passkey.cred_id());
+        assert_eq!(passkey.update_credential(&result), Some(true));
+    }
+
+    #[test]
+    fn login_with_another_authenticator_fails() {
+        let webauthn = build_webauthn().unwrap();
+        let passkey = register(&webauthn, &mut WebauthnAuthenticator::new(SoftPasskey::new(true)));
+        let mut stranger = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        let strangers_passkey = register(&webauthn, &mut stranger);
+
+        // The stranger signs for its own credential, which isn't in this ceremony
+        let (_, state) = webauthn.start_passkey_authentication(&[passkey]).unwrap();
+        let (strangers_options, _) = webauthn.start_passkey_authentication(&[strangers_passkey]).unwrap();
+        let credential = stranger.do_authentication(origin(), strangers_options).unwrap();
+
+        assert!(webauthn.finish_passkey_authentication(&credential, &state).is_err());
+    }
+
+    #[test]
+    fn finished_login_state_cant_be_replayed_with_a_new_challenge() {
+        let webauthn = build_webauthn().unwrap();
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        let passkey = register(&webauthn, &mut authenticator);
+
+        let (first_options, _) = webauthn.start_passkey_authentication(std::slice::from_ref(&passkey)).unwrap();
+        let (_, second_state) = webauthn.start_passkey_authentication(&[passkey]).unwrap();
+
+        // An assertion signed for one challenge doesn't finish another ceremony
+        let credential = authenticator.do_authentication(origin(), first_options).unwrap();
+        assert!(webauthn.finish_passkey_authentication(&credential, &second_state).is_err());
+    }
+}